@@ -0,0 +1,157 @@
+use crate::crawler::{GenericStore, SYNC_CHECKPOINT};
+use crate::error::CrawlerError;
+use crate::item::Item;
+use crate::replicate::REPLICATION_CHECKPOINT;
+use sha2::{Digest, Sha256};
+
+/// Name of the checkpoint of the target holding the last item id copied by `copy_items`
+pub const COPY_CHECKPOINT: &str = "copy";
+
+/// Checkpoints of the source carried over to the target by `copy_items`. A store can't list
+/// its checkpoints, so the ones saved under other names are not copied
+pub const COPIED_CHECKPOINTS: [&str; 2] = [SYNC_CHECKPOINT, REPLICATION_CHECKPOINT];
+
+/// Number of items and checksum of the items of a store, see `digest_items`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemsDigest {
+    pub count: u64,
+    pub checksum: String,
+}
+
+/// Visit the items of `store` with ids in `from_id..=last_id`, ordered by id,
+/// reading windows of `window` ids so every read is a bounded range
+fn for_each_window(
+    store: &mut dyn GenericStore,
+    from_id: i64,
+    last_id: i64,
+    window: usize,
+    mut f: impl FnMut(Vec<Item>, i64) -> Result<(), CrawlerError>,
+) -> Result<(), CrawlerError> {
+    let window = window.max(1);
+    let mut start = from_id;
+    while start <= last_id {
+        let end = std::cmp::min(start.saturating_add(window as i64 - 1), last_id);
+        f(store.get_items_range(start..=end, window)?, end)?;
+        start = end + 1;
+    }
+    Ok(())
+}
+
+/// Copy the items of `from` with ids up to its last item into `to`, in batches of ids.
+/// The progress is saved in the `COPY_CHECKPOINT` of `to`, so an interrupted copy resumes
+/// after the last complete batch. The `COPIED_CHECKPOINTS` are copied at the end,
+/// never moving a checkpoint of the target backwards.
+/// Returns the number of items copied
+pub fn copy_items(
+    from: &mut dyn GenericStore,
+    to: &mut dyn GenericStore,
+    batch: usize,
+) -> Result<u64, CrawlerError> {
+    let last_id = from.get_last_item()?;
+    let resume = to.get_checkpoint(COPY_CHECKPOINT)?.unwrap_or(0);
+    let mut copied = 0;
+    for_each_window(from, resume + 1, last_id, batch, |items, end| {
+        if !items.is_empty() {
+            copied += to.store_items(items)? as u64;
+            to.save_checkpoint(COPY_CHECKPOINT, end)?;
+            eprintln!("Copied items up to {end}");
+        }
+        Ok(())
+    })?;
+    to.save_checkpoint(COPY_CHECKPOINT, std::cmp::max(resume, last_id))?;
+    for name in COPIED_CHECKPOINTS {
+        if let Some(value) = from.get_checkpoint(name)? {
+            let target = to.get_checkpoint(name)?.unwrap_or(0);
+            to.save_checkpoint(name, std::cmp::max(value, target))?;
+        }
+    }
+    Ok(copied)
+}
+
+/// Count and checksum the items with ids in `1..=last_id`. The checksum is a SHA-256 of
+/// the columns of `items` in id order, so it is the same on every backend holding the same items
+pub fn digest_items(
+    store: &mut dyn GenericStore,
+    last_id: i64,
+    window: usize,
+) -> Result<ItemsDigest, CrawlerError> {
+    let mut hasher = Sha256::new();
+    let mut count = 0;
+    for_each_window(store, 1, last_id, window, |items, _| {
+        for i in items {
+            // `descendants` is not a column of `items`
+            let columns = (
+                i.id, i.deleted, i.tp, i.who, i.time, i.dead, i.kids, i.title, i.text, i.score,
+                i.url, i.parent,
+            );
+            hasher.update(serde_json::to_vec(&columns)?);
+            hasher.update(b"\n");
+            count += 1;
+        }
+        Ok(())
+    })?;
+    Ok(ItemsDigest {
+        count,
+        checksum: hex::encode(hasher.finalize()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::copy::{copy_items, digest_items, COPY_CHECKPOINT};
+    use crate::crawler::{GenericCheckpoint, GenericStore, GenericStoreItem, SYNC_CHECKPOINT};
+    use crate::error::CrawlerError;
+    use crate::ext::file::FileClient;
+    use crate::ext::open_readable_store;
+    use crate::item::Item;
+    use crate::replicate::REPLICATION_CHECKPOINT;
+    use crate::store::Store;
+    use crate::testing::TempDir;
+
+    fn comment(id: i64, text: &str) -> Item {
+        Item::from(format!(
+            r#"{{"id": {id}, "type": "comment", "by": "pg", "time": 1, "kids": [{}], "text": "{text}"}}"#,
+            id * 10
+        ))
+    }
+
+    #[test]
+    fn test_copy_resumes_and_verifies() {
+        let dir = TempDir::new("copy-file");
+        let mut from = Store::<sqlite::Connection>::new(":memory:");
+        let items: Vec<Item> = [1, 2, 3, 5, 8, 13]
+            .iter()
+            .map(|id| comment(*id, "hi"))
+            .collect();
+        from.store_items(items).unwrap();
+        from.save_checkpoint(SYNC_CHECKPOINT, 20).unwrap();
+        from.save_checkpoint(REPLICATION_CHECKPOINT, 7).unwrap();
+        let mut to: Box<dyn GenericStore> = Box::new(Store::<FileClient>::new(&dir.uri()));
+
+        // A previous run stopped after the ids up to 3
+        to.store_items(vec![comment(1, "hi"), comment(2, "hi"), comment(3, "hi")])
+            .unwrap();
+        to.save_checkpoint(COPY_CHECKPOINT, 3).unwrap();
+        assert_eq!(copy_items(&mut from, to.as_mut(), 2).unwrap(), 3);
+        assert_eq!(to.get_checkpoint(COPY_CHECKPOINT).unwrap(), Some(13));
+        assert_eq!(to.get_checkpoint(SYNC_CHECKPOINT).unwrap(), Some(20));
+        assert_eq!(to.get_checkpoint(REPLICATION_CHECKPOINT).unwrap(), Some(7));
+        assert_eq!(copy_items(&mut from, to.as_mut(), 2).unwrap(), 0);
+
+        let source = digest_items(&mut from, 13, 4).unwrap();
+        assert_eq!(source.count, 6);
+        assert_eq!(digest_items(to.as_mut(), 13, 5).unwrap(), source);
+        from.upsert_item(comment(8, "edited")).unwrap();
+        assert_ne!(digest_items(&mut from, 13, 4).unwrap(), source);
+    }
+
+    #[test]
+    fn test_write_only_store_rejected() {
+        let err = open_readable_store("webhook", "https://example.com/hook").err();
+        assert!(
+            matches!(err, Some(CrawlerError::Store { ref reason }) if reason.contains("write-only")),
+            "{:?}",
+            err
+        );
+    }
+}
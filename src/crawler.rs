@@ -1,25 +1,158 @@
-use std::sync::{Arc, mpsc};
-use std::time::Duration;
+use crate::error::CrawlerError;
+use crate::history::GenericItemHistory;
 use crate::hub::NewsHub;
 use crate::item::Item;
-use crate::utils::MAX_BATCH_ITEMS;
+use crate::ranking::{GenericRankingStore, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot};
+use crate::retry::{GenericRetryQueue, RetryEntry, RetryState};
+use crate::store::ItemScan;
+use crate::tombstone::GenericTombstoneStore;
+use crate::utils::{now, MAX_BATCH_ITEMS, REFRESH_SCHEDULE};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 use tokio::runtime;
 
+/// Name of the checkpoint holding the last item id handled by `run_sync_data`
+pub const SYNC_CHECKPOINT: &str = "sync";
+
 pub struct ItemsCrawler<StoreClient> {
     pub hub: Arc<NewsHub>,
     pub client: StoreClient,
+    /// Set on SIGINT/SIGTERM, long-running flows stop after the current batch
+    pub shutdown: Arc<AtomicBool>,
 }
 
 // Default Traits
 /// Storing item data into generic Store
-pub trait GenericStoreItem<T> {
-    fn get_last_item(&mut self) -> Result<i64, ()>;
-    fn store_item(&mut self, item: Item) -> Result<bool, ()>;
+/// The defaults only rely on the required methods, backends override them with their fast paths
+pub trait GenericStoreItem {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError>;
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError>;
+    /// Insert the item or replace the stored one with the same id
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError>;
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError>;
+    /// Stored items with ids in `range` ordered by id, at most `limit` of them
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError>;
+    /// Store a batch of items, backends should override it
+    /// with a bulk path which is transactional
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        let count = items.len();
+        for item in items {
+            self.store_item(item)?;
+        }
+        Ok(count)
+    }
+    /// The `ids` which are stored
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        let mut stored = HashSet::new();
+        for id in ids {
+            if self.get_item(*id)?.is_some() {
+                stored.insert(*id);
+            }
+        }
+        Ok(stored)
+    }
+    /// The ids of `range` stored more than once, ordered. Backends keyed by id can't hold duplicates
+    fn duplicates(&mut self, _range: RangeInclusive<i64>) -> Result<Vec<i64>, CrawlerError> {
+        Ok(vec![])
+    }
+    /// The ids of `range` which are not stored, ordered
+    fn missing_in_range(&mut self, range: RangeInclusive<i64>) -> Result<Vec<i64>, CrawlerError>
+    where
+        Self: Sized,
+    {
+        let stored = self
+            .scan(range.clone())
+            .map(|item| item.map(|i| i.id))
+            .collect::<Result<HashSet<i64>, CrawlerError>>()?;
+        Ok(range.filter(|id| !stored.contains(id)).collect())
+    }
+    /// Number of distinct stored items
+    fn count(&mut self) -> Result<u64, CrawlerError>
+    where
+        Self: Sized,
+    {
+        let mut count = 0;
+        for item in self.scan(i64::MIN..=i64::MAX) {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+    /// Stream the stored items with ids in `range` ordered by id, page by page
+    fn scan(&mut self, range: RangeInclusive<i64>) -> ItemScan<'_, Self>
+    where
+        Self: Sized,
+    {
+        ItemScan::new(self, range)
+    }
+}
+/// Storing named positions of the crawl flows into generic Store
+pub trait GenericCheckpoint {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError>;
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError>;
 }
+/// Every capability a Store needs to run all the crawl flows
+pub trait GenericStore:
+    GenericStoreItem
+    + GenericRetryQueue
+    + GenericCheckpoint
+    + GenericSnapshotStore
+    + GenericRankingStore
+    + GenericItemHistory
+    + GenericTombstoneStore
+{
+}
+
+impl<T> GenericStore for T where
+    T: GenericStoreItem
+        + GenericRetryQueue
+        + GenericCheckpoint
+        + GenericSnapshotStore
+        + GenericRankingStore
+        + GenericItemHistory
+        + GenericTombstoneStore
+{
+}
+
 /// Generic crawl logic
 pub trait GenericCrawlerFlow<T> {
-    fn run_one(&mut self) -> Result<(), ()>;
-    fn run_sync_data(&mut self);
+    fn run_one(&mut self) -> Result<(), CrawlerError>;
+    /// Fetch up to `MAX_BATCH_ITEMS` new items one by one, storing each of them
+    fn run_many(&mut self) -> Result<(), CrawlerError>;
+    /// Fetch up to `MAX_BATCH_ITEMS` new items one by one and store them in a single batch
+    fn run_many_insert_batch(&mut self) -> Result<(), CrawlerError>;
+    fn run_sync_data(&mut self) -> Result<(), CrawlerError>;
+    /// Drain the due entries of the retry queue
+    fn run_retry(&mut self) -> Result<(), CrawlerError>;
+    /// Re-fetch the young stories following `REFRESH_SCHEDULE` and store snapshots
+    fn run_refresh_snapshots(&mut self) -> Result<(), CrawlerError>;
+    /// Store the current story lists and the listed items which are not stored yet
+    fn run_rankings(&mut self) -> Result<(), CrawlerError>;
+    /// Keep syncing new items every `poll_interval` and run the refresh jobs
+    /// every `refresh_interval` until a shutdown is requested
+    fn run_daemon(
+        &mut self,
+        poll_interval: Duration,
+        refresh_interval: Duration,
+    ) -> Result<(), CrawlerError>;
+}
+
+/// Create the async runtime used to fetch a batch of items
+pub(crate) fn batch_runtime() -> runtime::Runtime {
+    runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap()
 }
 
 // Default Implementations
@@ -27,112 +160,379 @@ impl<StoreClient> ItemsCrawler<StoreClient> {
     pub fn new(hub: NewsHub, store_client: StoreClient) -> Self {
         Self {
             hub: Arc::new(hub),
-            client: store_client
+            client: store_client,
+            shutdown: Arc::new(AtomicBool::new(false)),
         }
     }
-    pub fn fetch_latest_item(&mut self) -> Result<i64, ()> {
-        let res = self.hub.fetch_max_item().unwrap();
+    /// Request a graceful shutdown on SIGINT/SIGTERM,
+    /// a second SIGINT terminates the process immediately
+    pub fn handle_signals(&self) -> Result<(), CrawlerError> {
+        signal_hook::flag::register_conditional_shutdown(SIGINT, 1, self.shutdown.clone())?;
+        signal_hook::flag::register(SIGINT, self.shutdown.clone())?;
+        signal_hook::flag::register(SIGTERM, self.shutdown.clone())?;
+        Ok(())
+    }
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+    /// Sleep for `duration` but wake up early on shutdown
+    pub fn pause(&self, duration: Duration) {
+        let started = Instant::now();
+        while !self.is_shutdown() && started.elapsed() < duration {
+            std::thread::sleep(std::cmp::min(
+                Duration::from_millis(500),
+                duration.saturating_sub(started.elapsed()),
+            ));
+        }
+    }
+    pub fn fetch_latest_item(&mut self) -> Result<i64, CrawlerError> {
+        let res = self.hub.fetch_max_item()?;
         let latest_item_id = res.trim().parse::<i64>().unwrap();
         Ok(latest_item_id)
     }
-    pub fn fetch_item(&mut self, item_id: i64) -> Result<Item, ()> {
-        let res = self.hub.fetch_item(item_id).unwrap();
-        Ok(Item::from(res))
+    pub fn fetch_item(&mut self, item_id: i64) -> Result<Item, CrawlerError> {
+        let res = self.hub.fetch_item(item_id)?;
+        Item::parse(item_id, &res)
+    }
+    pub fn fetch_story_list(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        let res = self.hub.fetch_story_list(list.as_str())?;
+        Ok(serde_json::from_str(&res)?)
     }
-    pub fn fetch_items_async(&self, ids: Vec<i64>, rt: tokio::runtime::Runtime) -> Vec<Item> {
+    /// Fetch all `ids` concurrently, returns the fetched items
+    /// and the ids which failed together with their error
+    pub fn fetch_items_async(
+        &self,
+        ids: Vec<i64>,
+        rt: tokio::runtime::Runtime,
+    ) -> (Vec<Item>, Vec<(i64, CrawlerError)>) {
         let (s, r) = mpsc::channel();
-        let ids_cnt = ids.len();
+        let mut pending: HashSet<i64> = ids.iter().copied().collect();
 
         ids.into_iter().for_each(|i| {
             rt.spawn({
                 let sender = s.clone();
                 let hub = self.hub.clone();
                 async move {
-                    match hub.fetch_item_async(i).await {
-                        Ok(response) => {
-                            if response.len() < 10 {
-                                println!("invalid response");
-                                let _ = sender.send(None);
-                            } else {
-                                let item = Item::from(response);
-                                let _ = sender.send(Some(item));
-                            }
-                        }
-                        Err(_) => {
-                            let _ = sender.send(None);
-                        }
-                    }
+                    let res = match hub.fetch_item_async(i).await {
+                        Ok(response) => Item::parse(i, &response),
+                        Err(e) => Err(e.into()),
+                    };
+                    let _ = sender.send((i, res));
                 }
             });
         });
 
-        let mut res = vec![];
-        for _ in 0..ids_cnt {
+        let mut items = vec![];
+        let mut failed = vec![];
+        while !pending.is_empty() {
             match r.recv_timeout(Duration::from_secs(50)) {
-                Ok(item) => {
-                    if let Some(item) = item {
-                        res.push(item);
+                Ok((id, res)) => {
+                    pending.remove(&id);
+                    match res {
+                        Ok(item) => items.push(item),
+                        Err(e) => failed.push((id, e)),
                     }
                 }
-                Err(_) => continue 
+                Err(_) => break,
             }
         }
+        // Whatever did not answer in time is considered failed as well
+        failed.extend(
+            pending
+                .into_iter()
+                .map(|id| (id, CrawlerError::Timeout { item_id: id })),
+        );
+
+        (items, failed)
+    }
+}
+
+impl<T> ItemsCrawler<T>
+where
+    T: GenericRetryQueue,
+{
+    /// Push a failed item into the retry queue, or into the dead-letter
+    /// state once it ran out of attempts
+    pub fn record_failure(
+        &mut self,
+        item_id: i64,
+        error: &CrawlerError,
+    ) -> Result<RetryEntry, CrawlerError> {
+        let previous = self.client.get_retry(item_id)?;
+        let entry = RetryEntry::failed(previous, item_id, &error.to_string(), now());
+        self.client.save_retry(&entry)?;
+        if entry.state == RetryState::Dead {
+            eprintln!(
+                "Item {} gave up after {} attempts: {}",
+                item_id, entry.attempts, error
+            );
+        }
+        Ok(entry)
+    }
+}
+
+impl<T> ItemsCrawler<T>
+where
+    T: GenericStore,
+{
+    /// Jobs which the daemon runs every refresh interval
+    fn run_refresh_jobs(&mut self) {
+        if let Err(e) = self.run_retry() {
+            eprintln!("Retry job failed: {e}");
+        }
+        if let Err(e) = self.run_refresh_snapshots() {
+            eprintln!("Refresh job failed: {e}");
+        }
+        if let Err(e) = self.run_rankings() {
+            eprintln!("Rankings job failed: {e}");
+        }
+    }
 
-        res
+    /// Fetch `ids` concurrently and store them in one batch, the failed ones go to the retry queue.
+    /// Returns the number of stored items
+    fn fetch_and_store(&mut self, ids: Vec<i64>) -> Result<usize, CrawlerError> {
+        let (items, failed) = self.fetch_items_async(ids, batch_runtime());
+        for (id, e) in failed {
+            self.record_failure(id, &e)?;
+        }
+        let ids: Vec<i64> = items.iter().map(|i| i.id).collect();
+        match self.client.store_items(items) {
+            Ok(stored) => Ok(stored),
+            Err(e) => {
+                for id in ids {
+                    self.record_failure(id, &e)?;
+                }
+                Ok(0)
+            }
+        }
     }
 }
 
 impl<T> GenericCrawlerFlow<T> for ItemsCrawler<T>
-    where T: GenericStoreItem<T> {
+where
+    T: GenericStore,
+{
     /// Run the crawler which get latest item in Hub and store to the store
-    fn run_one(&mut self) -> Result<(), ()> {
-        println!("Execute in Generic");
-        let latest_item_id = self.fetch_latest_item().unwrap();
-        let last_item_id = self.client.get_last_item().unwrap();
+    fn run_one(&mut self) -> Result<(), CrawlerError> {
+        eprintln!("Execute in Generic");
+        let latest_item_id = self.fetch_latest_item()?;
+        let last_item_id = self.client.get_last_item()?;
         if latest_item_id > last_item_id {
-            let item = self.fetch_item(latest_item_id).unwrap();
-            println!("{:?}", item.to_string());
-            self.client.store_item(item).unwrap();
+            let stored = self.fetch_item(latest_item_id).and_then(|item| {
+                eprintln!("{:?}", item.to_string());
+                self.client.store_item(item)
+            });
+            if let Err(e) = stored {
+                self.record_failure(latest_item_id, &e)?;
+                return Err(e);
+            }
+        }
+        eprintln!("Store 1 item into store!");
+        Ok(())
+    }
+
+    fn run_many(&mut self) -> Result<(), CrawlerError> {
+        let latest_item_id = self.fetch_latest_item()?;
+        let mut last_item_id = self.client.get_last_item()?;
+        let mut counter: u16 = 0;
+        while (latest_item_id > last_item_id) && (counter < *MAX_BATCH_ITEMS) {
+            last_item_id += 1;
+            counter += 1;
+            let stored = self
+                .fetch_item(last_item_id)
+                .and_then(|item| self.client.store_item(item));
+            if let Err(e) = stored {
+                self.record_failure(last_item_id, &e)?;
+            }
         }
-        println!("Store 1 item into store!");
+        eprintln!("Inserted {counter} items!");
         Ok(())
     }
 
-    fn run_sync_data(&mut self) {
-        let latest_item = self.fetch_latest_item().unwrap();
-        let last_item = self.client.get_last_item().unwrap();
+    fn run_many_insert_batch(&mut self) -> Result<(), CrawlerError> {
+        let latest_item_id = self.fetch_latest_item()?;
+        let mut last_item_id = self.client.get_last_item()?;
+        let mut counter: u16 = 0;
+        let mut items: Vec<Item> = vec![];
+        while (latest_item_id > last_item_id) && (counter < *MAX_BATCH_ITEMS) {
+            last_item_id += 1;
+            counter += 1;
+            match self.fetch_item(last_item_id) {
+                Ok(item) => items.push(item),
+                Err(e) => {
+                    self.record_failure(last_item_id, &e)?;
+                }
+            }
+        }
+        let ids: Vec<i64> = items.iter().map(|i| i.id).collect();
+        if let Err(e) = self.client.store_items(items) {
+            for id in ids {
+                self.record_failure(id, &e)?;
+            }
+            return Err(e);
+        }
+        eprintln!("Inserted {counter} items!");
+        Ok(())
+    }
+
+    fn run_sync_data(&mut self) -> Result<(), CrawlerError> {
+        let latest_item = self.fetch_latest_item()?;
+        // The checkpoint is ahead of the failed items, and behind the newest stories stored by
+        // the rankings, so the last stored item only seeds a store synced without checkpoint
+        let last_item = match self.client.get_checkpoint(SYNC_CHECKPOINT)? {
+            Some(checkpoint) => checkpoint,
+            None => self.client.get_last_item()?,
+        };
         let max_item_per_batch = *MAX_BATCH_ITEMS as i64;
         let mut max_item_id = last_item;
-        while latest_item > max_item_id {
-            // Create single async runtime
-            let rt = runtime::Builder::new_multi_thread()
-                                .worker_threads(1)
-                                .enable_all()
-                                .build()
-                                .unwrap();
-            let to_item_id = std::cmp::min(
-                max_item_id + max_item_per_batch, 
-                latest_item);
-            let batched: Vec<i64> = (max_item_id+1..to_item_id+1).into_iter().collect();
-            let res = self.fetch_items_async(batched, rt);
+        while latest_item > max_item_id && !self.is_shutdown() {
+            let to_item_id = std::cmp::min(max_item_id + max_item_per_batch, latest_item);
+            let batched: Vec<i64> = (max_item_id + 1..to_item_id + 1).collect();
+            self.fetch_and_store(batched)?;
             max_item_id = to_item_id;
-            res.into_iter().for_each(|i| {self.client.store_item(i).unwrap();});
-            // res.into_iter().for_each(|i| println!("Item: {}", i.id));
-            println!("max item: {}", max_item_id);
+            self.client.save_checkpoint(SYNC_CHECKPOINT, max_item_id)?;
+            eprintln!("max item: {}", max_item_id);
+        }
+        Ok(())
+    }
+
+    fn run_retry(&mut self) -> Result<(), CrawlerError> {
+        let mut healed = 0;
+        let mut failed_again = 0;
+        while !self.is_shutdown() {
+            let due = self.client.due_retries(now(), *MAX_BATCH_ITEMS as usize)?;
+            if due.is_empty() {
+                break;
+            }
+            let ids = due.iter().map(|e| e.item_id).collect();
+            let (items, failed) = self.fetch_items_async(ids, batch_runtime());
+            for (id, e) in failed {
+                self.record_failure(id, &e)?;
+                failed_again += 1;
+            }
+            for item in items {
+                let id = item.id;
+                match self.client.store_item(item) {
+                    Ok(_) => {
+                        self.client.remove_retry(id)?;
+                        healed += 1;
+                    }
+                    Err(e) => {
+                        self.record_failure(id, &e)?;
+                        failed_again += 1;
+                    }
+                }
+            }
         }
+        eprintln!("Retried items: {healed} succeeded, {failed_again} failed again");
+        Ok(())
+    }
+
+    fn run_refresh_snapshots(&mut self) -> Result<(), CrawlerError> {
+        let schedule = &*REFRESH_SCHEDULE;
+        let horizon = schedule.iter().max().copied().unwrap_or(0);
+        let ts = now();
+        // Look back twice the horizon so the last offset is not missed after a downtime
+        let due: Vec<i64> = self
+            .client
+            .refresh_candidates(ts - 2 * horizon)?
+            .into_iter()
+            .filter(|c| c.is_due(ts, schedule))
+            .map(|c| c.item_id)
+            .collect();
+        let mut refreshed = 0;
+        for batch in due.chunks(*MAX_BATCH_ITEMS as usize) {
+            if self.is_shutdown() {
+                break;
+            }
+            let (items, failed) = self.fetch_items_async(batch.to_vec(), batch_runtime());
+            // Failed refreshes stay due and are picked up by the next run
+            for (id, e) in failed {
+                eprintln!("Refresh of item {id} failed: {e}");
+            }
+            let observed_at = now();
+            for item in items {
+                self.client
+                    .save_snapshot(&ItemSnapshot::observe(&item, observed_at))?;
+                self.client.record_version(&item, observed_at)?;
+                refreshed += 1;
+            }
+        }
+        eprintln!("Refreshed {refreshed} of {} due stories", due.len());
+        Ok(())
+    }
+
+    fn run_rankings(&mut self) -> Result<(), CrawlerError> {
+        let mut listed: Vec<i64> = vec![];
+        for list in StoryList::ALL {
+            let ids = self.fetch_story_list(list)?;
+            listed.extend(&ids);
+            self.client.save_ranking(&RankingSnapshot {
+                list,
+                observed_at: now(),
+                ids,
+            })?;
+        }
+        listed.sort_unstable();
+        listed.dedup();
+        let stored = self.client.contains(&listed)?;
+        let unstored: Vec<i64> = listed
+            .iter()
+            .copied()
+            .filter(|id| !stored.contains(id))
+            .collect();
+        let mut stored = 0;
+        for batch in unstored.chunks(*MAX_BATCH_ITEMS as usize) {
+            if self.is_shutdown() {
+                break;
+            }
+            stored += self.fetch_and_store(batch.to_vec())?;
+        }
+        eprintln!(
+            "Stored rankings of {} stories, {stored} new items",
+            listed.len()
+        );
+        Ok(())
+    }
+
+    fn run_daemon(
+        &mut self,
+        poll_interval: Duration,
+        refresh_interval: Duration,
+    ) -> Result<(), CrawlerError> {
+        let mut last_refresh: Option<Instant> = None;
+        while !self.is_shutdown() {
+            // Errors are logged only, the next poll will try again
+            if let Err(e) = self.run_sync_data() {
+                eprintln!("Sync failed: {e}");
+            }
+            if !self.is_shutdown() && last_refresh.is_none_or(|t| t.elapsed() >= refresh_interval) {
+                self.run_refresh_jobs();
+                last_refresh = Some(Instant::now());
+            }
+            self.pause(poll_interval);
+        }
+        eprintln!("Shutdown requested, daemon stopped");
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::crawler::{GenericCrawlerFlow, ItemsCrawler};
+    use crate::crawler::{
+        GenericCheckpoint, GenericCrawlerFlow, GenericStoreItem, ItemsCrawler, SYNC_CHECKPOINT,
+    };
     use crate::hub::NewsHub;
+    use crate::item::Item;
     use crate::store::Store;
+    use crate::testing::{item_routes, mock_hub};
     use crate::utils::CRAWLER_HUB;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
 
     /// Create crawler for item which using sqlite memory as storage
     fn mock_crawler() -> ItemsCrawler<Store<sqlite::Connection>> {
-        let hub = NewsHub::new(&**CRAWLER_HUB);
+        let hub = NewsHub::new(&CRAWLER_HUB);
         let store = Store::<sqlite::Connection>::new("resources/items.db");
         ItemsCrawler::new(hub, store)
     }
@@ -149,10 +549,8 @@ mod tests {
     fn test_fetch_item_info() {
         let mut item_crawler = mock_crawler();
         item_crawler.fetch_item(34103778).unwrap();
-        assert!(true);
     }
 
-
     // Test crawler flow
     #[test]
     fn test_run_one() {
@@ -163,6 +561,49 @@ mod tests {
     #[test]
     fn test_run_async() {
         let mut crawler = mock_crawler();
-        let _ = crawler.run_sync_data(); 
+        let _ = crawler.run_sync_data();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sync_after_rankings_fetches_the_gap() {
+        let items: Vec<Item> = (1..=12)
+            .map(|id| {
+                Item::from(format!(
+                    r#"{{"id": {id}, "type": "story", "by": "pg", "time": 1}}"#
+                ))
+            })
+            .collect();
+        let mut routes = item_routes(&items);
+        routes.insert(String::from("/maxitem.json"), String::from("12"));
+        routes.insert(String::from("/newstories.json"), String::from("[12, 11]"));
+        routes.insert(String::from("/topstories.json"), String::from("[]"));
+        routes.insert(String::from("/beststories.json"), String::from("[]"));
+        let mut crawler = ItemsCrawler::new(
+            NewsHub::new(&mock_hub(routes)),
+            Store::<sqlite::Connection>::new(":memory:"),
+        );
+        crawler.client.store_items(items[..2].to_vec()).unwrap();
+        crawler.client.save_checkpoint(SYNC_CHECKPOINT, 2).unwrap();
+
+        // The newest stories are stored above the checkpoint
+        crawler.run_rankings().unwrap();
+        assert_eq!(crawler.client.get_last_item().unwrap(), 12);
+        crawler.run_sync_data().unwrap();
+        assert!(crawler.client.missing_in_range(1..=12).unwrap().is_empty());
+        assert_eq!(
+            crawler.client.get_checkpoint(SYNC_CHECKPOINT).unwrap(),
+            Some(12)
+        );
+    }
+
+    #[test]
+    fn test_daemon_stops_on_shutdown() {
+        let hub = NewsHub::new(&CRAWLER_HUB);
+        let mut crawler = ItemsCrawler::new(hub, Store::<sqlite::Connection>::new(":memory:"));
+        crawler.shutdown.store(true, Ordering::Relaxed);
+        crawler
+            .run_daemon(Duration::from_secs(3600), Duration::from_secs(3600))
+            .unwrap();
+        crawler.pause(Duration::from_secs(3600));
+    }
+}
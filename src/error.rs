@@ -0,0 +1,35 @@
+use custom_error::custom_error;
+
+custom_error! {pub CrawlerError
+    Io{source: std::io::Error}        = "io error: {source}",
+    Hub{source: reqwest::Error}       = "failed to fetch from hub: {source}",
+    Parse{source: serde_json::Error}  = "failed to parse item: {source}",
+    Sqlite{source: sqlite::Error}     = "sqlite error: {source}",
+    Postgres{source: postgres::Error} = "postgres error: {source}",
+    Parquet{source: parquet::errors::ParquetError} = "parquet error: {source}",
+    Redb{error: Box<redb::Error>}     = "redb error: {error}",
+    Mysql{source: mysql::Error}       = "mysql error: {source}",
+    Webhook{reason: String}           = "webhook error: {reason}",
+    EmptyItem{item_id: i64}           = "hub returned no data for item {item_id}",
+    Timeout{item_id: i64}             = "timed out while fetching item {item_id}",
+    Store{reason: String}             = "store error: {reason}"
+}
+
+/// redb returns a specific error type per operation, all of them convert into `redb::Error`
+/// which is boxed since it is much larger than the other sources
+macro_rules! from_redb_error {
+    ($($error:ty),*) => {
+        $(impl From<$error> for CrawlerError {
+            fn from(e: $error) -> Self {
+                CrawlerError::Redb { error: Box::new(e.into()) }
+            }
+        })*
+    };
+}
+from_redb_error!(
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError
+);
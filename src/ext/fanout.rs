@@ -0,0 +1,625 @@
+use crate::crawler::{GenericCheckpoint, GenericStore, GenericStoreItem, SYNC_CHECKPOINT};
+use crate::error::CrawlerError;
+use crate::ext::open_store;
+use crate::history::{GenericItemHistory, ItemVersion};
+use crate::item::Item;
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::retry::{GenericRetryQueue, RetryEntry};
+use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use std::collections::{HashSet, VecDeque};
+use std::ops::RangeInclusive;
+
+/// Number of items a lagging sink copies from the primary on each saved checkpoint
+const CATCH_UP_ITEMS: usize = 10_000;
+
+/// How the fan-out store handles a failed write of a sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkPolicy {
+    /// Fail the write, the crawler queues the items for retry
+    FailFast,
+    /// Log the failure and go on, the sink misses the write. Its checkpoints stop moving,
+    /// so they never claim the items it lacks
+    BestEffort,
+    /// Log the failure and stop writing to the sink, which catches up from the primary
+    /// when the next sync checkpoints are saved, then replays the other writes it missed.
+    /// Only the items above its sync checkpoint are caught up after a restart
+    RetryLater,
+}
+
+impl SinkPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "fail-fast" => Some(SinkPolicy::FailFast),
+            "best-effort" => Some(SinkPolicy::BestEffort),
+            "retry-later" => Some(SinkPolicy::RetryLater),
+            _ => None,
+        }
+    }
+}
+
+/// A write a `RetryLater` sink missed, replayed once it has caught up
+#[derive(Debug, Clone)]
+enum MissedWrite {
+    /// Items at or below the sync checkpoint of the sink, copied again from the primary.
+    /// The ones above are copied by the catch up
+    Items(Vec<i64>),
+    Snapshot(ItemSnapshot),
+    Ranking(RankingSnapshot),
+    Version(Item, i64),
+    Tombstone(Tombstone),
+    /// A checkpoint other than the sync one
+    Checkpoint(String, i64),
+}
+
+impl MissedWrite {
+    fn replay(
+        &self,
+        primary: &mut dyn GenericStore,
+        sink: &mut dyn GenericStore,
+    ) -> Result<(), CrawlerError> {
+        match self {
+            MissedWrite::Items(ids) => {
+                for id in ids {
+                    if let Some(item) = primary.get_item(*id)? {
+                        sink.upsert_item(item)?;
+                    }
+                }
+                Ok(())
+            }
+            MissedWrite::Snapshot(snapshot) => sink.save_snapshot(snapshot),
+            MissedWrite::Ranking(snapshot) => sink.save_ranking(snapshot),
+            MissedWrite::Version(item, observed_at) => {
+                sink.record_version(item, *observed_at).map(|_| ())
+            }
+            MissedWrite::Tombstone(tombstone) => sink.save_tombstone(tombstone),
+            MissedWrite::Checkpoint(name, value) => sink.save_checkpoint(name, *value),
+        }
+    }
+}
+
+/// One of the stores of the fan-out, with its own checkpoints
+pub struct Sink {
+    name: String,
+    store: Box<dyn GenericStore>,
+    policy: SinkPolicy,
+    /// Set when a `RetryLater` sink missed writes, it is skipped until it has caught up
+    lagging: bool,
+    /// Set when a `BestEffort` sink missed writes, its checkpoints are not saved anymore
+    missed: bool,
+    /// The sync checkpoint of the sink
+    synced: i64,
+    /// The writes missed by a lagging sink, in order
+    missed_writes: VecDeque<MissedWrite>,
+}
+
+impl Sink {
+    pub fn new(
+        name: &str,
+        mut store: Box<dyn GenericStore>,
+        policy: SinkPolicy,
+        primary_checkpoint: Option<i64>,
+    ) -> Result<Self, CrawlerError> {
+        // A sink which fell behind in a previous run resumes its catch up, or stays behind
+        let synced = store.get_checkpoint(SYNC_CHECKPOINT)?.unwrap_or(0);
+        let behind = synced < primary_checkpoint.unwrap_or(0);
+        Ok(Self {
+            name: name.to_string(),
+            store,
+            policy,
+            lagging: policy == SinkPolicy::RetryLater && behind,
+            missed: policy == SinkPolicy::BestEffort && behind,
+            synced,
+            missed_writes: VecDeque::new(),
+        })
+    }
+
+    /// Keep `write` for the catch up
+    fn miss(&mut self, write: MissedWrite) {
+        let write = match write {
+            MissedWrite::Items(ids) => {
+                let ids: Vec<i64> = ids.into_iter().filter(|id| *id <= self.synced).collect();
+                if ids.is_empty() {
+                    return;
+                }
+                MissedWrite::Items(ids)
+            }
+            write => write,
+        };
+        self.missed_writes.push_back(write);
+    }
+
+    /// Parse a `<store>=<uri>[;policy=fail-fast|best-effort|retry-later]` sink spec
+    pub fn parse_spec(spec: &str) -> Result<(String, String, SinkPolicy), CrawlerError> {
+        let invalid = |reason: &str| CrawlerError::Store {
+            reason: format!("sink {:?}: {}", spec, reason),
+        };
+        let (target, policy) = match spec.rsplit_once(";policy=") {
+            Some((target, policy)) => (
+                target,
+                SinkPolicy::parse(policy.trim()).ok_or_else(|| invalid("unknown policy"))?,
+            ),
+            None => (spec, SinkPolicy::FailFast),
+        };
+        let (store, uri) = target
+            .split_once('=')
+            .ok_or_else(|| invalid("expected `<store>=<uri>`"))?;
+        Ok((store.trim().to_string(), uri.trim().to_string(), policy))
+    }
+}
+
+/// Writes every item to several stores at once. The first sink is the primary:
+/// it is always fail-fast, serves every read and holds the retry queue.
+/// Each sink keeps its own checkpoints, which only move once the sink has the items
+pub struct FanOutClient {
+    sinks: Vec<Sink>,
+}
+
+impl FanOutClient {
+    pub fn new(sinks: Vec<Sink>) -> Result<Self, CrawlerError> {
+        match sinks.first() {
+            None => Err(CrawlerError::Store {
+                reason: "the fan-out needs at least one sink".to_string(),
+            }),
+            Some(primary) if primary.policy != SinkPolicy::FailFast => Err(CrawlerError::Store {
+                reason: format!("the primary sink {:?} must be fail-fast", primary.name),
+            }),
+            Some(_) => Ok(Self { sinks }),
+        }
+    }
+
+    fn primary(&mut self) -> &mut dyn GenericStore {
+        self.sinks[0].store.as_mut()
+    }
+
+    /// Run `write` on the primary, then on every other sink following its policy.
+    /// The lagging sinks keep the `missed` write instead. The result is the one of the primary
+    fn fan_out<R>(
+        &mut self,
+        missed: impl Fn() -> MissedWrite,
+        mut write: impl FnMut(&mut dyn GenericStore) -> Result<R, CrawlerError>,
+    ) -> Result<R, CrawlerError> {
+        let (primary, others) = self.sinks.split_first_mut().unwrap();
+        let result = write(primary.store.as_mut())?;
+        for sink in others.iter_mut() {
+            if sink.lagging {
+                sink.miss(missed());
+                continue;
+            }
+            if let Err(e) = write(sink.store.as_mut()) {
+                match sink.policy {
+                    SinkPolicy::FailFast => {
+                        return Err(CrawlerError::Store {
+                            reason: format!("sink {:?} failed: {}", sink.name, e),
+                        });
+                    }
+                    SinkPolicy::BestEffort => {
+                        eprintln!(
+                            "Sink {:?} missed a write, its checkpoints stop here: {}",
+                            sink.name, e
+                        );
+                        sink.missed = true;
+                    }
+                    SinkPolicy::RetryLater => {
+                        eprintln!(
+                            "Sink {:?} missed a write, it will catch up: {}",
+                            sink.name, e
+                        );
+                        sink.lagging = true;
+                        sink.miss(missed());
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Copy the next items of `sink` up to the sync checkpoint `value` from the primary and
+    /// move its sync checkpoint. Once it reached `value`, replay the other writes it missed
+    fn catch_up(
+        primary: &mut dyn GenericStore,
+        sink: &mut Sink,
+        value: i64,
+    ) -> Result<(), CrawlerError> {
+        let from = sink.store.get_checkpoint(SYNC_CHECKPOINT)?.unwrap_or(0);
+        let items = primary.get_items_range(from.saturating_add(1)..=value, CATCH_UP_ITEMS)?;
+        let reached = match items.last() {
+            Some(last) if items.len() == CATCH_UP_ITEMS => last.id,
+            _ => value,
+        };
+        sink.store.store_items(items)?;
+        sink.store.save_checkpoint(SYNC_CHECKPOINT, reached)?;
+        sink.synced = reached;
+        if reached < value {
+            return Ok(());
+        }
+        while let Some(write) = sink.missed_writes.front() {
+            write.replay(primary, sink.store.as_mut())?;
+            sink.missed_writes.pop_front();
+        }
+        sink.lagging = false;
+        Ok(())
+    }
+}
+
+impl Store<FanOutClient> {
+    /// Open the sinks of `specs`, see `Sink::parse_spec`
+    pub fn new(specs: &[String]) -> Self {
+        let mut sinks = vec![];
+        let mut primary_checkpoint = None;
+        for spec in specs {
+            let (store, uri, policy) = Sink::parse_spec(spec).unwrap();
+            let mut store = open_store(&store, &uri).unwrap();
+            if sinks.is_empty() {
+                primary_checkpoint = store.get_checkpoint(SYNC_CHECKPOINT).unwrap();
+            }
+            sinks.push(Sink::new(spec, store, policy, primary_checkpoint).unwrap());
+        }
+        Self {
+            backend_client: FanOutClient::new(sinks).unwrap(),
+        }
+    }
+}
+
+impl GenericStoreItem for Store<FanOutClient> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        self.backend_client.primary().get_last_item()
+    }
+
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.backend_client.fan_out(
+            || MissedWrite::Items(vec![item.id]),
+            |store| store.store_item(item.clone()),
+        )
+    }
+
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        self.backend_client.fan_out(
+            || MissedWrite::Items(vec![item.id]),
+            |store| store.upsert_item(item.clone()),
+        )
+    }
+
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        self.backend_client.primary().get_item(item_id)
+    }
+
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        self.backend_client.primary().get_items_range(range, limit)
+    }
+
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        self.backend_client.fan_out(
+            || MissedWrite::Items(items.iter().map(|i| i.id).collect()),
+            |store| store.store_items(items.clone()),
+        )
+    }
+
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        self.backend_client.primary().contains(ids)
+    }
+}
+
+impl GenericCheckpoint for Store<FanOutClient> {
+    /// The checkpoint of the primary, the other sinks catch up on their own
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        self.backend_client.primary().get_checkpoint(name)
+    }
+
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        let (primary, others) = self.backend_client.sinks.split_first_mut().unwrap();
+        primary.store.save_checkpoint(name, value)?;
+        for sink in others.iter_mut().filter(|sink| !sink.missed) {
+            let saved = match (sink.lagging, name == SYNC_CHECKPOINT) {
+                (true, true) => FanOutClient::catch_up(primary.store.as_mut(), sink, value),
+                (true, false) => {
+                    sink.miss(MissedWrite::Checkpoint(name.to_string(), value));
+                    Ok(())
+                }
+                (false, _) => sink.store.save_checkpoint(name, value).map(|_| {
+                    if name == SYNC_CHECKPOINT {
+                        sink.synced = value;
+                    }
+                }),
+            };
+            if let Err(e) = saved {
+                match sink.policy {
+                    SinkPolicy::FailFast => {
+                        return Err(CrawlerError::Store {
+                            reason: format!("sink {:?} failed: {}", sink.name, e),
+                        });
+                    }
+                    SinkPolicy::BestEffort => {
+                        eprintln!(
+                            "Sink {:?} missed checkpoint {} {}: {}",
+                            sink.name, name, value, e
+                        );
+                        sink.missed = true;
+                    }
+                    SinkPolicy::RetryLater => {
+                        eprintln!(
+                            "Sink {:?} is still behind checkpoint {} {}: {}",
+                            sink.name, name, value, e
+                        );
+                        sink.lagging = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GenericRetryQueue for Store<FanOutClient> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        self.backend_client.primary().get_retry(item_id)
+    }
+
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        self.backend_client.primary().save_retry(entry)
+    }
+
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        self.backend_client.primary().remove_retry(item_id)
+    }
+
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        self.backend_client.primary().due_retries(now, limit)
+    }
+}
+
+impl GenericSnapshotStore for Store<FanOutClient> {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        self.backend_client.fan_out(
+            || MissedWrite::Snapshot(snapshot.clone()),
+            |store| store.save_snapshot(snapshot),
+        )
+    }
+
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        self.backend_client.primary().refresh_candidates(since)
+    }
+
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        self.backend_client.primary().get_snapshots(item_id)
+    }
+}
+
+impl GenericRankingStore for Store<FanOutClient> {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        self.backend_client.fan_out(
+            || MissedWrite::Ranking(snapshot.clone()),
+            |store| store.save_ranking(snapshot),
+        )
+    }
+
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        self.backend_client.primary().ranking_at(list, at)
+    }
+
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        self.backend_client.primary().ranking_history(list, item_id)
+    }
+
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        self.backend_client.primary().ranking_observations(list)
+    }
+}
+
+impl GenericItemHistory for Store<FanOutClient> {
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError> {
+        self.backend_client.fan_out(
+            || MissedWrite::Version(item.clone(), observed_at),
+            |store| store.record_version(item, observed_at),
+        )
+    }
+
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        self.backend_client.primary().item_versions(item_id)
+    }
+}
+
+impl GenericTombstoneStore for Store<FanOutClient> {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        self.backend_client.primary().get_tombstone(item_id)
+    }
+
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        self.backend_client.fan_out(
+            || MissedWrite::Tombstone(tombstone.clone()),
+            |store| store.save_tombstone(tombstone),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::{GenericCheckpoint, GenericStore, GenericStoreItem};
+    use crate::ext::fanout::{FanOutClient, Sink, SinkPolicy};
+    use crate::ext::file::FileClient;
+    use crate::ext::sqlite::SqlitePragmas;
+    use crate::item::Item;
+    use crate::migrate::GenericMigrations;
+    use crate::ranking::{GenericRankingStore, RankingSnapshot, StoryList};
+    use crate::store::Store;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    fn story(id: i64) -> Item {
+        Item::parse(
+            id,
+            &format!(
+                r#"{{"id":{},"type":"story","by":"pg","time":{}}}"#,
+                id,
+                1_700_000_000 + id
+            ),
+        )
+        .unwrap()
+    }
+
+    /// A file sink whose directory is removed, so that its writes fail until it is created again
+    fn broken_file_sink(name: &str) -> (TempDir, Box<dyn GenericStore>) {
+        let dir = TempDir::new(&format!("fanout-{}", name));
+        let store = Box::new(Store::<FileClient>::new(&dir.uri()));
+        fs::remove_dir_all(dir.path()).unwrap();
+        (dir, store)
+    }
+
+    fn mock_fanout(name: &str, policy: SinkPolicy) -> (TempDir, Store<FanOutClient>) {
+        let primary = Box::new(Store::<sqlite::Connection>::new(":memory:"));
+        let (dir, sink) = broken_file_sink(name);
+        let sinks = vec![
+            Sink::new("primary", primary, SinkPolicy::FailFast, None).unwrap(),
+            Sink::new("file", sink, policy, None).unwrap(),
+        ];
+        (
+            dir,
+            Store {
+                backend_client: FanOutClient::new(sinks).unwrap(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_parse_sink_spec() {
+        let (store, uri, policy) =
+            Sink::parse_spec("postgres=postgresql://u:p@host/db;policy=retry-later").unwrap();
+        assert_eq!(
+            (store.as_str(), uri.as_str(), policy),
+            (
+                "postgres",
+                "postgresql://u:p@host/db",
+                SinkPolicy::RetryLater
+            )
+        );
+        let (store, uri, policy) = Sink::parse_spec("sqlite=/data/items.db").unwrap();
+        assert_eq!(
+            (store.as_str(), uri.as_str(), policy),
+            ("sqlite", "/data/items.db", SinkPolicy::FailFast)
+        );
+        assert!(Sink::parse_spec("sqlite=/data/items.db;policy=never").is_err());
+        assert!(Sink::parse_spec("/data/items.db").is_err());
+    }
+
+    #[test]
+    fn test_sink_policies() {
+        let (_, mut store_client) = mock_fanout("fail-fast", SinkPolicy::FailFast);
+        assert!(store_client.store_items(vec![story(1), story(2)]).is_err());
+
+        let (dir, mut store_client) = mock_fanout("best-effort", SinkPolicy::BestEffort);
+        assert_eq!(
+            store_client.store_items(vec![story(1), story(2)]).unwrap(),
+            2
+        );
+        assert_eq!(store_client.get_item(2).unwrap(), Some(story(2)));
+        fs::create_dir_all(dir.path()).unwrap();
+        store_client.save_checkpoint("sync", 2).unwrap();
+        // The best-effort sink missed the items for good, its checkpoint does not claim them
+        let sink = &mut store_client.backend_client.sinks[1];
+        assert_eq!(sink.store.get_checkpoint("sync").unwrap(), None);
+        assert_eq!(sink.store.get_item(1).unwrap(), None);
+        // Nor after a restart
+        let store = std::mem::replace(
+            &mut sink.store,
+            Box::new(Store::<sqlite::Connection>::new(":memory:")),
+        );
+        assert!(
+            Sink::new("file", store, SinkPolicy::BestEffort, Some(2))
+                .unwrap()
+                .missed
+        );
+    }
+
+    #[test]
+    fn test_retry_later_sink_catches_up() {
+        let (dir, mut store_client) = mock_fanout("retry-later", SinkPolicy::RetryLater);
+        store_client.store_items(vec![story(1), story(2)]).unwrap();
+        store_client.save_checkpoint("sync", 2).unwrap();
+        assert!(store_client.backend_client.sinks[1].lagging);
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), Some(2));
+
+        // The sink is back, the next checkpoint copies what it missed from the primary
+        fs::create_dir_all(dir.path()).unwrap();
+        store_client.store_items(vec![story(3)]).unwrap();
+        store_client.save_checkpoint("sync", 3).unwrap();
+        let sink = &mut store_client.backend_client.sinks[1];
+        assert!(!sink.lagging);
+        assert_eq!(sink.store.get_checkpoint("sync").unwrap(), Some(3));
+        assert_eq!(
+            sink.store.get_items_range(1..=3, 10).unwrap(),
+            vec![story(1), story(2), story(3)]
+        );
+    }
+
+    #[test]
+    fn test_catch_up_replays_missed_writes() {
+        let dir = TempDir::new("fanout-replay");
+        let path = dir.join("sink.db");
+        // No busy timeout, the writes fail as soon as another connection holds the lock
+        let pragmas = SqlitePragmas::new("delete", "normal", -2000, 0, 0).unwrap();
+        let mut sink = Store::<sqlite::Connection>::open_with(&path, &pragmas).unwrap();
+        sink.migrate_up().unwrap();
+        let sinks = vec![
+            Sink::new(
+                "primary",
+                Box::new(Store::<sqlite::Connection>::new(":memory:")),
+                SinkPolicy::FailFast,
+                None,
+            )
+            .unwrap(),
+            Sink::new("sqlite", Box::new(sink), SinkPolicy::RetryLater, None).unwrap(),
+        ];
+        let mut store_client = Store {
+            backend_client: FanOutClient::new(sinks).unwrap(),
+        };
+        store_client
+            .store_items(vec![story(1), story(2), story(3)])
+            .unwrap();
+        store_client.save_checkpoint("sync", 3).unwrap();
+
+        let lock = sqlite::Connection::open(&path).unwrap();
+        lock.execute("BEGIN EXCLUSIVE").unwrap();
+        let mut edited = story(2);
+        edited.score = 9;
+        store_client.upsert_item(edited.clone()).unwrap();
+        let ranking = RankingSnapshot {
+            list: StoryList::Top,
+            observed_at: 10,
+            ids: vec![2, 1],
+        };
+        store_client.save_ranking(&ranking).unwrap();
+        store_client.store_items(vec![story(4), story(5)]).unwrap();
+        // Not an item id, the sink must not copy up to 5 for it
+        store_client.save_checkpoint("replication", 5).unwrap();
+        store_client.save_checkpoint("sync", 4).unwrap();
+        assert!(store_client.backend_client.sinks[1].lagging);
+
+        lock.execute("COMMIT").unwrap();
+        store_client.save_checkpoint("sync", 4).unwrap();
+        let sink = &mut store_client.backend_client.sinks[1];
+        assert!(!sink.lagging);
+        assert_eq!(
+            sink.store.get_items_range(1..=5, 10).unwrap(),
+            vec![story(1), edited, story(3), story(4)]
+        );
+        assert_eq!(
+            sink.store.ranking_at(StoryList::Top, 10).unwrap(),
+            Some(ranking)
+        );
+        assert_eq!(sink.store.get_checkpoint("sync").unwrap(), Some(4));
+        assert_eq!(sink.store.get_checkpoint("replication").unwrap(), Some(5));
+    }
+}
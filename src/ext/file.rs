@@ -1,53 +1,1167 @@
-use crate::crawler::GenericStoreItem;
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::history::{GenericItemHistory, ItemVersion};
 use crate::item::Item;
-use crate::store::{Store};
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::retry::{GenericRetryQueue, RetryEntry, RetryState};
+use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use crate::utils::{now, FILE_COMPRESSION, FILE_SEGMENT_BYTES, FILE_SEGMENT_IDS};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 
-/// Wrap File into FileClient
+const MANIFEST_FILE: &str = "manifest.json";
+const RETRY_FILE: &str = "retry_queue.json";
+const SNAPSHOTS_FILE: &str = "item_snapshots.jsonl";
+const RANKINGS_FILE: &str = "story_rankings.jsonl";
+const TOMBSTONES_FILE: &str = "item_tombstones.jsonl";
+
+/// Compression of the closed segments, the active segment is always plain NDJSON
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(Compression::None),
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+/// One NDJSON file of items, only the last segment is appended to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    file: String,
+    compression: Compression,
+    closed: bool,
+    min_id: i64,
+    max_id: i64,
+    max_time: i64,
+    records: u64,
+    bytes: u64,
+    /// First id appended above the ids of the store, the id span only counts the new ids
+    /// so the updates of old items do not rotate the segment
+    #[serde(default)]
+    first_new_id: Option<i64>,
+    /// File of the sorted ids of the segment, written once closed
+    #[serde(default)]
+    index: Option<String>,
+}
+
+impl Segment {
+    fn new(file: String) -> Self {
+        Self {
+            file,
+            compression: Compression::None,
+            closed: false,
+            min_id: i64::MAX,
+            max_id: i64::MIN,
+            max_time: i64::MIN,
+            records: 0,
+            bytes: 0,
+            first_new_id: None,
+            index: None,
+        }
+    }
+
+    /// Count `record`, `last_id` being the highest id of the store before it
+    fn add(&mut self, record: &FileRecord, bytes: u64, last_id: i64) {
+        if record.item.id > last_id && self.first_new_id.is_none() {
+            self.first_new_id = Some(record.item.id);
+        }
+        self.min_id = self.min_id.min(record.item.id);
+        self.max_id = self.max_id.max(record.item.id);
+        self.max_time = self.max_time.max(record.item.time);
+        self.records += 1;
+        self.bytes += bytes;
+    }
+
+    fn overlaps(&self, range: &RangeInclusive<i64>) -> bool {
+        self.records > 0 && self.min_id <= *range.end() && *range.start() <= self.max_id
+    }
+}
+
+/// Highest id of `segments`
+fn last_id(segments: &[Segment]) -> Option<i64> {
+    segments
+        .iter()
+        .filter(|s| s.records > 0)
+        .map(|s| s.max_id)
+        .max()
+}
+
+/// The segments and the checkpoints of the store, rewritten atomically on every change
+/// so `get_last_item` and `count` never scan the segments
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Manifest {
+    segments: Vec<Segment>,
+    checkpoints: BTreeMap<String, i64>,
+    /// Number of distinct ids, counted again from the id indexes when unknown
+    #[serde(default)]
+    items: Option<u64>,
+}
+
+/// One line of a segment: a version of the item as observed at `observed_at`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct FileRecord {
+    pub(crate) observed_at: i64,
+    #[serde(flatten)]
+    pub(crate) item: Item,
+}
+
+/// One line of `story_rankings.jsonl`
+#[derive(Serialize, Deserialize, Debug)]
+struct RankingRecord {
+    list: String,
+    observed_at: i64,
+    ids: Vec<i64>,
+}
+
+/// Wrap File into FileClient: append-only NDJSON segments of items under `base_path`,
+/// rotated by size or span of new ids, indexed and compressed once closed
 pub struct FileClient {
-    base_path: String
+    base_path: PathBuf,
+    manifest: Manifest,
+    retries: BTreeMap<i64, RetryEntry>,
+    compression: Compression,
+    segment_bytes: u64,
+    segment_ids: i64,
 }
 impl FileClient {
-    pub fn new(dir: String) -> Self {
-        Self {
-            base_path: dir
+    /// Open the store under `dir`, recovering from an interrupted write
+    pub fn open(dir: &str) -> Result<Self, CrawlerError> {
+        let base_path = PathBuf::from(dir);
+        fs::create_dir_all(&base_path)?;
+        let compression =
+            Compression::parse(&FILE_COMPRESSION).ok_or_else(|| CrawlerError::Store {
+                reason: format!(
+                    "`FILE_COMPRESSION` {:?} is not supported",
+                    *FILE_COMPRESSION
+                ),
+            })?;
+        let manifest = match fs::read(base_path.join(MANIFEST_FILE)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Manifest {
+                items: Some(0),
+                ..Manifest::default()
+            },
+            Err(e) => return Err(e.into()),
+        };
+        let retries = match fs::read(base_path.join(RETRY_FILE)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut client = Self {
+            base_path,
+            manifest,
+            retries,
+            compression,
+            segment_bytes: *FILE_SEGMENT_BYTES,
+            segment_ids: *FILE_SEGMENT_IDS,
+        };
+        client.recover()?;
+        Ok(client)
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.base_path.join(file)
+    }
+
+    /// Drop the torn tails left by a crash and the files which never made it into the manifest,
+    /// then recount the active segment which may be ahead of the manifest
+    fn recover(&mut self) -> Result<(), CrawlerError> {
+        for file in [SNAPSHOTS_FILE, RANKINGS_FILE, TOMBSTONES_FILE] {
+            truncate_torn_tail(&self.path(file))?;
         }
+        let listed: HashSet<String> = self
+            .manifest
+            .segments
+            .iter()
+            .flat_map(|s| [Some(s.file.clone()), s.index.clone()])
+            .flatten()
+            .collect();
+        for entry in fs::read_dir(&self.base_path)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if name.ends_with(".tmp") || (name.starts_with("items-") && !listed.contains(&name)) {
+                fs::remove_file(self.path(&name))?;
+            }
+        }
+        if let Some(active) = self.manifest.segments.last().filter(|s| !s.closed) {
+            let path = self.path(&active.file);
+            truncate_torn_tail(&path)?;
+            let mut recounted = Segment::new(active.file.clone());
+            let segments = self.manifest.segments.len();
+            let mut last = last_id(&self.manifest.segments[..segments - 1]).unwrap_or(i64::MIN);
+            read_lines(&path, Compression::None, |record: FileRecord, bytes| {
+                recounted.add(&record, bytes, last);
+                last = last.max(record.item.id);
+            })?;
+            // Lines appended after the last manifest write are not in the count of items
+            if recounted.records != active.records {
+                self.manifest.items = None;
+            }
+            *self.manifest.segments.last_mut().unwrap() = recounted;
+            self.write_manifest()?;
+        }
+        // The segments closed before the id indexes existed
+        for position in 0..self.manifest.segments.len() {
+            let segment = &self.manifest.segments[position];
+            if segment.closed && segment.index.is_none() {
+                let index = self.write_index(segment)?;
+                self.manifest.segments[position].index = Some(index);
+                self.write_manifest()?;
+            }
+        }
+        Ok(())
     }
+
+    /// Write the sorted ids of `segment` to its index file, returning the file name
+    fn write_index(&self, segment: &Segment) -> Result<String, CrawlerError> {
+        let mut ids = BTreeSet::new();
+        read_lines(
+            &self.path(&segment.file),
+            segment.compression,
+            |record: FileRecord, _| {
+                ids.insert(record.item.id);
+            },
+        )?;
+        let index = format!(
+            "{}.ids.json",
+            segment.file.split('.').next().unwrap_or_default()
+        );
+        write_id_index(&self.path(&index), ids)?;
+        Ok(index)
+    }
+
+    /// Whether `segment` may hold ids of `range`, asking its id index once closed
+    fn holds(&self, segment: &Segment, range: &RangeInclusive<i64>) -> Result<bool, CrawlerError> {
+        Ok(segment.overlaps(range)
+            && match &segment.index {
+                Some(index) => index_holds(&self.path(index), range)?,
+                None => true,
+            })
+    }
+
+    fn write_manifest(&self) -> Result<(), CrawlerError> {
+        write_atomic(
+            &self.path(MANIFEST_FILE),
+            &serde_json::to_vec_pretty(&self.manifest)?,
+        )
+    }
+
+    /// Append `records`, holding `new_ids` ids not stored yet, to the active segment
+    /// in a single synced write, then rotate it when full
+    fn append_records(&mut self, records: &[FileRecord], new_ids: u64) -> Result<(), CrawlerError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if let Some(items) = &mut self.manifest.items {
+            *items += new_ids;
+        }
+        if self.manifest.segments.last().is_none_or(|s| s.closed) {
+            let file = format!("items-{:06}.jsonl", self.manifest.segments.len() + 1);
+            self.manifest.segments.push(Segment::new(file));
+            // List the segment before writing to it, `recover` removes unlisted segments
+            self.write_manifest()?;
+        }
+        let mut lines = String::new();
+        let mut sizes = Vec::with_capacity(records.len());
+        for record in records {
+            let line = serde_json::to_string(record)? + "\n";
+            sizes.push(line.len() as u64);
+            lines.push_str(&line);
+        }
+        let mut last = last_id(&self.manifest.segments).unwrap_or(i64::MIN);
+        let segment = self.manifest.segments.last_mut().unwrap();
+        append_synced(&self.base_path.join(&segment.file), &lines)?;
+        for (record, size) in records.iter().zip(sizes) {
+            segment.add(record, size, last);
+            last = last.max(record.item.id);
+        }
+        let span = segment
+            .first_new_id
+            .map_or(0, |first| segment.max_id - first);
+        if segment.bytes >= self.segment_bytes || span >= self.segment_ids {
+            self.rotate()?;
+        }
+        self.write_manifest()
+    }
+
+    /// Close the active segment, indexing its ids and compressing it into a new file first
+    fn rotate(&mut self) -> Result<(), CrawlerError> {
+        let compression = self.compression;
+        let index = self.write_index(self.manifest.segments.last().unwrap())?;
+        let segment = self.manifest.segments.last_mut().unwrap();
+        segment.closed = true;
+        segment.index = Some(index);
+        if compression == Compression::None {
+            return Ok(());
+        }
+        let plain_file = segment.file.clone();
+        let plain = self.path(&plain_file);
+        let file = format!("{}{}", plain_file, compression.extension());
+        let target = self.path(&file);
+        let tmp = self.path(&format!("{}.tmp", file));
+        let mut source = File::open(&plain)?;
+        let output = File::create(&tmp)?;
+        let output = match compression {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                std::io::copy(&mut source, &mut encoder)?;
+                encoder.finish()?
+            }
+            _ => {
+                let mut encoder = zstd::Encoder::new(output, 0)?;
+                std::io::copy(&mut source, &mut encoder)?;
+                encoder.finish()?
+            }
+        };
+        output.sync_all()?;
+        fs::rename(&tmp, &target)?;
+        let segment = self.manifest.segments.last_mut().unwrap();
+        segment.bytes = fs::metadata(&target)?.len();
+        segment.file = file;
+        segment.compression = compression;
+        // The plain file is only removed once the manifest points to the compressed one
+        self.write_manifest()?;
+        fs::remove_file(plain)?;
+        sync_dir(&self.base_path)
+    }
+
+    /// The latest record of the items with ids in `range`, keeping only the `limit` lowest ids
+    fn latest_records(
+        &self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<BTreeMap<i64, FileRecord>, CrawlerError> {
+        let mut latest = BTreeMap::new();
+        for segment in &self.manifest.segments {
+            if !self.holds(segment, &range)? {
+                continue;
+            }
+            read_lines(
+                &self.path(&segment.file),
+                segment.compression,
+                |record: FileRecord, _| {
+                    if range.contains(&record.item.id) {
+                        latest.insert(record.item.id, record);
+                        if latest.len() > limit {
+                            latest.pop_last();
+                        }
+                    }
+                },
+            )?;
+        }
+        Ok(latest)
+    }
+
+    /// Number of distinct ids, counted from the id indexes once when the manifest doesn't know it
+    fn count_items(&mut self) -> Result<u64, CrawlerError> {
+        if let Some(items) = self.manifest.items {
+            return Ok(items);
+        }
+        let items = count_ids(self)?;
+        self.manifest.items = Some(items);
+        self.write_manifest()?;
+        Ok(items)
+    }
+
+    /// Read every line of an auxiliary NDJSON file, a missing file has no lines
+    fn read_aux<T: DeserializeOwned>(&self, file: &str) -> Result<Vec<T>, CrawlerError> {
+        let path = self.path(file);
+        let mut values = vec![];
+        if path.exists() {
+            read_lines(&path, Compression::None, |value: T, _| values.push(value))?;
+        }
+        Ok(values)
+    }
+
+    /// Time of the latest snapshot of every observed item
+    pub(crate) fn last_observed(&self) -> Result<HashMap<i64, i64>, CrawlerError> {
+        let mut last = HashMap::new();
+        for snapshot in self.read_aux::<ItemSnapshot>(SNAPSHOTS_FILE)? {
+            let at = last.entry(snapshot.item_id).or_insert(snapshot.observed_at);
+            *at = snapshot.observed_at.max(*at);
+        }
+        Ok(last)
+    }
+
+    fn append_aux<T: Serialize>(&self, file: &str, value: &T) -> Result<(), CrawlerError> {
+        append_synced(&self.path(file), &(serde_json::to_string(value)? + "\n"))
+    }
+}
+
+/// Files of item versions with sorted id indexes: the segments of the file store or the
+/// parts of the parquet store. The reads they share are answered from the indexes
+pub(crate) trait IndexedRecords {
+    /// The latest record of the `ids` held by the store
+    fn latest_of(&self, ids: &BTreeSet<i64>) -> Result<BTreeMap<i64, FileRecord>, CrawlerError>;
+
+    /// Call `f` with the sorted ids of every file which may hold ids of `range`
+    fn for_each_file_ids(
+        &self,
+        range: &RangeInclusive<i64>,
+        f: impl FnMut(&[i64]),
+    ) -> Result<(), CrawlerError>;
+}
+
+impl IndexedRecords for FileClient {
+    /// Only reads the segments whose id index holds one of the `ids`
+    fn latest_of(&self, ids: &BTreeSet<i64>) -> Result<BTreeMap<i64, FileRecord>, CrawlerError> {
+        let mut latest = BTreeMap::new();
+        let (Some(first), Some(last)) = (ids.first(), ids.last()) else {
+            return Ok(latest);
+        };
+        for segment in &self.manifest.segments {
+            if !segment.overlaps(&(*first..=*last)) {
+                continue;
+            }
+            if let Some(index) = &segment.index {
+                let held = read_id_index(&self.path(index))?;
+                if !ids.iter().any(|id| held.binary_search(id).is_ok()) {
+                    continue;
+                }
+            }
+            read_lines(
+                &self.path(&segment.file),
+                segment.compression,
+                |record: FileRecord, _| {
+                    if ids.contains(&record.item.id) {
+                        latest.insert(record.item.id, record);
+                    }
+                },
+            )?;
+        }
+        Ok(latest)
+    }
+
+    /// The closed segments are answered from their id index, only the active one is read
+    fn for_each_file_ids(
+        &self,
+        range: &RangeInclusive<i64>,
+        mut f: impl FnMut(&[i64]),
+    ) -> Result<(), CrawlerError> {
+        for segment in &self.manifest.segments {
+            if !segment.overlaps(range) {
+                continue;
+            }
+            let ids = match &segment.index {
+                Some(index) => read_id_index(&self.path(index))?,
+                None => {
+                    let mut ids = BTreeSet::new();
+                    read_lines(
+                        &self.path(&segment.file),
+                        segment.compression,
+                        |record: FileRecord, _| {
+                            ids.insert(record.item.id);
+                        },
+                    )?;
+                    ids.into_iter().collect()
+                }
+            };
+            f(&ids);
+        }
+        Ok(())
+    }
+}
+
+/// Build the records of the items which changed since their latest version, tracking their
+/// tombstones. Also returns how many of the ids were not stored yet
+pub(crate) fn new_versions<C: IndexedRecords>(
+    store: &mut Store<C>,
+    items: Vec<Item>,
+    observed_at: i64,
+) -> Result<(Vec<FileRecord>, u64), CrawlerError>
+where
+    Store<C>: GenericTombstoneStore,
+{
+    let ids: BTreeSet<i64> = items.iter().map(|i| i.id).collect();
+    let mut latest: BTreeMap<i64, Item> = store
+        .backend_client
+        .latest_of(&ids)?
+        .into_iter()
+        .map(|(id, record)| (id, record.item))
+        .collect();
+    let mut records = vec![];
+    let mut new_ids = 0;
+    for item in items {
+        let previous = latest.get(&item.id);
+        if previous == Some(&item) {
+            continue;
+        }
+        if previous.is_none() {
+            new_ids += 1;
+        }
+        let existing = store.get_tombstone(item.id)?;
+        if let Some(tombstone) = Tombstone::track(existing, previous, &item, observed_at) {
+            store.save_tombstone(&tombstone)?;
+        }
+        latest.insert(item.id, item.clone());
+        records.push(FileRecord { observed_at, item });
+    }
+    Ok((records, new_ids))
+}
+
+/// The `ids` held by `records`, looked up in the id indexes one file at a time
+pub(crate) fn contains_ids(
+    records: &impl IndexedRecords,
+    ids: &[i64],
+) -> Result<HashSet<i64>, CrawlerError> {
+    let mut held = HashSet::new();
+    let (Some(min), Some(max)) = (ids.iter().min(), ids.iter().max()) else {
+        return Ok(held);
+    };
+    records.for_each_file_ids(&(*min..=*max), |file_ids| {
+        held.extend(ids.iter().filter(|id| file_ids.binary_search(id).is_ok()));
+    })?;
+    Ok(held)
+}
+
+/// The ids of `range` which `records` does not hold
+pub(crate) fn missing_ids(
+    records: &impl IndexedRecords,
+    range: RangeInclusive<i64>,
+) -> Result<Vec<i64>, CrawlerError> {
+    let mut stored: BTreeSet<i64> = BTreeSet::new();
+    records.for_each_file_ids(&range, |file_ids| {
+        let first = file_ids.partition_point(|id| id < range.start());
+        stored.extend(file_ids[first..].iter().take_while(|id| *id <= range.end()));
+    })?;
+    Ok(range.filter(|id| !stored.contains(id)).collect())
+}
+
+/// Number of distinct ids of `records`, for the stores which don't know it yet
+pub(crate) fn count_ids(records: &impl IndexedRecords) -> Result<u64, CrawlerError> {
+    let mut ids = BTreeSet::new();
+    records.for_each_file_ids(&(i64::MIN..=i64::MAX), |file_ids| {
+        ids.extend(file_ids.iter().copied())
+    })?;
+    Ok(ids.len() as u64)
+}
+
+/// Call `f` with every complete line of `path` and its size in bytes.
+/// A last line without its newline is an interrupted write and is skipped
+pub(crate) fn read_lines<T: DeserializeOwned>(
+    path: &Path,
+    compression: Compression,
+    mut f: impl FnMut(T, u64),
+) -> Result<(), CrawlerError> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+    };
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        if !line.ends_with('\n') {
+            break;
+        }
+        f(serde_json::from_str(&line)?, line.len() as u64);
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Write the sorted `ids` of a closed file of items to `path`
+pub(crate) fn write_id_index(path: &Path, ids: BTreeSet<i64>) -> Result<(), CrawlerError> {
+    write_atomic(path, &serde_json::to_vec(&ids)?)
+}
+
+/// The sorted ids of the id index at `path`
+pub(crate) fn read_id_index(path: &Path) -> Result<Vec<i64>, CrawlerError> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Whether the id index at `path` holds an id of `range`
+pub(crate) fn index_holds(path: &Path, range: &RangeInclusive<i64>) -> Result<bool, CrawlerError> {
+    let ids = read_id_index(path)?;
+    let first = ids.partition_point(|id| id < range.start());
+    Ok(ids.get(first).is_some_and(|id| id <= range.end()))
+}
+
+/// Append `data` and wait for it to reach the disk
+pub(crate) fn append_synced(path: &Path, data: &str) -> Result<(), CrawlerError> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Replace `path` with `data` through a synced temporary file and a rename,
+/// so readers see either the old or the new content
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> Result<(), CrawlerError> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+pub(crate) fn sync_dir(dir: &Path) -> Result<(), CrawlerError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Cut a plain file after its last newline
+pub(crate) fn truncate_torn_tail(path: &Path) -> Result<(), CrawlerError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let len = data.iter().rposition(|b| *b == b'\n').map_or(0, |p| p + 1);
+    if len < data.len() {
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(len as u64)?;
+        file.sync_all()?;
+    }
+    Ok(())
 }
 
 // Implement the Store with FileClient
 impl Store<FileClient> {
     pub fn new(store_path: &str) -> Self {
         Self {
-            backend_client: FileClient::new(store_path.to_string())
+            backend_client: FileClient::open(store_path).unwrap(),
         }
     }
 }
 // Implement trait StoreItem
-impl GenericStoreItem<Store<FileClient>> for Store<FileClient> {
-    fn get_last_item(&mut self) -> Result<i64, ()> {
-        Ok(34103931i64)
+impl GenericStoreItem for Store<FileClient> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        Ok(last_id(&self.backend_client.manifest.segments).unwrap_or(0))
+    }
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.upsert_item(item)?;
+        Ok(true)
+    }
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        self.record_version(&item, now())?;
+        Ok(())
+    }
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        let mut latest = self.backend_client.latest_records(item_id..=item_id, 1)?;
+        Ok(latest.remove(&item_id).map(|r| r.item))
+    }
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        let latest = self.backend_client.latest_records(range, limit)?;
+        Ok(latest.into_values().map(|r| r.item).collect())
+    }
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        let count = items.len();
+        let (records, new_ids) = new_versions(self, items, now())?;
+        self.backend_client.append_records(&records, new_ids)?;
+        Ok(count)
+    }
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        contains_ids(&self.backend_client, ids)
+    }
+    fn missing_in_range(&mut self, range: RangeInclusive<i64>) -> Result<Vec<i64>, CrawlerError> {
+        missing_ids(&self.backend_client, range)
+    }
+    fn count(&mut self) -> Result<u64, CrawlerError> {
+        self.backend_client.count_items()
+    }
+}
+
+// Implement the checkpoints in the manifest
+impl GenericCheckpoint for Store<FileClient> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        Ok(self.backend_client.manifest.checkpoints.get(name).copied())
+    }
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        self.backend_client
+            .manifest
+            .checkpoints
+            .insert(name.to_string(), value);
+        self.backend_client.write_manifest()
+    }
+}
+
+// Implement the retry queue in `retry_queue.json`
+impl GenericRetryQueue for Store<FileClient> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        Ok(self.backend_client.retries.get(&item_id).cloned())
+    }
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        self.backend_client
+            .retries
+            .insert(entry.item_id, entry.clone());
+        write_atomic(
+            &self.backend_client.path(RETRY_FILE),
+            &serde_json::to_vec(&self.backend_client.retries)?,
+        )
+    }
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        if self.backend_client.retries.remove(&item_id).is_some() {
+            write_atomic(
+                &self.backend_client.path(RETRY_FILE),
+                &serde_json::to_vec(&self.backend_client.retries)?,
+            )?;
+        }
+        Ok(())
+    }
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        let mut due: Vec<RetryEntry> = self
+            .backend_client
+            .retries
+            .values()
+            .filter(|e| e.state == RetryState::Pending && e.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|e| e.next_attempt_at);
+        due.truncate(limit);
+        Ok(due)
+    }
+}
+
+// Implement the snapshots in `item_snapshots.jsonl`
+impl GenericSnapshotStore for Store<FileClient> {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        self.backend_client.append_aux(SNAPSHOTS_FILE, snapshot)
+    }
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        let client = &self.backend_client;
+        let mut stories = BTreeMap::new();
+        for segment in client
+            .manifest
+            .segments
+            .iter()
+            .filter(|s| s.records > 0 && s.max_time >= since)
+        {
+            read_lines(
+                &client.path(&segment.file),
+                segment.compression,
+                |record: FileRecord, _| {
+                    stories.insert(record.item.id, record.item);
+                },
+            )?;
+        }
+        let last_observed = client.last_observed()?;
+        Ok(stories
+            .into_values()
+            .filter(|item| item.tp == "story" && item.time >= since)
+            .map(|item| RefreshCandidate {
+                item_id: item.id,
+                time: item.time,
+                last_observed_at: last_observed.get(&item.id).copied(),
+            })
+            .collect())
+    }
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        let mut snapshots: Vec<ItemSnapshot> = self
+            .backend_client
+            .read_aux::<ItemSnapshot>(SNAPSHOTS_FILE)?
+            .into_iter()
+            .filter(|s| s.item_id == item_id)
+            .collect();
+        snapshots.sort_by_key(|s| s.observed_at);
+        Ok(snapshots)
+    }
+}
+
+// Implement the rankings in `story_rankings.jsonl`, one line per observation of a list
+impl GenericRankingStore for Store<FileClient> {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        if snapshot.ids.is_empty() {
+            return Ok(());
+        }
+        self.backend_client.append_aux(
+            RANKINGS_FILE,
+            &RankingRecord {
+                list: snapshot.list.as_str().to_string(),
+                observed_at: snapshot.observed_at,
+                ids: snapshot.ids.clone(),
+            },
+        )
+    }
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        Ok(self
+            .backend_client
+            .read_aux::<RankingRecord>(RANKINGS_FILE)?
+            .into_iter()
+            .filter(|r| r.list == list.as_str() && r.observed_at <= at)
+            .max_by_key(|r| r.observed_at)
+            .map(|r| RankingSnapshot {
+                list,
+                observed_at: r.observed_at,
+                ids: r.ids,
+            }))
+    }
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        let mut history: Vec<RankPosition> = self
+            .backend_client
+            .read_aux::<RankingRecord>(RANKINGS_FILE)?
+            .into_iter()
+            .filter(|r| r.list == list.as_str())
+            .filter_map(|r| {
+                r.ids
+                    .iter()
+                    .position(|id| *id == item_id)
+                    .map(|position| RankPosition {
+                        observed_at: r.observed_at,
+                        position: position as i64,
+                    })
+            })
+            .collect();
+        history.sort_by_key(|p| p.observed_at);
+        Ok(history)
+    }
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        let observations: BTreeSet<i64> = self
+            .backend_client
+            .read_aux::<RankingRecord>(RANKINGS_FILE)?
+            .into_iter()
+            .filter(|r| r.list == list.as_str())
+            .map(|r| r.observed_at)
+            .collect();
+        Ok(observations.into_iter().collect())
     }
-    fn store_item(&mut self, item: Item) -> Result<bool, ()>{
-        let _ = self.backend_client.base_path;
-        let _ = item;
-        todo!();
+}
+
+// The segments are the version history: a new line is appended whenever an item changes
+impl GenericItemHistory for Store<FileClient> {
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError> {
+        let (records, new_ids) = new_versions(self, vec![item.clone()], observed_at)?;
+        self.backend_client.append_records(&records, new_ids)?;
+        Ok(!records.is_empty())
+    }
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        let client = &self.backend_client;
+        let range = item_id..=item_id;
+        let mut versions = vec![];
+        for segment in &client.manifest.segments {
+            if !client.holds(segment, &range)? {
+                continue;
+            }
+            read_lines(
+                &client.path(&segment.file),
+                segment.compression,
+                |record: FileRecord, _| {
+                    if record.item.id == item_id {
+                        versions.push(ItemVersion {
+                            observed_at: record.observed_at,
+                            item: record.item,
+                        });
+                    }
+                },
+            )?;
+        }
+        versions.sort_by_key(|v| v.observed_at);
+        Ok(versions)
+    }
+}
+
+// Implement the tombstones in `item_tombstones.jsonl`, the last line of an item wins
+impl GenericTombstoneStore for Store<FileClient> {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        Ok(self
+            .backend_client
+            .read_aux::<Tombstone>(TOMBSTONES_FILE)?
+            .into_iter()
+            .rfind(|t| t.item_id == item_id))
+    }
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        self.backend_client.append_aux(TOMBSTONES_FILE, tombstone)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::crawler::{GenericStoreItem, ItemsCrawler};
-    use crate::ext::file::FileClient;
-    use crate::hub::NewsHub;
+    use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+    use crate::ext::file::{Compression, FileClient};
+    use crate::history::GenericItemHistory;
+    use crate::item::Item;
+    use crate::ranking::{GenericRankingStore, RankingSnapshot, StoryList};
+    use crate::retry::{GenericRetryQueue, RetryEntry};
     use crate::store::Store;
-    use crate::utils::CRAWLER_HUB;
+    use crate::testing::TempDir;
+    use crate::tombstone::GenericTombstoneStore;
+    use std::fs;
+    use std::io::Write;
+
+    fn mock_file_store(name: &str) -> (TempDir, Store<FileClient>) {
+        let dir = TempDir::new(&format!("file-{}", name));
+        let store = Store::<FileClient>::new(&dir.uri());
+        (dir, store)
+    }
+
+    fn story(id: i64, score: i64) -> Item {
+        let mut item = Item::parse(
+            id,
+            &format!(
+                r#"{{"id":{},"type":"story","by":"pg","time":{}}}"#,
+                id,
+                1_700_000_000 + id
+            ),
+        )
+        .unwrap();
+        item.score = score;
+        item
+    }
 
     #[test]
     fn test_get_item_file() {
-        let hub = NewsHub::new(&**CRAWLER_HUB);
-        let store_client  = Store::<FileClient>::new("/tmp/test");
-        let mut crawler = ItemsCrawler::new(hub, store_client);
-        let item = crawler.client.get_last_item();
-        println!("{:?}", item);
+        let (dir, mut store_client) = mock_file_store("get-item");
+        assert_eq!(store_client.get_last_item().unwrap(), 0);
+        assert!(store_client.store_item(story(3, 1)).unwrap());
+        store_client
+            .store_items(vec![story(1, 1), story(2, 1), story(3, 1)])
+            .unwrap();
+        store_client.upsert_item(story(2, 5)).unwrap();
+        store_client.save_checkpoint("sync", 3).unwrap();
+
+        let mut store_client = Store::<FileClient>::new(&dir.uri());
+        assert_eq!(store_client.get_last_item().unwrap(), 3);
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), Some(3));
+        assert_eq!(store_client.get_item(2).unwrap(), Some(story(2, 5)));
+        assert_eq!(store_client.get_item(4).unwrap(), None);
+        // Unchanged items are not appended again
+        assert_eq!(store_client.item_versions(3).unwrap().len(), 1);
+        assert_eq!(store_client.item_versions(2).unwrap().len(), 2);
+        assert_eq!(store_client.count().unwrap(), 3);
+        assert_eq!(store_client.missing_in_range(1..=5).unwrap(), vec![4, 5]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_rotation_and_compression() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let (dir, mut store_client) = mock_file_store(&format!("{:?}", compression));
+            store_client.backend_client.compression = compression;
+            store_client.backend_client.segment_ids = 9;
+            store_client
+                .store_items((1..=25).map(|id| story(id, 1)).collect())
+                .unwrap();
+            store_client
+                .store_items((26..=40).map(|id| story(id, 1)).collect())
+                .unwrap();
+            store_client.store_item(story(5, 7)).unwrap();
+
+            let mut store_client = Store::<FileClient>::new(&dir.uri());
+            let segments = &store_client.backend_client.manifest.segments;
+            assert_eq!(segments.len(), 3);
+            assert!(segments[..2]
+                .iter()
+                .all(|s| s.closed && s.compression == compression && s.index.is_some()));
+            assert!(!dir_has(&dir, "items-000001.jsonl"));
+            assert_eq!(store_client.get_last_item().unwrap(), 40);
+            assert_eq!(store_client.get_item(5).unwrap(), Some(story(5, 7)));
+            let ids: Vec<i64> = store_client
+                .get_items_range(20..=30, 5)
+                .unwrap()
+                .iter()
+                .map(|i| i.id)
+                .collect();
+            assert_eq!(ids, vec![20, 21, 22, 23, 24]);
+            let scanned: Vec<i64> = store_client.scan(1..=40).map(|i| i.unwrap().id).collect();
+            assert_eq!(scanned, (1..=40).collect::<Vec<i64>>());
+        }
+    }
+
+    #[test]
+    fn test_old_ids_skip_segments() {
+        let (dir, mut store_client) = mock_file_store("old-ids");
+        store_client.backend_client.segment_ids = 5;
+        store_client
+            .store_items((1..=6).map(|id| story(id, 1)).collect())
+            .unwrap();
+        // A segment of updates of old items, closed by size only
+        store_client.backend_client.segment_bytes = 1;
+        store_client
+            .store_items(vec![story(1, 2), story(6, 2)])
+            .unwrap();
+        store_client.backend_client.segment_bytes = u64::MAX;
+        store_client
+            .store_items(vec![story(7, 1), story(8, 1)])
+            .unwrap();
+        for _ in 0..3 {
+            store_client
+                .store_items(vec![story(1, 3), story(8, 3)])
+                .unwrap();
+            store_client
+                .store_items(vec![story(1, 4), story(8, 4)])
+                .unwrap();
+        }
+        assert_eq!(store_client.backend_client.manifest.segments.len(), 3);
+
+        // The id index of the updates tells they hold none of 2..=5, so the file is never read
+        fs::write(dir.path().join("items-000002.jsonl"), b"garbage\n").unwrap();
+        let mut store_client = Store::<FileClient>::new(&dir.uri());
+        assert_eq!(store_client.get_item(3).unwrap(), Some(story(3, 1)));
+        assert_eq!(store_client.get_items_range(2..=5, 10).unwrap().len(), 4);
+        assert_eq!(store_client.item_versions(5).unwrap().len(), 1);
+        assert!(store_client.get_item(6).is_err());
+    }
+
+    #[test]
+    fn test_membership_from_id_index() {
+        let (dir, mut store_client) = mock_file_store("membership");
+        store_client.backend_client.segment_ids = 4;
+        store_client
+            .store_items(
+                (1..=10)
+                    .filter(|id| id % 3 != 0)
+                    .map(|id| story(id, 1))
+                    .collect(),
+            )
+            .unwrap();
+        store_client
+            .store_items(vec![story(1, 2), story(12, 1)])
+            .unwrap();
+        assert_eq!(store_client.backend_client.manifest.segments.len(), 2);
+        assert_eq!(store_client.count().unwrap(), 8);
+
+        // Only the id indexes of the closed segments are read
+        fs::write(dir.path().join("items-000001.jsonl"), b"garbage\n").unwrap();
+        let mut held: Vec<i64> = store_client
+            .contains(&[1, 3, 8, 12, 40])
+            .unwrap()
+            .into_iter()
+            .collect();
+        held.sort();
+        assert_eq!(held, vec![1, 8, 12]);
+        assert_eq!(
+            store_client.missing_in_range(5..=13).unwrap(),
+            vec![6, 9, 11, 13]
+        );
+
+        // Lines appended after the last manifest write, and a manifest without the count
+        let mut line = serde_json::to_string(&story(11, 1)).unwrap();
+        line.insert_str(1, r#""observed_at":1,"#);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("items-000002.jsonl"))
+            .unwrap()
+            .write_all((line + "\n").as_bytes())
+            .unwrap();
+        let mut store_client = Store::<FileClient>::new(&dir.uri());
+        assert_eq!(store_client.count().unwrap(), 9);
+        store_client.backend_client.manifest.items = None;
+        assert_eq!(store_client.count().unwrap(), 9);
+    }
+
+    fn dir_has(dir: &TempDir, file: &str) -> bool {
+        dir.path().join(file).exists()
+    }
+
+    #[test]
+    fn test_recover_torn_write() {
+        let (dir, mut store_client) = mock_file_store("torn");
+        store_client
+            .store_items(vec![story(1, 1), story(2, 1)])
+            .unwrap();
+        let segment = dir.path().join("items-000001.jsonl");
+        // A crash in the middle of appending an item and of rewriting the manifest
+        let mut file = fs::OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(br#"{"observed_at":1,"id":3,"type":"sto"#)
+            .unwrap();
+        fs::write(dir.path().join("manifest.json.tmp"), b"{").unwrap();
+
+        let mut store_client = Store::<FileClient>::new(&dir.uri());
+        assert_eq!(store_client.get_last_item().unwrap(), 2);
+        assert!(!dir_has(&dir, "manifest.json.tmp"));
+        store_client.store_item(story(3, 1)).unwrap();
+        assert_eq!(store_client.get_item(3).unwrap(), Some(story(3, 1)));
+        assert_eq!(store_client.backend_client.manifest.segments[0].records, 3);
+    }
+
+    #[test]
+    fn test_auxiliary_files() {
+        let (dir, mut store_client) = mock_file_store("aux");
+        store_client
+            .save_retry(&RetryEntry::failed(None, 7, "timeout", 100))
+            .unwrap();
+        store_client
+            .save_ranking(&RankingSnapshot {
+                list: StoryList::Top,
+                observed_at: 10,
+                ids: vec![3, 1],
+            })
+            .unwrap();
+        store_client
+            .save_ranking(&RankingSnapshot {
+                list: StoryList::Top,
+                observed_at: 20,
+                ids: vec![1, 3],
+            })
+            .unwrap();
+        let mut deleted = story(1, 1);
+        store_client.store_item(deleted.clone()).unwrap();
+        deleted.deleted = true;
+        deleted.title = String::new();
+        store_client.store_item(deleted).unwrap();
+
+        let mut store_client = Store::<FileClient>::new(&dir.uri());
+        assert_eq!(
+            store_client.get_retry(7).unwrap().map(|e| e.attempts),
+            Some(1)
+        );
+        assert_eq!(store_client.due_retries(i64::MAX, 10).unwrap().len(), 1);
+        store_client.remove_retry(7).unwrap();
+        assert_eq!(store_client.get_retry(7).unwrap(), None);
+        assert_eq!(
+            store_client
+                .ranking_at(StoryList::Top, 15)
+                .unwrap()
+                .unwrap()
+                .ids,
+            vec![3, 1]
+        );
+        assert_eq!(
+            store_client.ranking_observations(StoryList::Top).unwrap(),
+            vec![10, 20]
+        );
+        let positions: Vec<i64> = store_client
+            .ranking_history(StoryList::Top, 1)
+            .unwrap()
+            .iter()
+            .map(|p| p.position)
+            .collect();
+        assert_eq!(positions, vec![1, 0]);
+        assert!(store_client
+            .get_tombstone(1)
+            .unwrap()
+            .unwrap()
+            .deleted_at
+            .is_some());
+    }
+}
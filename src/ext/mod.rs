@@ -1,3 +1,51 @@
-pub mod postgres;
+pub mod fanout;
 pub mod file;
-mod sqlite;
\ No newline at end of file
+pub mod mysql;
+pub mod parquet;
+pub mod postgres;
+mod redb;
+mod sqlite;
+pub mod stdout;
+pub mod webhook;
+
+use crate::crawler::GenericStore;
+use crate::error::CrawlerError;
+use crate::store::Store;
+use file::FileClient;
+use parquet::ParquetClient;
+use webhook::WebhookClient;
+
+/// Open the `store` backend at `uri` behind a trait object
+pub fn open_store(store: &str, uri: &str) -> Result<Box<dyn GenericStore>, CrawlerError> {
+    Ok(match store {
+        "sqlite" => Box::new(Store::<::sqlite::Connection>::new(uri)),
+        "postgres" => Box::new(Store::<::postgres::Client>::new(uri)),
+        "mysql" => Box::new(Store::<::mysql::Conn>::new(uri)),
+        "file" => Box::new(Store::<FileClient>::new(uri)),
+        "parquet" => Box::new(Store::<ParquetClient>::new(uri)),
+        "redb" => Box::new(Store::<::redb::Database>::new(uri)),
+        "webhook" => Box::new(Store::<WebhookClient>::new(uri)),
+        _ => {
+            return Err(CrawlerError::Store {
+                reason: format!("`store` {:?} is not supported", store),
+            })
+        }
+    })
+}
+
+/// Stores which only take writes, reading them back never returns the items written
+const WRITE_ONLY_STORES: [&str; 1] = ["webhook"];
+
+/// Open the `store` backend at `uri` like `open_store`, for commands reading its items back.
+/// Write-only stores are rejected, their reads would never match what was written
+pub fn open_readable_store(store: &str, uri: &str) -> Result<Box<dyn GenericStore>, CrawlerError> {
+    if WRITE_ONLY_STORES.contains(&store) {
+        return Err(CrawlerError::Store {
+            reason: format!(
+                "`store` {:?} is write-only, its items can't be read back",
+                store
+            ),
+        });
+    }
+    open_store(store, uri)
+}
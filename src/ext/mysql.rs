@@ -0,0 +1,706 @@
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::history::{GenericItemHistory, ItemVersion};
+use crate::item::Item;
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::retry::{GenericRetryQueue, RetryEntry, RetryState};
+use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use crate::utils::now;
+use mysql::prelude::Queryable;
+use mysql::{Conn, Opts, Row, TxOpts, Value};
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+/// Rows of a multi-row statement, far below the 65535 placeholders of a prepared statement
+const MAX_ROWS_PER_STATEMENT: usize = 1000;
+
+/// Init all the tables needed by the crawler, same layout as the sqlite store
+fn init_tables(conn: &mut Conn) {
+    let query = r###"
+            CREATE TABLE IF NOT EXISTS `items` (
+              `id` bigint NOT NULL PRIMARY KEY,
+              `deleted` tinyint(4) DEFAULT '0',
+              `type` varchar(16) DEFAULT NULL,
+              `who` varchar(255) DEFAULT NULL,
+              `time` bigint DEFAULT NULL,
+              `dead` tinyint(4) DEFAULT '0',
+              `kids` mediumtext DEFAULT NULL,
+              `title` text DEFAULT NULL,
+              `content` mediumtext DEFAULT NULL,
+              `score` int(10) DEFAULT NULL,
+              `url` text DEFAULT NULL,
+              `parent` bigint DEFAULT NULL
+            ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+            CREATE TABLE IF NOT EXISTS `retry_queue` (
+              `id` bigint NOT NULL PRIMARY KEY,
+              `attempts` int(10) NOT NULL DEFAULT '0',
+              `last_error` text DEFAULT NULL,
+              `next_attempt_at` bigint NOT NULL,
+              `state` varchar(16) NOT NULL DEFAULT 'pending'
+            ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+            CREATE TABLE IF NOT EXISTS `checkpoints` (
+              `name` varchar(64) NOT NULL PRIMARY KEY,
+              `value` bigint NOT NULL,
+              `updated_at` bigint NOT NULL
+            ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+            CREATE TABLE IF NOT EXISTS `item_snapshots` (
+              `id` bigint NOT NULL,
+              `observed_at` bigint NOT NULL,
+              `score` int(10) DEFAULT NULL,
+              `descendants` int(10) DEFAULT NULL,
+              PRIMARY KEY (`id`, `observed_at`)
+            ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+            CREATE TABLE IF NOT EXISTS `story_rankings` (
+              `list` varchar(16) NOT NULL,
+              `observed_at` bigint NOT NULL,
+              `position` int(10) NOT NULL,
+              `id` bigint NOT NULL,
+              PRIMARY KEY (`list`, `observed_at`, `position`),
+              KEY `story_rankings_list_id` (`list`, `id`)
+            ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+            CREATE TABLE IF NOT EXISTS `item_versions` (
+              `seq` bigint NOT NULL AUTO_INCREMENT PRIMARY KEY,
+              `id` bigint NOT NULL,
+              `observed_at` bigint NOT NULL,
+              `data` mediumtext NOT NULL,
+              KEY `item_versions_id_observed_at` (`id`, `observed_at`)
+            ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+            CREATE TABLE IF NOT EXISTS `item_tombstones` (
+              `id` bigint NOT NULL PRIMARY KEY,
+              `deleted_at` bigint DEFAULT NULL,
+              `dead_at` bigint DEFAULT NULL,
+              `who` varchar(255) DEFAULT NULL,
+              `title` text DEFAULT NULL,
+              `content` mediumtext DEFAULT NULL,
+              `url` text DEFAULT NULL
+            ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4"###;
+    conn.query_drop(query).unwrap()
+}
+
+const UPSERT_ITEM_COLUMNS: &str = "\
+    `deleted` = VALUES(`deleted`), `type` = VALUES(`type`), `who` = VALUES(`who`), \
+    `time` = VALUES(`time`), `dead` = VALUES(`dead`), `kids` = VALUES(`kids`), \
+    `title` = VALUES(`title`), `content` = VALUES(`content`), `score` = VALUES(`score`), \
+    `url` = VALUES(`url`), `parent` = VALUES(`parent`)";
+
+/// `INSERT INTO <table> VALUES (?, ...), ...` for `rows` rows of `columns` values
+fn multi_row_insert(table: &str, columns: usize, rows: usize) -> String {
+    let row = format!("({})", vec!["?"; columns].join(", "));
+    format!(
+        "INSERT INTO `{}` VALUES {}",
+        table,
+        vec![row; rows].join(", ")
+    )
+}
+
+/// The values of an item in the column order of `items`
+fn item_values(item: &Item) -> Vec<Value> {
+    vec![
+        item.id.into(),
+        item.deleted.into(),
+        item.tp.as_str().into(),
+        item.who.as_str().into(),
+        item.time.into(),
+        item.dead.into(),
+        format!("{:?}", item.kids).into(),
+        item.title.as_str().into(),
+        item.text.as_str().into(),
+        item.score.into(),
+        item.url.as_str().into(),
+        item.parent.into(),
+    ]
+}
+
+fn upsert_tombstone(conn: &mut impl Queryable, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+    conn.exec_drop(
+        "INSERT INTO `item_tombstones` VALUES (?, ?, ?, ?, ?, ?, ?) \
+        ON DUPLICATE KEY UPDATE `deleted_at` = VALUES(`deleted_at`), `dead_at` = VALUES(`dead_at`), \
+        `who` = VALUES(`who`), `title` = VALUES(`title`), `content` = VALUES(`content`), `url` = VALUES(`url`)",
+        (tombstone.item_id, tombstone.deleted_at, tombstone.dead_at,
+            &tombstone.who, &tombstone.title, &tombstone.text, &tombstone.url))?;
+    Ok(())
+}
+
+/// The latest recorded version of the `ids`
+fn latest_versions(
+    conn: &mut impl Queryable,
+    ids: &[i64],
+) -> Result<HashMap<i64, String>, CrawlerError> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let sql = format!(
+        "SELECT v.`id`, v.`data` FROM `item_versions` v WHERE v.`id` IN ({}) AND NOT EXISTS ( \
+            SELECT 1 FROM `item_versions` w WHERE w.`id` = v.`id` AND (w.`observed_at` > v.`observed_at` \
+                OR (w.`observed_at` = v.`observed_at` AND w.`seq` > v.`seq`)))",
+        vec!["?"; ids.len()].join(", "));
+    Ok(conn
+        .exec_map(sql, ids.to_vec(), |(id, data)| (id, data))?
+        .into_iter()
+        .collect())
+}
+
+/// The tombstones of the `ids` which have one
+fn read_tombstones(
+    conn: &mut impl Queryable,
+    ids: &[i64],
+) -> Result<HashMap<i64, Tombstone>, CrawlerError> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let sql = format!(
+        "SELECT * FROM `item_tombstones` WHERE `id` IN ({})",
+        vec!["?"; ids.len()].join(", ")
+    );
+    let rows: Vec<Row> = conn.exec(sql, ids.to_vec())?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let text = |column: &str| {
+                r.get::<Option<String>, _>(column)
+                    .flatten()
+                    .unwrap_or_default()
+            };
+            let item_id = r.get::<i64, _>("id").unwrap_or_default();
+            (
+                item_id,
+                Tombstone {
+                    item_id,
+                    deleted_at: r.get::<Option<i64>, _>("deleted_at").flatten(),
+                    dead_at: r.get::<Option<i64>, _>("dead_at").flatten(),
+                    who: text("who"),
+                    title: text("title"),
+                    text: text("content"),
+                    url: text("url"),
+                },
+            )
+        })
+        .collect())
+}
+
+/// The tombstone to save when `item` is observed, if any
+fn track_tombstone(
+    conn: &mut impl Queryable,
+    item: &Item,
+    previous: Option<&str>,
+    at: i64,
+) -> Result<Option<Tombstone>, CrawlerError> {
+    let previous = previous.map(|p| Item::parse(item.id, p)).transpose()?;
+    let existing = read_tombstones(conn, &[item.id])?.remove(&item.id);
+    Ok(Tombstone::track(existing, previous.as_ref(), item, at))
+}
+
+/// Append `item` to its history with its tombstone, unless it equals the latest version.
+/// The caller runs it in the transaction of the write of the item
+fn record_version_in(
+    conn: &mut impl Queryable,
+    item: &Item,
+    observed_at: i64,
+) -> Result<bool, CrawlerError> {
+    let data = serde_json::to_string(item)?;
+    let previous = latest_versions(conn, &[item.id])?.remove(&item.id);
+    if previous.as_ref() == Some(&data) {
+        return Ok(false);
+    }
+    if let Some(tombstone) = track_tombstone(conn, item, previous.as_deref(), observed_at)? {
+        upsert_tombstone(conn, &tombstone)?;
+    }
+    conn.exec_drop(
+        "INSERT INTO `item_versions` (`id`, `observed_at`, `data`) VALUES (?, ?, ?)",
+        (item.id, observed_at, &data),
+    )?;
+    Ok(true)
+}
+
+// Implement Store for MySQL and MariaDB
+impl Store<Conn> {
+    pub fn new(uri: &str) -> Self {
+        let mut conn = Conn::new(Opts::from_url(uri).unwrap()).unwrap();
+        init_tables(&mut conn);
+        Self {
+            backend_client: conn,
+        }
+    }
+}
+
+// Implement item data to MySQL
+impl GenericStoreItem for Store<Conn> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        let last: Option<Option<i64>> = self
+            .backend_client
+            .query_first("SELECT max(`id`) FROM `items`")?;
+        Ok(last.flatten().unwrap_or(0))
+    }
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.upsert_item(item)?;
+        Ok(true)
+    }
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        let sql = format!(
+            "{} ON DUPLICATE KEY UPDATE {}",
+            multi_row_insert("items", 12, 1),
+            UPSERT_ITEM_COLUMNS
+        );
+        let mut tx = self.backend_client.start_transaction(TxOpts::default())?;
+        tx.exec_drop(sql, item_values(&item))?;
+        record_version_in(&mut tx, &item, now())?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        let row: Option<Row> = self
+            .backend_client
+            .exec_first("SELECT * FROM `items` WHERE `id` = ?", (item_id,))?;
+        Ok(row.map(|r| read_item(&r)))
+    }
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        let rows: Vec<Row> = self.backend_client.exec(
+            "SELECT * FROM `items` WHERE `id` BETWEEN ? AND ? ORDER BY `id` LIMIT ?",
+            (range.start(), range.end(), limit as u64),
+        )?;
+        Ok(rows.iter().map(read_item).collect())
+    }
+    /// Upsert the items and append their new versions with multi-row statements,
+    /// all in one transaction
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        if items.is_empty() {
+            return Ok(0);
+        }
+        let observed_at = now();
+        // The last occurrence of an id in the batch wins, like a sequence of upserts
+        let mut latest: HashMap<i64, &Item> = HashMap::new();
+        for item in &items {
+            latest.insert(item.id, item);
+        }
+        let ids: Vec<i64> = latest.keys().copied().collect();
+
+        let mut tx = self.backend_client.start_transaction(TxOpts::default())?;
+        let previous = latest_versions(&mut tx, &ids)?;
+        let mut existing = read_tombstones(&mut tx, &ids)?;
+        let mut versions = vec![];
+        let mut tombstones = vec![];
+        for (id, item) in &latest {
+            let data = serde_json::to_string(item)?;
+            if previous.get(id) == Some(&data) {
+                continue;
+            }
+            // Only the deleted or dead items, and the ones coming back, can change their tombstone
+            if item.deleted || item.dead || existing.contains_key(id) {
+                let before = previous.get(id).map(|p| Item::parse(*id, p)).transpose()?;
+                if let Some(tombstone) =
+                    Tombstone::track(existing.remove(id), before.as_ref(), item, observed_at)
+                {
+                    tombstones.push(tombstone);
+                }
+            }
+            versions.push((*id, data));
+        }
+        for chunk in items.chunks(MAX_ROWS_PER_STATEMENT) {
+            let sql = format!(
+                "{} ON DUPLICATE KEY UPDATE {}",
+                multi_row_insert("items", 12, chunk.len()),
+                UPSERT_ITEM_COLUMNS
+            );
+            tx.exec_drop(
+                sql,
+                chunk.iter().flat_map(item_values).collect::<Vec<Value>>(),
+            )?;
+        }
+        for chunk in versions.chunks(MAX_ROWS_PER_STATEMENT) {
+            let sql = format!(
+                "INSERT INTO `item_versions` (`id`, `observed_at`, `data`) VALUES {}",
+                vec!["(?, ?, ?)"; chunk.len()].join(", ")
+            );
+            let values: Vec<Value> = chunk
+                .iter()
+                .flat_map(|(id, data)| [(*id).into(), observed_at.into(), data.as_str().into()])
+                .collect();
+            tx.exec_drop(sql, values)?;
+        }
+        for tombstone in &tombstones {
+            upsert_tombstone(&mut tx, tombstone)?;
+        }
+        tx.commit()?;
+        Ok(items.len())
+    }
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        if ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let sql = format!(
+            "SELECT `id` FROM `items` WHERE `id` IN ({})",
+            vec!["?"; ids.len()].join(", ")
+        );
+        let stored: Vec<i64> = self.backend_client.exec(sql, ids.to_vec())?;
+        Ok(stored.into_iter().collect())
+    }
+    fn count(&mut self) -> Result<u64, CrawlerError> {
+        let count: Option<u64> = self
+            .backend_client
+            .query_first("SELECT count(*) FROM `items`")?;
+        Ok(count.unwrap_or(0))
+    }
+}
+
+/// Read a row of the `items` table
+fn read_item(row: &Row) -> Item {
+    let text = |column: &str| {
+        row.get::<Option<String>, _>(column)
+            .flatten()
+            .unwrap_or_default()
+    };
+    let number = |column: &str| {
+        row.get::<Option<i64>, _>(column)
+            .flatten()
+            .unwrap_or_default()
+    };
+    Item {
+        id: number("id"),
+        deleted: number("deleted") != 0,
+        tp: text("type"),
+        who: text("who"),
+        time: number("time"),
+        dead: number("dead") != 0,
+        kids: Item::parse_kids(&text("kids")),
+        title: text("title"),
+        score: number("score"),
+        text: text("content"),
+        url: text("url"),
+        parent: number("parent"),
+        descendants: 0,
+    }
+}
+
+// Implement the retry queue in the `retry_queue` table
+impl GenericRetryQueue for Store<Conn> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        let row: Option<Row> = self
+            .backend_client
+            .exec_first("SELECT * FROM `retry_queue` WHERE `id` = ?", (item_id,))?;
+        row.map(|r| read_retry_entry(&r)).transpose()
+    }
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        self.backend_client.exec_drop(
+            "INSERT INTO `retry_queue` VALUES (?, ?, ?, ?, ?) \
+            ON DUPLICATE KEY UPDATE `attempts` = VALUES(`attempts`), `last_error` = VALUES(`last_error`), \
+            `next_attempt_at` = VALUES(`next_attempt_at`), `state` = VALUES(`state`)",
+            (entry.item_id, entry.attempts, &entry.last_error, entry.next_attempt_at, entry.state.as_str()))?;
+        Ok(())
+    }
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        self.backend_client
+            .exec_drop("DELETE FROM `retry_queue` WHERE `id` = ?", (item_id,))?;
+        Ok(())
+    }
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        let rows: Vec<Row> = self.backend_client.exec(
+            "SELECT * FROM `retry_queue` WHERE `state` = ? AND `next_attempt_at` <= ? \
+            ORDER BY `next_attempt_at` LIMIT ?",
+            (RetryState::Pending.as_str(), now, limit as u64),
+        )?;
+        rows.iter().map(read_retry_entry).collect()
+    }
+}
+
+/// Read a row of the `retry_queue` table
+fn read_retry_entry(row: &Row) -> Result<RetryEntry, CrawlerError> {
+    let state = row.get::<String, _>("state").unwrap_or_default();
+    Ok(RetryEntry {
+        item_id: row.get("id").unwrap_or_default(),
+        attempts: row.get("attempts").unwrap_or_default(),
+        last_error: row
+            .get::<Option<String>, _>("last_error")
+            .flatten()
+            .unwrap_or_default(),
+        next_attempt_at: row.get("next_attempt_at").unwrap_or_default(),
+        state: RetryState::parse(&state).ok_or_else(|| CrawlerError::Store {
+            reason: format!("unknown retry state {state:?}"),
+        })?,
+    })
+}
+
+// Implement the checkpoints in the `checkpoints` table
+impl GenericCheckpoint for Store<Conn> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        Ok(self.backend_client.exec_first(
+            "SELECT `value` FROM `checkpoints` WHERE `name` = ?",
+            (name,),
+        )?)
+    }
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        self.backend_client.exec_drop(
+            "INSERT INTO `checkpoints` VALUES (?, ?, ?) \
+            ON DUPLICATE KEY UPDATE `value` = VALUES(`value`), `updated_at` = VALUES(`updated_at`)",
+            (name, value, now()),
+        )?;
+        Ok(())
+    }
+}
+
+// Implement the snapshots in the `item_snapshots` table
+impl GenericSnapshotStore for Store<Conn> {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        self.backend_client.exec_drop(
+            "INSERT INTO `item_snapshots` VALUES (?, ?, ?, ?) \
+            ON DUPLICATE KEY UPDATE `score` = VALUES(`score`), `descendants` = VALUES(`descendants`)",
+            (snapshot.item_id, snapshot.observed_at, snapshot.score, snapshot.descendants))?;
+        Ok(())
+    }
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        Ok(self.backend_client.exec_map(
+            "SELECT i.`id`, i.`time`, max(s.`observed_at`) AS last_observed_at \
+            FROM `items` i LEFT JOIN `item_snapshots` s ON s.`id` = i.`id` \
+            WHERE i.`type` = 'story' AND i.`time` >= ? \
+            GROUP BY i.`id`, i.`time`",
+            (since,),
+            |(item_id, time, last_observed_at)| RefreshCandidate {
+                item_id,
+                time,
+                last_observed_at,
+            },
+        )?)
+    }
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        Ok(self.backend_client.exec_map(
+            "SELECT `observed_at`, `score`, `descendants` FROM `item_snapshots` WHERE `id` = ? ORDER BY `observed_at`",
+            (item_id,),
+            |(observed_at, score, descendants): (i64, Option<i64>, Option<i64>)| ItemSnapshot {
+                item_id,
+                observed_at,
+                score: score.unwrap_or_default(),
+                descendants: descendants.unwrap_or_default(),
+            })?)
+    }
+}
+
+// Implement the story lists observations in the `story_rankings` table
+impl GenericRankingStore for Store<Conn> {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        for (chunk_index, chunk) in snapshot.ids.chunks(MAX_ROWS_PER_STATEMENT).enumerate() {
+            let sql = format!(
+                "{} ON DUPLICATE KEY UPDATE `id` = VALUES(`id`)",
+                multi_row_insert("story_rankings", 4, chunk.len())
+            );
+            let values: Vec<Value> = chunk
+                .iter()
+                .enumerate()
+                .flat_map(|(i, id)| {
+                    [
+                        snapshot.list.as_str().into(),
+                        snapshot.observed_at.into(),
+                        ((chunk_index * MAX_ROWS_PER_STATEMENT + i) as i64).into(),
+                        (*id).into(),
+                    ]
+                })
+                .collect();
+            self.backend_client.exec_drop(sql, values)?;
+        }
+        Ok(())
+    }
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        let rows: Vec<(i64, i64)> = self.backend_client.exec(
+            "SELECT `observed_at`, `id` FROM `story_rankings` \
+            WHERE `list` = ? AND `observed_at` = ( \
+                SELECT max(`observed_at`) FROM `story_rankings` WHERE `list` = ? AND `observed_at` <= ?) \
+            ORDER BY `position`",
+            (list.as_str(), list.as_str(), at))?;
+        Ok(rows.first().map(|(observed_at, _)| RankingSnapshot {
+            list,
+            observed_at: *observed_at,
+            ids: rows.iter().map(|(_, id)| *id).collect(),
+        }))
+    }
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        Ok(self.backend_client.exec_map(
+            "SELECT `observed_at`, `position` FROM `story_rankings` \
+            WHERE `list` = ? AND `id` = ? ORDER BY `observed_at`",
+            (list.as_str(), item_id),
+            |(observed_at, position)| RankPosition {
+                observed_at,
+                position,
+            },
+        )?)
+    }
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        Ok(self.backend_client.exec(
+            "SELECT DISTINCT `observed_at` FROM `story_rankings` WHERE `list` = ? ORDER BY `observed_at`",
+            (list.as_str(),))?)
+    }
+}
+
+// Implement the item history in the `item_versions` table
+impl GenericItemHistory for Store<Conn> {
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError> {
+        record_version_in(&mut self.backend_client, item, observed_at)
+    }
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        let rows: Vec<(i64, String)> = self.backend_client.exec(
+            "SELECT `observed_at`, `data` FROM `item_versions` WHERE `id` = ? ORDER BY `observed_at`, `seq`",
+            (item_id,))?;
+        rows.into_iter()
+            .map(|(observed_at, data)| {
+                Ok(ItemVersion {
+                    observed_at,
+                    item: Item::parse(item_id, &data)?,
+                })
+            })
+            .collect()
+    }
+}
+
+// Implement the tombstones in the `item_tombstones` table
+impl GenericTombstoneStore for Store<Conn> {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        Ok(read_tombstones(&mut self.backend_client, &[item_id])?.remove(&item_id))
+    }
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        upsert_tombstone(&mut self.backend_client, tombstone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::GenericStoreItem;
+    use crate::ext::mysql::multi_row_insert;
+    use crate::history::GenericItemHistory;
+    use crate::item::Item;
+    use crate::store::Store;
+    use crate::tombstone::GenericTombstoneStore;
+    use mysql::prelude::Queryable;
+    use mysql::Conn;
+
+    fn mock_mysql() -> Store<Conn> {
+        let url = format!(
+            "mysql://{}:{}@{}:{}/{}",
+            "hackernews", "hackernews", "localhost", 3306, "hackernews"
+        );
+        Store::<Conn>::new(&url)
+    }
+
+    #[test]
+    fn test_multi_row_insert() {
+        assert_eq!(
+            multi_row_insert("items", 2, 3),
+            "INSERT INTO `items` VALUES (?, ?), (?, ?), (?, ?)"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a MariaDB or MySQL server, run with --ignored"]
+    fn test_store_items_mysql() {
+        let mut store_client = mock_mysql();
+        // Negative ids are never crawled, the rows left by a previous run are cleared first
+        for table in ["items", "item_versions", "item_tombstones"] {
+            store_client
+                .backend_client
+                .query_drop(format!(
+                    "DELETE FROM `{}` WHERE `id` IN (-1001, -1002)",
+                    table
+                ))
+                .unwrap();
+        }
+
+        let story = Item::from(String::from(
+            r#"{"id": -1001, "type": "story", "time": 1, "title": "Quotes ' and \"emoji\" 🦀", "kids": [1, 2]}"#,
+        ));
+        let comment = Item::from(String::from(
+            r#"{"id": -1002, "type": "comment", "time": 1, "by": "pg", "text": "it's fine"}"#,
+        ));
+        assert_eq!(
+            store_client
+                .store_items(vec![story.clone(), comment.clone()])
+                .unwrap(),
+            2
+        );
+        // Storing the same items again upserts without new versions
+        assert_eq!(store_client.store_items(vec![story.clone()]).unwrap(), 1);
+        assert_eq!(store_client.get_item(-1001).unwrap(), Some(story));
+        assert_eq!(store_client.item_versions(-1001).unwrap().len(), 1);
+
+        let deleted = Item::from(String::from(
+            r#"{"id": -1002, "type": "comment", "time": 1, "deleted": true}"#,
+        ));
+        store_client.store_items(vec![deleted]).unwrap();
+        assert_eq!(
+            store_client.get_tombstone(-1002).unwrap().unwrap().text,
+            "it's fine"
+        );
+        assert_eq!(store_client.item_versions(-1002).unwrap().len(), 2);
+
+        let mut edited = comment;
+        edited.text = String::from("edited");
+        store_client.store_item(edited).unwrap();
+        assert_eq!(store_client.item_versions(-1002).unwrap().len(), 3);
+
+        assert_eq!(
+            store_client.contains(&[-1001, -1003]).unwrap(),
+            [-1001].into()
+        );
+        assert_eq!(
+            store_client.missing_in_range(-1003..=-1001).unwrap(),
+            vec![-1003]
+        );
+        assert!(store_client.get_last_item().unwrap() >= -1001);
+        assert!(store_client.count().unwrap() >= 2);
+    }
+
+    #[test]
+    #[ignore = "needs a MariaDB or MySQL server, run with --ignored"]
+    fn test_upsert_rolls_back_mysql() {
+        let mut store_client = mock_mysql();
+        for table in ["items", "item_versions"] {
+            store_client
+                .backend_client
+                .query_drop(format!(
+                    "DELETE FROM `{}` WHERE `id` IN (-1101, -1102)",
+                    table
+                ))
+                .unwrap();
+        }
+        let story = |id: i64, title: &str| {
+            Item::from(format!(
+                r#"{{"id": {id}, "type": "story", "time": 1, "title": "{title}"}}"#
+            ))
+        };
+        store_client.upsert_item(story(-1101, "first")).unwrap();
+
+        // The versions of the test ids can't be recorded anymore
+        store_client
+            .backend_client
+            .query_drop(
+                "DROP TRIGGER IF EXISTS `reject_test_version`; \
+                CREATE TRIGGER `reject_test_version` BEFORE INSERT ON `item_versions` FOR EACH ROW \
+                IF NEW.`id` IN (-1101, -1102) THEN \
+                  SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'rejected version'; \
+                END IF",
+            )
+            .unwrap();
+        let edited = store_client.upsert_item(story(-1101, "edited"));
+        let created = store_client.upsert_item(story(-1102, "new"));
+        store_client
+            .backend_client
+            .query_drop("DROP TRIGGER `reject_test_version`")
+            .unwrap();
+
+        assert!(edited.is_err() && created.is_err());
+        assert_eq!(
+            store_client.get_item(-1101).unwrap().unwrap().title,
+            "first"
+        );
+        assert_eq!(store_client.get_item(-1102).unwrap(), None);
+    }
+}
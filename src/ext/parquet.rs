@@ -0,0 +1,921 @@
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::ext::file::{
+    append_synced, contains_ids, count_ids, index_holds, missing_ids, new_versions, read_id_index,
+    read_lines, sync_dir, truncate_torn_tail, write_atomic, write_id_index, IndexedRecords,
+};
+use crate::ext::file::{Compression as FileCompression, FileClient, FileRecord};
+use crate::history::{GenericItemHistory, ItemVersion};
+use crate::item::Item;
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::retry::{GenericRetryQueue, RetryEntry};
+use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use crate::utils::{now, PARQUET_ROW_GROUP_ROWS};
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::SerializedFileReader;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::{Field, Row, RowAccessor};
+use parquet::schema::parser::parse_message_type;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const MANIFEST_FILE: &str = "_manifest.json";
+/// Readers of the dataset skip the paths starting with `_`
+const STATE_DIR: &str = "_state";
+/// Id indexes of the parts, named after them
+const INDEX_DIR: &str = "_index";
+
+/// Columns of the part files, the `Item` fields plus `observed_at`.
+/// `type` is the partition column, so it is only found in the path
+const ITEM_SCHEMA: &str = "
+message item {
+    required int64 observed_at;
+    required int64 id;
+    required boolean deleted;
+    required binary by (STRING);
+    required int64 time;
+    required boolean dead;
+    required group kids (LIST) {
+        repeated group list {
+            required int64 element;
+        }
+    }
+    required binary title (STRING);
+    required int64 score;
+    required binary text (STRING);
+    required binary url (STRING);
+    required int64 parent;
+    required int64 descendants;
+}";
+
+/// One Parquet file of a `type=/date=` partition, holding one row group
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Part {
+    file: String,
+    tp: String,
+    min_id: i64,
+    max_id: i64,
+    max_time: i64,
+    rows: u64,
+    /// File of the sorted ids of the part
+    #[serde(default)]
+    index: Option<String>,
+}
+
+impl Part {
+    fn overlaps(&self, range: &RangeInclusive<i64>) -> bool {
+        self.min_id <= *range.end() && *range.start() <= self.max_id
+    }
+}
+
+/// The written parts and the generation of the spool holding the buffered rows.
+/// Both change in the same atomic rewrite, so a row is either in a part or in the spool
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Manifest {
+    parts: Vec<Part>,
+    spool: u64,
+    /// Number of distinct ids of the parts, counted again from the id indexes when unknown
+    #[serde(default)]
+    items: Option<u64>,
+}
+
+/// Wrap a Parquet dataset into ParquetClient: item versions are buffered in an NDJSON spool
+/// until a row group is full, then written to `type=<type>/date=<yyyy-mm-dd>/part-N.parquet`.
+/// The other capabilities are kept by a file store under `_state`
+pub struct ParquetClient {
+    base_path: PathBuf,
+    manifest: Manifest,
+    buffer: Vec<FileRecord>,
+    row_group_rows: usize,
+    state: Store<FileClient>,
+}
+impl ParquetClient {
+    /// Open the dataset under `dir`, replaying the spool of the rows not written yet
+    pub fn open(dir: &str) -> Result<Self, CrawlerError> {
+        let base_path = PathBuf::from(dir);
+        fs::create_dir_all(&base_path)?;
+        let manifest = match fs::read(base_path.join(MANIFEST_FILE)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Manifest {
+                items: Some(0),
+                ..Manifest::default()
+            },
+            Err(e) => return Err(e.into()),
+        };
+        let state = Store::<FileClient>::new(&base_path.join(STATE_DIR).to_string_lossy());
+        let mut client = Self {
+            base_path,
+            manifest,
+            buffer: vec![],
+            row_group_rows: *PARQUET_ROW_GROUP_ROWS,
+            state,
+        };
+        client.recover()?;
+        Ok(client)
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.base_path.join(file)
+    }
+
+    fn spool_path(&self) -> PathBuf {
+        self.path(&format!("_spool-{:06}.jsonl", self.manifest.spool))
+    }
+
+    /// Remove the files a crash left out of the manifest, index the parts written
+    /// before the id indexes existed and reload the spool
+    fn recover(&mut self) -> Result<(), CrawlerError> {
+        let listed: HashSet<PathBuf> = self
+            .manifest
+            .parts
+            .iter()
+            .flat_map(|p| [Some(&p.file), p.index.as_ref()])
+            .flatten()
+            .map(|file| self.path(file))
+            .collect();
+        let spool = self.spool_path();
+        for entry in fs::read_dir(&self.base_path)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            if name.starts_with("_spool-") && path != spool {
+                fs::remove_file(&path)?;
+            } else if name == INDEX_DIR {
+                for index in fs::read_dir(&path)? {
+                    let index = index?.path();
+                    if !listed.contains(&index) {
+                        fs::remove_file(index)?;
+                    }
+                }
+            } else if name.starts_with("type=") {
+                for date in fs::read_dir(&path)? {
+                    for part in fs::read_dir(date?.path())? {
+                        let part = part?.path();
+                        if !listed.contains(&part) {
+                            fs::remove_file(part)?;
+                        }
+                    }
+                }
+            }
+        }
+        for position in 0..self.manifest.parts.len() {
+            if self.manifest.parts[position].index.is_none() {
+                let mut ids = BTreeSet::new();
+                let part = self.manifest.parts[position].clone();
+                self.for_each_record(
+                    |p| Ok(*p == part),
+                    |record| {
+                        ids.insert(record.item.id);
+                    },
+                )?;
+                let index = self.write_index(&part.file, ids)?;
+                self.manifest.parts[position].index = Some(index);
+                write_atomic(
+                    &self.path(MANIFEST_FILE),
+                    &serde_json::to_vec_pretty(&self.manifest)?,
+                )?;
+            }
+        }
+        if spool.exists() {
+            truncate_torn_tail(&spool)?;
+            let buffer = &mut self.buffer;
+            read_lines(&spool, FileCompression::None, |record: FileRecord, _| {
+                buffer.push(record)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Spool `records`, then write the row groups once the buffer is full
+    fn append_records(&mut self, records: Vec<FileRecord>) -> Result<(), CrawlerError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for record in &records {
+            lines.push_str(&(serde_json::to_string(record)? + "\n"));
+        }
+        append_synced(&self.spool_path(), &lines)?;
+        self.buffer.extend(records);
+        if self.buffer.len() >= self.row_group_rows {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered rows to one new part per partition and start a new spool
+    pub fn flush(&mut self) -> Result<(), CrawlerError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut partitions: BTreeMap<(String, String), Vec<&FileRecord>> = BTreeMap::new();
+        for record in &self.buffer {
+            partitions
+                .entry((record.item.tp.clone(), utc_date(record.item.time)))
+                .or_default()
+                .push(record);
+        }
+        let mut parts = vec![];
+        for ((tp, date), mut rows) in partitions {
+            // Stable, so the versions of an item stay in observation order
+            rows.sort_by_key(|r| r.item.id);
+            let dir = format!("type={}/date={}", tp, date);
+            fs::create_dir_all(self.path(&dir))?;
+            let file = format!(
+                "{}/part-{:06}.parquet",
+                dir,
+                self.manifest.parts.len() + parts.len() + 1
+            );
+            write_part(&self.path(&file), &rows)?;
+            let index = self.write_index(&file, rows.iter().map(|r| r.item.id).collect())?;
+            parts.push(Part {
+                file,
+                tp,
+                min_id: rows.first().unwrap().item.id,
+                max_id: rows.last().unwrap().item.id,
+                max_time: rows.iter().map(|r| r.item.time).max().unwrap(),
+                rows: rows.len() as u64,
+                index: Some(index),
+            });
+        }
+        let new_ids = self.buffered_new_ids()?;
+        if let Some(items) = &mut self.manifest.items {
+            *items += new_ids;
+        }
+        let spool = self.spool_path();
+        self.manifest.parts.extend(parts);
+        self.manifest.spool += 1;
+        write_atomic(
+            &self.path(MANIFEST_FILE),
+            &serde_json::to_vec_pretty(&self.manifest)?,
+        )?;
+        self.buffer.clear();
+        if spool.exists() {
+            fs::remove_file(spool)?;
+        }
+        sync_dir(&self.base_path)
+    }
+
+    /// Write the sorted `ids` of the part `file` to its index, returning the index file name
+    fn write_index(&self, file: &str, ids: BTreeSet<i64>) -> Result<String, CrawlerError> {
+        fs::create_dir_all(self.path(INDEX_DIR))?;
+        let name = Path::new(file)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let index = format!("{}/{}.ids.json", INDEX_DIR, name);
+        write_id_index(&self.path(&index), ids)?;
+        Ok(index)
+    }
+
+    /// Whether `part` may hold ids of `range`, asking its id index rather than its id bounds
+    fn holds(&self, part: &Part, range: &RangeInclusive<i64>) -> Result<bool, CrawlerError> {
+        Ok(part.overlaps(range)
+            && match &part.index {
+                Some(index) => index_holds(&self.path(index), range)?,
+                None => true,
+            })
+    }
+
+    /// Call `f` with the sorted ids of every part which may hold ids of `range`
+    fn for_each_part_ids(
+        &self,
+        range: &RangeInclusive<i64>,
+        mut f: impl FnMut(&[i64]),
+    ) -> Result<(), CrawlerError> {
+        for part in self.manifest.parts.iter().filter(|p| p.overlaps(range)) {
+            let ids = match &part.index {
+                Some(index) => read_id_index(&self.path(index))?,
+                None => {
+                    let mut ids = BTreeSet::new();
+                    self.for_each_record(
+                        |p| Ok(p == part),
+                        |record| {
+                            ids.insert(record.item.id);
+                        },
+                    )?;
+                    ids.into_iter().collect()
+                }
+            };
+            f(&ids);
+        }
+        Ok(())
+    }
+
+    /// Number of distinct ids of the buffer which no part holds
+    fn buffered_new_ids(&self) -> Result<u64, CrawlerError> {
+        let mut ids: BTreeSet<i64> = self.buffer.iter().map(|r| r.item.id).collect();
+        let (Some(first), Some(last)) = (ids.first().copied(), ids.last().copied()) else {
+            return Ok(0);
+        };
+        self.for_each_part_ids(&(first..=last), |part_ids| {
+            ids.retain(|id| part_ids.binary_search(id).is_err())
+        })?;
+        Ok(ids.len() as u64)
+    }
+
+    /// Number of distinct ids, counted from the id indexes once when the manifest doesn't know it
+    fn count_items(&mut self) -> Result<u64, CrawlerError> {
+        let buffered = self.buffered_new_ids()?;
+        if let Some(items) = self.manifest.items {
+            return Ok(items + buffered);
+        }
+        let items = count_ids(self)?;
+        self.manifest.items = Some(items - buffered);
+        write_atomic(
+            &self.path(MANIFEST_FILE),
+            &serde_json::to_vec_pretty(&self.manifest)?,
+        )?;
+        Ok(items)
+    }
+
+    /// Call `f` with every row of the parts matching `filter`, then of the buffer, in write order
+    fn for_each_record(
+        &self,
+        filter: impl Fn(&Part) -> Result<bool, CrawlerError>,
+        mut f: impl FnMut(FileRecord),
+    ) -> Result<(), CrawlerError> {
+        for part in &self.manifest.parts {
+            if !filter(part)? {
+                continue;
+            }
+            let reader = SerializedFileReader::new(File::open(self.path(&part.file))?)?;
+            for row in reader.into_iter() {
+                f(read_record(&row?, &part.tp)?);
+            }
+        }
+        self.buffer.iter().cloned().for_each(f);
+        Ok(())
+    }
+
+    /// The latest record of the items with ids in `range`, keeping only the `limit` lowest ids
+    fn latest_records(
+        &self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<BTreeMap<i64, FileRecord>, CrawlerError> {
+        let mut latest = BTreeMap::new();
+        self.for_each_record(
+            |p| self.holds(p, &range),
+            |record| {
+                if range.contains(&record.item.id) {
+                    latest.insert(record.item.id, record);
+                    if latest.len() > limit {
+                        latest.pop_last();
+                    }
+                }
+            },
+        )?;
+        Ok(latest)
+    }
+}
+
+impl IndexedRecords for ParquetClient {
+    /// Only reads the parts whose id index holds one of the `ids`, and the buffer
+    fn latest_of(&self, ids: &BTreeSet<i64>) -> Result<BTreeMap<i64, FileRecord>, CrawlerError> {
+        let mut latest = BTreeMap::new();
+        let (Some(first), Some(last)) = (ids.first(), ids.last()) else {
+            return Ok(latest);
+        };
+        let range = *first..=*last;
+        self.for_each_record(
+            |p| {
+                Ok(p.overlaps(&range)
+                    && match &p.index {
+                        Some(index) => {
+                            let held = read_id_index(&self.path(index))?;
+                            ids.iter().any(|id| held.binary_search(id).is_ok())
+                        }
+                        None => true,
+                    })
+            },
+            |record| {
+                if ids.contains(&record.item.id) {
+                    latest.insert(record.item.id, record);
+                }
+            },
+        )?;
+        Ok(latest)
+    }
+
+    /// The parts are answered from their id index, then the ids of the buffer follow
+    fn for_each_file_ids(
+        &self,
+        range: &RangeInclusive<i64>,
+        mut f: impl FnMut(&[i64]),
+    ) -> Result<(), CrawlerError> {
+        self.for_each_part_ids(range, &mut f)?;
+        let buffered: BTreeSet<i64> = self
+            .buffer
+            .iter()
+            .map(|r| r.item.id)
+            .filter(|id| range.contains(id))
+            .collect();
+        if !buffered.is_empty() {
+            f(&buffered.into_iter().collect::<Vec<i64>>());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ParquetClient {
+    /// Leave a readable dataset behind, the spool keeps the rows if this fails
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Failed to flush the parquet buffer: {}", e);
+        }
+    }
+}
+
+/// Write `rows` as a single row group
+fn write_part(path: &Path, rows: &[&FileRecord]) -> Result<(), CrawlerError> {
+    let schema = Arc::new(parse_message_type(ITEM_SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+    // Hidden until complete, so dataset readers never see a partial file
+    let tmp = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let mut writer = SerializedFileWriter::new(File::create(&tmp)?, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    let longs = |f: fn(&FileRecord) -> i64| rows.iter().map(|r| f(r)).collect::<Vec<i64>>();
+    let bools = |f: fn(&Item) -> bool| rows.iter().map(|r| f(&r.item)).collect::<Vec<bool>>();
+    let strings = |f: fn(&Item) -> &str| {
+        rows.iter()
+            .map(|r| ByteArray::from(f(&r.item)))
+            .collect::<Vec<ByteArray>>()
+    };
+    let mut column = 0;
+    while let Some(mut writer) = row_group.next_column()? {
+        match column {
+            0 => {
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&longs(|r| r.observed_at), None, None)?;
+            }
+            1 => {
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&longs(|r| r.item.id), None, None)?;
+            }
+            2 => {
+                writer
+                    .typed::<BoolType>()
+                    .write_batch(&bools(|i| i.deleted), None, None)?;
+            }
+            3 => {
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&strings(|i| &i.who), None, None)?;
+            }
+            4 => {
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&longs(|r| r.item.time), None, None)?;
+            }
+            5 => {
+                writer
+                    .typed::<BoolType>()
+                    .write_batch(&bools(|i| i.dead), None, None)?;
+            }
+            6 => {
+                // An empty list is a single level-0 slot, every kid a level-1 value
+                let (mut values, mut definitions, mut repetitions) = (vec![], vec![], vec![]);
+                for row in rows {
+                    if row.item.kids.is_empty() {
+                        definitions.push(0);
+                        repetitions.push(0);
+                    }
+                    for (i, kid) in row.item.kids.iter().enumerate() {
+                        values.push(*kid);
+                        definitions.push(1);
+                        repetitions.push(if i == 0 { 0 } else { 1 });
+                    }
+                }
+                writer.typed::<Int64Type>().write_batch(
+                    &values,
+                    Some(&definitions),
+                    Some(&repetitions),
+                )?;
+            }
+            7 => {
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&strings(|i| &i.title), None, None)?;
+            }
+            8 => {
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&longs(|r| r.item.score), None, None)?;
+            }
+            9 => {
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&strings(|i| &i.text), None, None)?;
+            }
+            10 => {
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&strings(|i| &i.url), None, None)?;
+            }
+            11 => {
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&longs(|r| r.item.parent), None, None)?;
+            }
+            _ => {
+                writer.typed::<Int64Type>().write_batch(
+                    &longs(|r| r.item.descendants),
+                    None,
+                    None,
+                )?;
+            }
+        }
+        writer.close()?;
+        column += 1;
+    }
+    row_group.close()?;
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Read a row written by `write_part` back, `tp` coming from the partition
+fn read_record(row: &Row, tp: &str) -> Result<FileRecord, CrawlerError> {
+    let kids = row
+        .get_list(6)?
+        .elements()
+        .iter()
+        .filter_map(|f| match f {
+            Field::Long(kid) => Some(*kid),
+            _ => None,
+        })
+        .collect();
+    Ok(FileRecord {
+        observed_at: row.get_long(0)?,
+        item: Item {
+            id: row.get_long(1)?,
+            deleted: row.get_bool(2)?,
+            tp: tp.to_string(),
+            who: row.get_string(3)?.clone(),
+            time: row.get_long(4)?,
+            dead: row.get_bool(5)?,
+            kids,
+            title: row.get_string(7)?.clone(),
+            score: row.get_long(8)?,
+            text: row.get_string(9)?.clone(),
+            url: row.get_string(10)?.clone(),
+            parent: row.get_long(11)?,
+            descendants: row.get_long(12)?,
+        },
+    })
+}
+
+/// `yyyy-mm-dd` of a unix timestamp in UTC
+fn utc_date(time: i64) -> String {
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let z = time.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Implement the Store with ParquetClient
+impl Store<ParquetClient> {
+    pub fn new(store_path: &str) -> Self {
+        Self {
+            backend_client: ParquetClient::open(store_path).unwrap(),
+        }
+    }
+}
+// Implement trait StoreItem
+impl GenericStoreItem for Store<ParquetClient> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        let client = &self.backend_client;
+        Ok(client
+            .manifest
+            .parts
+            .iter()
+            .map(|p| p.max_id)
+            .chain(client.buffer.iter().map(|r| r.item.id))
+            .max()
+            .unwrap_or(0))
+    }
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.upsert_item(item)?;
+        Ok(true)
+    }
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        self.record_version(&item, now())?;
+        Ok(())
+    }
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        let mut latest = self.backend_client.latest_records(item_id..=item_id, 1)?;
+        Ok(latest.remove(&item_id).map(|r| r.item))
+    }
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        let latest = self.backend_client.latest_records(range, limit)?;
+        Ok(latest.into_values().map(|r| r.item).collect())
+    }
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        let count = items.len();
+        let (records, _) = new_versions(self, items, now())?;
+        self.backend_client.append_records(records)?;
+        Ok(count)
+    }
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        contains_ids(&self.backend_client, ids)
+    }
+    fn missing_in_range(&mut self, range: RangeInclusive<i64>) -> Result<Vec<i64>, CrawlerError> {
+        missing_ids(&self.backend_client, range)
+    }
+    fn count(&mut self) -> Result<u64, CrawlerError> {
+        self.backend_client.count_items()
+    }
+}
+
+// The parts and the spool are the version history
+impl GenericItemHistory for Store<ParquetClient> {
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError> {
+        let (records, _) = new_versions(self, vec![item.clone()], observed_at)?;
+        let recorded = !records.is_empty();
+        self.backend_client.append_records(records)?;
+        Ok(recorded)
+    }
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        let range = item_id..=item_id;
+        let mut versions = vec![];
+        let client = &self.backend_client;
+        client.for_each_record(
+            |p| client.holds(p, &range),
+            |record| {
+                if record.item.id == item_id {
+                    versions.push(ItemVersion {
+                        observed_at: record.observed_at,
+                        item: record.item,
+                    });
+                }
+            },
+        )?;
+        versions.sort_by_key(|v| v.observed_at);
+        Ok(versions)
+    }
+}
+
+impl GenericSnapshotStore for Store<ParquetClient> {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        self.backend_client.state.save_snapshot(snapshot)
+    }
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        let mut stories = BTreeMap::new();
+        self.backend_client.for_each_record(
+            |p| Ok(p.tp == "story" && p.max_time >= since),
+            |record| {
+                stories.insert(record.item.id, record.item);
+            },
+        )?;
+        let last_observed = self.backend_client.state.backend_client.last_observed()?;
+        Ok(stories
+            .into_values()
+            .filter(|item| item.tp == "story" && item.time >= since)
+            .map(|item| RefreshCandidate {
+                item_id: item.id,
+                time: item.time,
+                last_observed_at: last_observed.get(&item.id).copied(),
+            })
+            .collect())
+    }
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        self.backend_client.state.get_snapshots(item_id)
+    }
+}
+
+// The capabilities below are kept by the file store under `_state`
+impl GenericCheckpoint for Store<ParquetClient> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        self.backend_client.state.get_checkpoint(name)
+    }
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        self.backend_client.state.save_checkpoint(name, value)
+    }
+}
+
+impl GenericRetryQueue for Store<ParquetClient> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        self.backend_client.state.get_retry(item_id)
+    }
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        self.backend_client.state.save_retry(entry)
+    }
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        self.backend_client.state.remove_retry(item_id)
+    }
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        self.backend_client.state.due_retries(now, limit)
+    }
+}
+
+impl GenericRankingStore for Store<ParquetClient> {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        self.backend_client.state.save_ranking(snapshot)
+    }
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        self.backend_client.state.ranking_at(list, at)
+    }
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        self.backend_client.state.ranking_history(list, item_id)
+    }
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        self.backend_client.state.ranking_observations(list)
+    }
+}
+
+impl GenericTombstoneStore for Store<ParquetClient> {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        self.backend_client.state.get_tombstone(item_id)
+    }
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        self.backend_client.state.save_tombstone(tombstone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+    use crate::ext::parquet::{utc_date, ParquetClient, MANIFEST_FILE};
+    use crate::history::GenericItemHistory;
+    use crate::item::Item;
+    use crate::store::Store;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    fn mock_parquet_store(name: &str) -> (TempDir, Store<ParquetClient>) {
+        let dir = TempDir::new(&format!("parquet-{}", name));
+        let store = Store::<ParquetClient>::new(&dir.uri());
+        (dir, store)
+    }
+
+    fn item(id: i64, tp: &str, time: i64) -> Item {
+        let mut item = Item::parse(
+            id,
+            &format!(
+                r#"{{"id":{},"type":"{}","by":"pg","time":{}}}"#,
+                id, tp, time
+            ),
+        )
+        .unwrap();
+        item.kids = (0..id % 3).map(|k| id * 10 + k).collect();
+        item.title = format!("title {}", id);
+        item
+    }
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!(utc_date(0), "1970-01-01");
+        assert_eq!(utc_date(951_782_400), "2000-02-29");
+        assert_eq!(utc_date(1_760_832_000), "2025-10-19");
+    }
+
+    #[test]
+    fn test_store_items_parquet() {
+        let (dir, mut store_client) = mock_parquet_store("items");
+        store_client.backend_client.row_group_rows = 4;
+        let items = vec![
+            item(1, "story", 0),
+            item(2, "comment", 0),
+            item(3, "comment", 86400),
+            item(4, "story", 86400),
+            item(5, "story", 86400),
+        ];
+        store_client.store_items(items.clone()).unwrap();
+        // Buffered rows are spooled until the next row group, and survive a restart
+        store_client.store_item(item(6, "job", 0)).unwrap();
+        store_client.save_checkpoint("sync", 6).unwrap();
+        std::mem::forget(store_client);
+
+        let mut store_client = Store::<ParquetClient>::new(&dir.uri());
+        assert_eq!(store_client.backend_client.buffer.len(), 1);
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), Some(6));
+        assert_eq!(store_client.get_last_item().unwrap(), 6);
+        for file in [
+            "type=story/date=1970-01-01/part-000003.parquet",
+            "type=comment/date=1970-01-02/part-000002.parquet",
+        ] {
+            assert!(dir.path().join(file).exists(), "{}", file);
+        }
+        assert_eq!(store_client.get_items_range(1..=5, 10).unwrap(), items);
+        let mut edited = item(4, "story", 86400);
+        edited.score = 10;
+        store_client.store_item(edited.clone()).unwrap();
+        drop(store_client);
+
+        let mut store_client = Store::<ParquetClient>::new(&dir.uri());
+        assert!(store_client.backend_client.buffer.is_empty());
+        assert_eq!(store_client.get_item(4).unwrap(), Some(edited));
+        assert_eq!(store_client.get_item(6).unwrap(), Some(item(6, "job", 0)));
+        assert_eq!(store_client.item_versions(4).unwrap().len(), 2);
+        assert_eq!(store_client.missing_in_range(5..=7).unwrap(), vec![7]);
+    }
+    #[test]
+    fn test_parts_skipped_by_id_index() {
+        let (dir, mut store_client) = mock_parquet_store("index");
+        store_client.backend_client.row_group_rows = 4;
+        store_client
+            .store_items((1..=4).map(|id| item(id, "story", 0)).collect())
+            .unwrap();
+        // Updates of the first and last stories, a part spanning all their ids
+        let mut updates = vec![item(1, "story", 0), item(4, "story", 0)];
+        updates.iter_mut().for_each(|i| i.score = 7);
+        store_client.store_items(updates.clone()).unwrap();
+        store_client.backend_client.flush().unwrap();
+        assert!(dir.path().join("_index/part-000002.ids.json").exists());
+
+        // The id index of the updates tells they hold neither 2 nor 3, so the part is never read
+        fs::write(
+            dir.path()
+                .join("type=story/date=1970-01-01/part-000002.parquet"),
+            b"garbage",
+        )
+        .unwrap();
+        drop(store_client);
+        let mut store_client = Store::<ParquetClient>::new(&dir.uri());
+        assert_eq!(
+            store_client.get_items_range(2..=3, 10).unwrap(),
+            vec![item(2, "story", 0), item(3, "story", 0)]
+        );
+        assert_eq!(store_client.item_versions(3).unwrap().len(), 1);
+        assert!(store_client.get_item(4).is_err());
+    }
+
+    #[test]
+    fn test_membership_from_id_index() {
+        let (dir, mut store_client) = mock_parquet_store("membership");
+        store_client.backend_client.row_group_rows = 4;
+        store_client
+            .store_items((1..=4).map(|id| item(id, "story", 0)).collect())
+            .unwrap();
+        store_client.store_item(item(5, "story", 0)).unwrap();
+        store_client.store_item(item(2, "story", 0)).unwrap();
+        std::mem::forget(store_client);
+
+        // Membership and counts come from the id indexes and the spool, never from the parts
+        fs::write(
+            dir.path()
+                .join("type=story/date=1970-01-01/part-000001.parquet"),
+            b"garbage",
+        )
+        .unwrap();
+        let mut store_client = Store::<ParquetClient>::new(&dir.uri());
+        assert_eq!(
+            store_client.contains(&[2, 5, 9]).unwrap(),
+            [2, 5].into_iter().collect()
+        );
+        assert_eq!(store_client.missing_in_range(0..=6).unwrap(), vec![0, 6]);
+        assert_eq!(store_client.count().unwrap(), 5);
+        std::mem::forget(store_client);
+
+        // A manifest written before the count was kept gets it from the id indexes
+        let path = dir.path().join(MANIFEST_FILE);
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        manifest.as_object_mut().unwrap().remove("items");
+        fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+        let mut store_client = Store::<ParquetClient>::new(&dir.uri());
+        assert_eq!(store_client.count().unwrap(), 5);
+        assert_eq!(store_client.backend_client.manifest.items, Some(4));
+    }
+}
@@ -1,65 +1,1044 @@
-use postgres::NoTls;
-use crate::crawler::{ItemsCrawler, GenericStoreItem};
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::history::{GenericItemHistory, ItemVersion};
 use crate::item::Item;
+use crate::migrate::{AppliedMigration, GenericMigrations, Migration};
+use crate::outbox::{ChangeType, GenericOutbox, OutboxEntry};
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::replicate::{Change, GenericChangeFeed};
+use crate::retry::{GenericRetryQueue, RetryEntry, RetryState};
+use crate::search::{GenericSearch, SearchHit, SearchQuery, HIGHLIGHT_END, HIGHLIGHT_START};
 use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use crate::utils::now;
+use postgres::{GenericClient, NoTls, Row};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Migrations of the postgres schema, same layout as the sqlite store
+/// with native postgres types, see `GenericMigrations`
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        sql: r###"
+            CREATE TABLE IF NOT EXISTS items (
+              id bigint NOT NULL PRIMARY KEY,
+              deleted boolean DEFAULT false,
+              type varchar(16) DEFAULT NULL,
+              who varchar(255) DEFAULT NULL,
+              time bigint DEFAULT NULL,
+              dead boolean DEFAULT false,
+              kids text DEFAULT NULL,
+              title text DEFAULT NULL,
+              content text DEFAULT NULL,
+              score bigint DEFAULT NULL,
+              url text DEFAULT NULL,
+              parent bigint DEFAULT NULL
+            );
+            CREATE TABLE IF NOT EXISTS retry_queue (
+              id bigint NOT NULL PRIMARY KEY,
+              attempts bigint NOT NULL DEFAULT 0,
+              last_error text DEFAULT NULL,
+              next_attempt_at bigint NOT NULL,
+              state varchar(16) NOT NULL DEFAULT 'pending'
+            );
+            CREATE TABLE IF NOT EXISTS checkpoints (
+              name varchar(64) NOT NULL PRIMARY KEY,
+              value bigint NOT NULL,
+              updated_at bigint NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS item_snapshots (
+              id bigint NOT NULL,
+              observed_at bigint NOT NULL,
+              score bigint DEFAULT NULL,
+              descendants bigint DEFAULT NULL,
+              PRIMARY KEY (id, observed_at)
+            );
+            CREATE TABLE IF NOT EXISTS story_rankings (
+              list varchar(16) NOT NULL,
+              observed_at bigint NOT NULL,
+              position bigint NOT NULL,
+              id bigint NOT NULL,
+              PRIMARY KEY (list, observed_at, position)
+            );
+            CREATE TABLE IF NOT EXISTS item_versions (
+              seq bigserial PRIMARY KEY,
+              id bigint NOT NULL,
+              observed_at bigint NOT NULL,
+              data text NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS item_versions_id_observed_at
+              ON item_versions (id, observed_at);
+            CREATE TABLE IF NOT EXISTS item_tombstones (
+              id bigint NOT NULL PRIMARY KEY,
+              deleted_at bigint DEFAULT NULL,
+              dead_at bigint DEFAULT NULL,
+              who varchar(255) DEFAULT NULL,
+              title text DEFAULT NULL,
+              content text DEFAULT NULL,
+              url text DEFAULT NULL
+            );"###,
+    },
+    Migration {
+        version: 2,
+        name: "item lookup indexes",
+        sql: r###"
+            CREATE INDEX IF NOT EXISTS items_parent ON items (parent);
+            CREATE INDEX IF NOT EXISTS items_who ON items (who);
+            CREATE INDEX IF NOT EXISTS items_time ON items (time);
+            CREATE INDEX IF NOT EXISTS items_type ON items (type);"###,
+    },
+    Migration {
+        version: 3,
+        name: "item change outbox",
+        sql: r###"
+            CREATE TABLE IF NOT EXISTS item_outbox (
+              seq bigserial PRIMARY KEY,
+              id bigint NOT NULL,
+              change_type text NOT NULL,
+              fields text NOT NULL,
+              changed_at bigint NOT NULL
+            );"###,
+    },
+    Migration {
+        version: 4,
+        name: "full-text search",
+        // The generated column follows every write, adding it rewrites `items` once
+        sql: r###"
+            ALTER TABLE items ADD COLUMN IF NOT EXISTS search tsvector GENERATED ALWAYS AS (
+              setweight(to_tsvector('english', coalesce(title, '')), 'A') ||
+              setweight(to_tsvector('english', coalesce(content, '')), 'B')) STORED;
+            CREATE INDEX IF NOT EXISTS items_search ON items USING GIN (search);"###,
+    },
+];
+
+/// Key of the advisory lock serializing the migrations of concurrent crawlers
+const MIGRATION_LOCK: i64 = 0x0063_7261_776c_6572;
+
+const UPSERT_ITEM_COLUMNS: &str = "\
+    deleted = EXCLUDED.deleted, type = EXCLUDED.type, who = EXCLUDED.who, \
+    time = EXCLUDED.time, dead = EXCLUDED.dead, kids = EXCLUDED.kids, \
+    title = EXCLUDED.title, content = EXCLUDED.content, score = EXCLUDED.score, \
+    url = EXCLUDED.url, parent = EXCLUDED.parent";
+
+/// Escape a value for the text format of `COPY`
+fn copy_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Format an item as one line of `COPY items_staging FROM STDIN`, the columns of `items`
+/// followed by the item as JSON and its `ordinal` in the batch
+fn copy_row(item: &Item, ordinal: usize) -> Result<String, CrawlerError> {
+    let fmt_bool = |the_bool: bool| if the_bool { "t" } else { "f" };
+    Ok(format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        item.id,
+        fmt_bool(item.deleted),
+        copy_text(&item.tp),
+        copy_text(&item.who),
+        item.time,
+        fmt_bool(item.dead),
+        copy_text(&format!("{:?}", item.kids)),
+        copy_text(&item.title),
+        copy_text(&item.text),
+        item.score,
+        copy_text(&item.url),
+        item.parent,
+        copy_text(&serde_json::to_string(item)?),
+        ordinal
+    ))
+}
+
+fn upsert_tombstone(
+    client: &mut impl GenericClient,
+    tombstone: &Tombstone,
+) -> Result<(), CrawlerError> {
+    client.execute(
+        "INSERT INTO item_tombstones VALUES ($1, $2, $3, $4, $5, $6, $7) \
+        ON CONFLICT (id) DO UPDATE SET deleted_at = EXCLUDED.deleted_at, dead_at = EXCLUDED.dead_at, \
+        who = EXCLUDED.who, title = EXCLUDED.title, content = EXCLUDED.content, url = EXCLUDED.url",
+        &[&tombstone.item_id, &tombstone.deleted_at, &tombstone.dead_at,
+            &tombstone.who, &tombstone.title, &tombstone.text, &tombstone.url])?;
+    Ok(())
+}
 
 // Implement Store for postgres
 impl Store<postgres::Client> {
+    /// Connect and apply the pending migrations
     pub fn new(uri: &str) -> Self {
-        Self {
-            backend_client: postgres::Client::connect(uri, NoTls).unwrap()
-        }
+        let mut store = Self::open(uri).unwrap();
+        store.migrate_up().unwrap();
+        store
+    }
+
+    /// Connect to the database as it is, without migrating it
+    pub fn open(uri: &str) -> Result<Self, CrawlerError> {
+        Ok(Self {
+            backend_client: postgres::Client::connect(uri, NoTls)?,
+        })
     }
 }
 
+/// The latest recorded version of an item
+fn latest_version(
+    client: &mut impl GenericClient,
+    item_id: i64,
+) -> Result<Option<String>, CrawlerError> {
+    let row = client.query_opt(
+        "SELECT data FROM item_versions WHERE id = $1 ORDER BY observed_at DESC, seq DESC LIMIT 1",
+        &[&item_id],
+    )?;
+    Ok(row.map(|r| r.get("data")))
+}
+
+fn read_tombstone(
+    client: &mut impl GenericClient,
+    item_id: i64,
+) -> Result<Option<Tombstone>, CrawlerError> {
+    let row = client.query_opt("SELECT * FROM item_tombstones WHERE id = $1", &[&item_id])?;
+    Ok(row.map(|r| Tombstone {
+        item_id,
+        deleted_at: r.get("deleted_at"),
+        dead_at: r.get("dead_at"),
+        who: r.get::<_, Option<String>>("who").unwrap_or_default(),
+        title: r.get::<_, Option<String>>("title").unwrap_or_default(),
+        text: r.get::<_, Option<String>>("content").unwrap_or_default(),
+        url: r.get::<_, Option<String>>("url").unwrap_or_default(),
+    }))
+}
+
+/// The tombstone to save when `item` is observed, if any
+fn track_tombstone(
+    client: &mut impl GenericClient,
+    item: &Item,
+    previous: Option<&str>,
+    at: i64,
+) -> Result<Option<Tombstone>, CrawlerError> {
+    let previous = previous.map(|p| Item::parse(item.id, p)).transpose()?;
+    let existing = read_tombstone(client, item.id)?;
+    Ok(Tombstone::track(existing, previous.as_ref(), item, at))
+}
+
+/// Append `item` to its history with its tombstone and outbox entry, unless it equals the latest
+/// version. The caller runs it in the transaction of the write of the item
+fn record_version_in(
+    client: &mut impl GenericClient,
+    item: &Item,
+    observed_at: i64,
+) -> Result<bool, CrawlerError> {
+    let data = serde_json::to_string(item)?;
+    let previous = latest_version(client, item.id)?;
+    if previous.as_ref() == Some(&data) {
+        return Ok(false);
+    }
+    if let Some(tombstone) = track_tombstone(client, item, previous.as_deref(), observed_at)? {
+        upsert_tombstone(client, &tombstone)?;
+    }
+    let previous = previous.map(|p| Item::parse(item.id, &p)).transpose()?;
+    client.execute(
+        "INSERT INTO item_versions (id, observed_at, data) VALUES ($1, $2, $3)",
+        &[&item.id, &observed_at, &data],
+    )?;
+    append_outbox(
+        client,
+        OutboxEntry::track(previous.as_ref(), item, observed_at).as_slice(),
+    )?;
+    Ok(true)
+}
+
+/// Append the outbox `entries` in their order, the sequences are assigned by `item_outbox`
+fn append_outbox(
+    client: &mut impl GenericClient,
+    entries: &[OutboxEntry],
+) -> Result<(), CrawlerError> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i64> = entries.iter().map(|e| e.item_id).collect();
+    let changes: Vec<&str> = entries.iter().map(|e| e.change.as_str()).collect();
+    let fields = entries
+        .iter()
+        .map(|e| serde_json::to_string(&e.fields))
+        .collect::<Result<Vec<String>, _>>()?;
+    let changed_at: Vec<i64> = entries.iter().map(|e| e.changed_at).collect();
+    client.execute(
+        "INSERT INTO item_outbox (id, change_type, fields, changed_at) \
+        SELECT id, c, f, t FROM UNNEST($1::bigint[], $2::text[], $3::text[], $4::bigint[]) \
+        WITH ORDINALITY AS e(id, c, f, t, n) ORDER BY n",
+        &[&ids, &changes, &fields, &changed_at],
+    )?;
+    Ok(())
+}
+
 // Implement item data to Postgres
-impl GenericStoreItem<Store<postgres::Client>> for Store<postgres::Client> {
-    fn get_last_item(&mut self) -> Result<i64, ()> {
-        println!("Getting last item from store...");
-        let row = self.backend_client.query_one("select max(id) from items", &[]);
-        match row {
-            Ok(row) => Ok(row.get(0)),
-            Err(_) => Err(())
+impl GenericStoreItem for Store<postgres::Client> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        eprintln!("Getting last item from store...");
+        let row = self
+            .backend_client
+            .query_one("select max(id) from items", &[])?;
+        Ok(row.get::<_, Option<i64>>(0).unwrap_or(0))
+    }
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.upsert_item(item)?;
+        Ok(true)
+    }
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        let sql = format!(
+            "INSERT INTO items VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) \
+            ON CONFLICT (id) DO UPDATE SET {}",
+            UPSERT_ITEM_COLUMNS
+        );
+        // The item, its version and its outbox entry are committed together
+        let mut tx = self.backend_client.transaction()?;
+        tx.execute(
+            &sql,
+            &[
+                &item.id,
+                &item.deleted,
+                &item.tp,
+                &item.who,
+                &item.time,
+                &item.dead,
+                &format!("{:?}", item.kids),
+                &item.title,
+                &item.text,
+                &item.score,
+                &item.url,
+                &item.parent,
+            ],
+        )?;
+        record_version_in(&mut tx, &item, now())?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        let row = self
+            .backend_client
+            .query_opt("SELECT * FROM items WHERE id = $1", &[&item_id])?;
+        Ok(row.map(|r| read_item(&r)))
+    }
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT * FROM items WHERE id BETWEEN $1 AND $2 ORDER BY id LIMIT $3",
+            &[range.start(), range.end(), &(limit as i64)],
+        )?;
+        Ok(rows.iter().map(read_item).collect())
+    }
+    /// Bulk load the items with `COPY` into a staging table, then upsert them
+    /// into `items` and append their new versions, all in one transaction
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        if items.is_empty() {
+            return Ok(0);
+        }
+        let observed_at = now();
+        let mut tx = self.backend_client.transaction()?;
+        let ids: Vec<i64> = items.iter().map(|i| i.id).collect();
+        let previous: HashMap<i64, String> = tx
+            .query(
+                "SELECT DISTINCT ON (id) id, data FROM item_versions WHERE id = ANY($1) \
+            ORDER BY id, observed_at DESC, seq DESC",
+                &[&ids],
+            )?
+            .iter()
+            .map(|r| (r.get("id"), r.get("data")))
+            .collect();
+        // The last occurrence of an id in the batch wins, like a sequence of upserts
+        let latest: BTreeMap<i64, &Item> = items.iter().map(|i| (i.id, i)).collect();
+        // Only the deleted or dead items can change their tombstone
+        let mut tombstones = vec![];
+        for item in latest.values().filter(|i| i.deleted || i.dead) {
+            if let Some(tombstone) = track_tombstone(
+                &mut tx,
+                item,
+                previous.get(&item.id).map(String::as_str),
+                observed_at,
+            )? {
+                tombstones.push(tombstone);
+            }
         }
+        let mut entries = vec![];
+        for item in latest.into_values() {
+            let before = previous
+                .get(&item.id)
+                .map(|p| Item::parse(item.id, p))
+                .transpose()?;
+            entries.extend(OutboxEntry::track(before.as_ref(), item, observed_at));
+        }
+
+        tx.batch_execute(
+            "CREATE TEMP TABLE items_staging (LIKE items INCLUDING DEFAULTS, data text, ordinal bigint) ON COMMIT DROP")?;
+        let mut writer = tx.copy_in(
+            "COPY items_staging (id, deleted, type, who, time, dead, kids, title, content, score, url, parent, data, ordinal) FROM STDIN")?;
+        for (ordinal, item) in items.iter().enumerate() {
+            writer.write_all(copy_row(item, ordinal)?.as_bytes())?;
+        }
+        writer.finish()?;
+        tx.batch_execute(&format!(
+            "INSERT INTO items \
+            SELECT DISTINCT ON (id) id, deleted, type, who, time, dead, kids, title, content, score, url, parent \
+            FROM items_staging ORDER BY id, ordinal DESC \
+            ON CONFLICT (id) DO UPDATE SET {}", UPSERT_ITEM_COLUMNS))?;
+        tx.execute(
+            "INSERT INTO item_versions (id, observed_at, data) \
+            SELECT s.id, $1, s.data FROM (SELECT DISTINCT ON (id) id, data FROM items_staging ORDER BY id, ordinal DESC) s \
+            WHERE s.data IS DISTINCT FROM ( \
+                SELECT v.data FROM item_versions v WHERE v.id = s.id \
+                ORDER BY v.observed_at DESC, v.seq DESC LIMIT 1)",
+            &[&observed_at])?;
+        for tombstone in &tombstones {
+            upsert_tombstone(&mut tx, tombstone)?;
+        }
+        append_outbox(&mut tx, &entries)?;
+        tx.commit()?;
+        Ok(items.len())
+    }
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        let rows = self
+            .backend_client
+            .query("SELECT id FROM items WHERE id = ANY($1)", &[&ids])?;
+        Ok(rows.iter().map(|r| r.get("id")).collect())
+    }
+    fn missing_in_range(&mut self, range: RangeInclusive<i64>) -> Result<Vec<i64>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT g.id FROM generate_series($1::bigint, $2::bigint) AS g(id) \
+            WHERE NOT EXISTS (SELECT 1 FROM items i WHERE i.id = g.id) ORDER BY g.id",
+            &[range.start(), range.end()],
+        )?;
+        Ok(rows.iter().map(|r| r.get("id")).collect())
+    }
+    fn count(&mut self) -> Result<u64, CrawlerError> {
+        let row = self
+            .backend_client
+            .query_one("SELECT count(*) FROM items", &[])?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+}
+
+/// Read a row of the `items` table
+fn read_item(row: &Row) -> Item {
+    let text = |column: &str| row.get::<_, Option<String>>(column).unwrap_or_default();
+    Item {
+        id: row.get("id"),
+        deleted: row.get::<_, Option<bool>>("deleted").unwrap_or_default(),
+        tp: text("type"),
+        who: text("who"),
+        time: row.get::<_, Option<i64>>("time").unwrap_or_default(),
+        dead: row.get::<_, Option<bool>>("dead").unwrap_or_default(),
+        kids: Item::parse_kids(&text("kids")),
+        title: text("title"),
+        score: row.get::<_, Option<i64>>("score").unwrap_or_default(),
+        text: text("content"),
+        url: text("url"),
+        parent: row.get::<_, Option<i64>>("parent").unwrap_or_default(),
+        descendants: 0,
+    }
+}
+
+// Implement the retry queue in the `retry_queue` table
+impl GenericRetryQueue for Store<postgres::Client> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        let row = self
+            .backend_client
+            .query_opt("SELECT * FROM retry_queue WHERE id = $1", &[&item_id])?;
+        row.map(|r| read_retry_entry(&r)).transpose()
+    }
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        self.backend_client.execute(
+            "INSERT INTO retry_queue VALUES ($1, $2, $3, $4, $5) \
+            ON CONFLICT (id) DO UPDATE SET attempts = EXCLUDED.attempts, last_error = EXCLUDED.last_error, \
+            next_attempt_at = EXCLUDED.next_attempt_at, state = EXCLUDED.state",
+            &[&entry.item_id, &entry.attempts, &entry.last_error, &entry.next_attempt_at, &entry.state.as_str()])?;
+        Ok(())
+    }
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        self.backend_client
+            .execute("DELETE FROM retry_queue WHERE id = $1", &[&item_id])?;
+        Ok(())
+    }
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT * FROM retry_queue WHERE state = $1 AND next_attempt_at <= $2 \
+            ORDER BY next_attempt_at LIMIT $3",
+            &[&RetryState::Pending.as_str(), &now, &(limit as i64)],
+        )?;
+        rows.iter().map(read_retry_entry).collect()
+    }
+}
+
+/// Read a row of the `retry_queue` table
+fn read_retry_entry(row: &Row) -> Result<RetryEntry, CrawlerError> {
+    let state: String = row.get("state");
+    Ok(RetryEntry {
+        item_id: row.get("id"),
+        attempts: row.get("attempts"),
+        last_error: row
+            .get::<_, Option<String>>("last_error")
+            .unwrap_or_default(),
+        next_attempt_at: row.get("next_attempt_at"),
+        state: RetryState::parse(&state).ok_or_else(|| CrawlerError::Store {
+            reason: format!("unknown retry state {state:?}"),
+        })?,
+    })
+}
+
+// Implement the checkpoints in the `checkpoints` table
+impl GenericCheckpoint for Store<postgres::Client> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        let row = self
+            .backend_client
+            .query_opt("SELECT value FROM checkpoints WHERE name = $1", &[&name])?;
+        Ok(row.map(|r| r.get("value")))
     }
-    fn store_item(&mut self, item: Item) -> Result<bool, ()> {
-        let _ = item;
-        todo!()
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        self.backend_client.execute(
+            "INSERT INTO checkpoints VALUES ($1, $2, $3) \
+            ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value, updated_at = EXCLUDED.updated_at",
+            &[&name, &value, &now()])?;
+        Ok(())
     }
 }
 
-// This implementation will override the GenericCrawlerFlow above
-impl ItemsCrawler<Store<postgres::Client>> {
-    /// Run the crawler which get latest item in Hub and store to Postgres
-    pub fn run_one(&mut self) -> Result<String, ()> {
-        let latest_item_id = self.fetch_latest_item().unwrap();
-        let last_item_id = self.client.get_last_item();
-        println!("Execute in postgres");
-        println!("Last item: \t\t{:?}\nLatest item: \t{:?}", last_item_id, latest_item_id);
-        Ok("Generic!".to_string())
+// Implement the snapshots in the `item_snapshots` table
+impl GenericSnapshotStore for Store<postgres::Client> {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        self.backend_client.execute(
+            "INSERT INTO item_snapshots VALUES ($1, $2, $3, $4) \
+            ON CONFLICT (id, observed_at) DO UPDATE SET score = EXCLUDED.score, descendants = EXCLUDED.descendants",
+            &[&snapshot.item_id, &snapshot.observed_at, &snapshot.score, &snapshot.descendants])?;
+        Ok(())
+    }
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT i.id, i.time, max(s.observed_at) AS last_observed_at \
+            FROM items i LEFT JOIN item_snapshots s ON s.id = i.id \
+            WHERE i.type = 'story' AND i.time >= $1 \
+            GROUP BY i.id, i.time",
+            &[&since],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|r| RefreshCandidate {
+                item_id: r.get("id"),
+                time: r.get("time"),
+                last_observed_at: r.get("last_observed_at"),
+            })
+            .collect())
+    }
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT * FROM item_snapshots WHERE id = $1 ORDER BY observed_at",
+            &[&item_id],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|r| ItemSnapshot {
+                item_id: r.get("id"),
+                observed_at: r.get("observed_at"),
+                score: r.get("score"),
+                descendants: r.get("descendants"),
+            })
+            .collect())
+    }
+}
+
+// Implement the story lists observations in the `story_rankings` table
+impl GenericRankingStore for Store<postgres::Client> {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        let positions: Vec<i64> = (0..snapshot.ids.len() as i64).collect();
+        self.backend_client.execute(
+            "INSERT INTO story_rankings \
+            SELECT $1, $2, p, i FROM unnest($3::bigint[], $4::bigint[]) AS r(p, i) \
+            ON CONFLICT (list, observed_at, position) DO UPDATE SET id = EXCLUDED.id",
+            &[
+                &snapshot.list.as_str(),
+                &snapshot.observed_at,
+                &positions,
+                &snapshot.ids,
+            ],
+        )?;
+        Ok(())
+    }
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT observed_at, id FROM story_rankings \
+            WHERE list = $1 AND observed_at = ( \
+                SELECT max(observed_at) FROM story_rankings WHERE list = $1 AND observed_at <= $2) \
+            ORDER BY position",
+            &[&list.as_str(), &at],
+        )?;
+        Ok(rows.first().map(|first| RankingSnapshot {
+            list,
+            observed_at: first.get("observed_at"),
+            ids: rows.iter().map(|r| r.get("id")).collect(),
+        }))
+    }
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT observed_at, position FROM story_rankings \
+            WHERE list = $1 AND id = $2 ORDER BY observed_at",
+            &[&list.as_str(), &item_id],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|r| RankPosition {
+                observed_at: r.get("observed_at"),
+                position: r.get("position"),
+            })
+            .collect())
+    }
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT DISTINCT observed_at FROM story_rankings WHERE list = $1 ORDER BY observed_at",
+            &[&list.as_str()],
+        )?;
+        Ok(rows.iter().map(|r| r.get("observed_at")).collect())
+    }
+}
+
+// Implement the item history in the `item_versions` table
+impl GenericItemHistory for Store<postgres::Client> {
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError> {
+        let mut tx = self.backend_client.transaction()?;
+        let recorded = record_version_in(&mut tx, item, observed_at)?;
+        tx.commit()?;
+        Ok(recorded)
+    }
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT observed_at, data FROM item_versions WHERE id = $1 ORDER BY observed_at, seq",
+            &[&item_id],
+        )?;
+        rows.iter()
+            .map(|r| {
+                Ok(ItemVersion {
+                    observed_at: r.get("observed_at"),
+                    item: Item::parse(item_id, r.get("data"))?,
+                })
+            })
+            .collect()
+    }
+}
+
+// Implement the change feed with the `seq` of `item_versions`
+impl GenericChangeFeed for Store<postgres::Client> {
+    fn changes_since(&mut self, after_seq: i64, limit: usize) -> Result<Vec<Change>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT seq, id, observed_at, data FROM item_versions WHERE seq > $1 ORDER BY seq LIMIT $2",
+            &[&after_seq, &(limit as i64)])?;
+        rows.iter()
+            .map(|r| {
+                Ok(Change {
+                    seq: r.get("seq"),
+                    observed_at: r.get("observed_at"),
+                    item: Item::parse(r.get("id"), r.get("data"))?,
+                })
+            })
+            .collect()
+    }
+}
+
+// Implement the outbox in the `item_outbox` table, appended with the versions of the items
+impl GenericOutbox for Store<postgres::Client> {
+    fn outbox_since(
+        &mut self,
+        after_seq: i64,
+        limit: usize,
+    ) -> Result<Vec<OutboxEntry>, CrawlerError> {
+        let rows = self.backend_client.query(
+            "SELECT * FROM item_outbox WHERE seq > $1 ORDER BY seq LIMIT $2",
+            &[&after_seq, &(limit as i64)],
+        )?;
+        rows.iter()
+            .map(|r| {
+                let change: String = r.get("change_type");
+                Ok(OutboxEntry {
+                    seq: r.get("seq"),
+                    item_id: r.get("id"),
+                    change: ChangeType::parse(&change).ok_or_else(|| CrawlerError::Store {
+                        reason: format!("unknown change type {change:?}"),
+                    })?,
+                    fields: serde_json::from_str(r.get("fields"))?,
+                    changed_at: r.get("changed_at"),
+                })
+            })
+            .collect()
+    }
+}
+
+// Implement the search with the `search` tsvector of `items` and its GIN index
+impl GenericSearch for Store<postgres::Client> {
+    fn search(&mut self, query: &SearchQuery) -> Result<Vec<SearchHit>, CrawlerError> {
+        // `plainto_tsquery` matches all the words and ignores the operators
+        let text = query.words()?.join(" ");
+        let rows = self.backend_client.query(
+            &format!("SELECT id, type, who, time, title, ts_rank(search, q)::float8 AS rank, \
+                ts_headline('english', concat_ws(' ', NULLIF(title, ''), NULLIF(content, '')), q, \
+                    'StartSel={HIGHLIGHT_START}, StopSel={HIGHLIGHT_END}, MaxWords=24, MinWords=8') AS snippet \
+                FROM items, plainto_tsquery('english', $1) q \
+                WHERE search @@ q AND ($2::text IS NULL OR type = $2) AND ($3::text IS NULL OR who = $3) \
+                AND ($4::bigint IS NULL OR time >= $4) AND ($5::bigint IS NULL OR time <= $5) \
+                ORDER BY rank DESC, id LIMIT $6"),
+            &[&text, &query.tp, &query.author, &query.since, &query.until, &(query.limit as i64)])?;
+        Ok(rows
+            .iter()
+            .map(|r| SearchHit {
+                item_id: r.get("id"),
+                tp: r.get::<_, Option<String>>("type").unwrap_or_default(),
+                who: r.get::<_, Option<String>>("who").unwrap_or_default(),
+                time: r.get::<_, Option<i64>>("time").unwrap_or_default(),
+                title: r.get::<_, Option<String>>("title").unwrap_or_default(),
+                rank: r.get("rank"),
+                snippet: r.get::<_, Option<String>>("snippet").unwrap_or_default(),
+            })
+            .collect())
+    }
+}
+
+// Implement the tombstones in the `item_tombstones` table
+impl GenericTombstoneStore for Store<postgres::Client> {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        read_tombstone(&mut self.backend_client, item_id)
+    }
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        upsert_tombstone(&mut self.backend_client, tombstone)
+    }
+}
+
+// Implement the migrations with the `schema_version` table
+impl GenericMigrations for Store<postgres::Client> {
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    fn applied_migrations(&mut self) -> Result<Vec<AppliedMigration>, CrawlerError> {
+        let mut transaction = self.backend_client.transaction()?;
+        // Concurrent `CREATE TABLE IF NOT EXISTS` can conflict, the lock serializes them
+        transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+        transaction.batch_execute(
+            r###"
+            CREATE TABLE IF NOT EXISTS schema_version (
+              version bigint NOT NULL PRIMARY KEY,
+              name varchar(255) NOT NULL,
+              applied_at bigint NOT NULL
+            )"###,
+        )?;
+        let applied = transaction
+            .query(
+                "SELECT version, name, applied_at FROM schema_version ORDER BY version",
+                &[],
+            )?
+            .iter()
+            .map(|row| AppliedMigration {
+                version: row.get("version"),
+                name: row.get("name"),
+                applied_at: row.get("applied_at"),
+            })
+            .collect();
+        transaction.commit()?;
+        Ok(applied)
+    }
+
+    fn apply_migration(&mut self, migration: &Migration) -> Result<bool, CrawlerError> {
+        let mut transaction = self.backend_client.transaction()?;
+        transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+        if transaction
+            .query_opt(
+                "SELECT 1 FROM schema_version WHERE version = $1",
+                &[&migration.version],
+            )?
+            .is_some()
+        {
+            return Ok(false);
+        }
+        transaction.batch_execute(migration.sql)?;
+        transaction.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES ($1, $2, $3)",
+            &[&migration.version, &migration.name, &now()],
+        )?;
+        transaction.commit()?;
+        Ok(true)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::crawler::{GenericStoreItem, ItemsCrawler};
+    use crate::ext::postgres::copy_text;
+    use crate::history::GenericItemHistory;
     use crate::hub::NewsHub;
+    use crate::item::Item;
+    use crate::migrate::GenericMigrations;
+    use crate::outbox::{ChangeType, GenericOutbox};
+    use crate::search::{GenericSearch, SearchQuery};
     use crate::store::Store;
+    use crate::tombstone::GenericTombstoneStore;
     use crate::utils::CRAWLER_HUB;
 
+    fn mock_postgres() -> Store<postgres::Client> {
+        let url = format!(
+            "postgresql://{}:{}@{}:{}/{}",
+            "hackernews", "hackernews", "localhost", 5432, "hackernews"
+        );
+        Store::<postgres::Client>::new(&url)
+    }
+
+    #[test]
+    fn test_migrations_postgres() {
+        let mut store_client = mock_postgres();
+        let latest = store_client.migrations().last().unwrap().version;
+        assert_eq!(store_client.schema_version().unwrap(), latest);
+        assert!(store_client.migrate_up().unwrap().is_empty());
+        assert!(store_client
+            .migration_status()
+            .unwrap()
+            .iter()
+            .all(|(_, applied)| applied.is_some()));
+    }
+
     #[test]
     fn test_get_item_postgres() {
         // let mut crawler = mock_crawler();
-        let hub = NewsHub::new(&**CRAWLER_HUB);
-        let url = format!("postgresql://{}:{}@{}:{}/{}",
-                          "hackernews",
-                          "hackernews",
-                          "localhost",
-                          5432,
-                          "hackernews");
-        let store_client  = Store::<postgres::Client>::new(&url);
+        let hub = NewsHub::new(&CRAWLER_HUB);
+        let store_client = mock_postgres();
         let mut crawler = ItemsCrawler::new(hub, store_client);
         let item = crawler.client.get_last_item();
         println!("{:?}", item);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_upsert_rolls_back_postgres() {
+        let mut store_client = mock_postgres();
+        let ids = [-1101i64, -1102];
+        for table in ["items", "item_versions", "item_outbox"] {
+            store_client
+                .backend_client
+                .execute(
+                    &format!("DELETE FROM {table} WHERE id = ANY($1)"),
+                    &[&&ids[..]],
+                )
+                .unwrap();
+        }
+        let story = |id: i64, title: &str| {
+            Item::from(format!(
+                r#"{{"id": {id}, "type": "story", "time": 1, "title": "{title}"}}"#
+            ))
+        };
+        store_client.upsert_item(story(-1101, "first")).unwrap();
+
+        // The versions of the test ids can't be recorded anymore
+        store_client
+            .backend_client
+            .batch_execute(
+                "
+            CREATE OR REPLACE FUNCTION reject_test_version() RETURNS trigger AS $$
+            BEGIN RAISE EXCEPTION 'rejected version of %', NEW.id; END $$ LANGUAGE plpgsql;
+            DROP TRIGGER IF EXISTS reject_test_version ON item_versions;
+            CREATE TRIGGER reject_test_version BEFORE INSERT ON item_versions
+              FOR EACH ROW WHEN (NEW.id IN (-1101, -1102)) EXECUTE FUNCTION reject_test_version();",
+            )
+            .unwrap();
+        let edited = store_client.upsert_item(story(-1101, "edited"));
+        let created = store_client.upsert_item(story(-1102, "new"));
+        store_client
+            .backend_client
+            .batch_execute("DROP TRIGGER reject_test_version ON item_versions")
+            .unwrap();
+
+        assert!(edited.is_err() && created.is_err());
+        assert_eq!(
+            store_client.get_item(-1101).unwrap().unwrap().title,
+            "first"
+        );
+        assert_eq!(store_client.get_item(-1102).unwrap(), None);
+        let outbox: i64 = store_client
+            .backend_client
+            .query_one(
+                "SELECT count(*) FROM item_outbox WHERE id = ANY($1)",
+                &[&&ids[..]],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(outbox, 1);
+    }
+
+    #[test]
+    fn test_store_duplicated_id_postgres() {
+        let mut store_client = mock_postgres();
+        for table in ["items", "item_versions", "item_outbox"] {
+            store_client
+                .backend_client
+                .execute(&format!("DELETE FROM {} WHERE id = -1201", table), &[])
+                .unwrap();
+        }
+        let versions: Vec<Item> = (1..=5)
+            .map(|score| {
+                let mut item = Item::from(String::from(
+                    r#"{"id": -1201, "type": "story", "time": 1, "title": "again"}"#,
+                ));
+                item.score = score;
+                item
+            })
+            .collect();
+        assert_eq!(store_client.store_items(versions.clone()).unwrap(), 5);
+
+        // The row, the version log and the outbox all hold the last occurrence
+        assert_eq!(
+            store_client.get_item(-1201).unwrap(),
+            Some(versions[4].clone())
+        );
+        let history = store_client.item_versions(-1201).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].item, versions[4]);
+        let outbox: i64 = store_client
+            .backend_client
+            .query_one("SELECT count(*) FROM item_outbox WHERE id = -1201", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(outbox, 1);
+    }
+
+    #[test]
+    fn test_copy_text() {
+        assert_eq!(copy_text("a\tb\nc\\d\re"), "a\\tb\\nc\\\\d\\re");
+    }
+
+    #[test]
+    fn test_store_items_postgres() {
+        let mut store_client = mock_postgres();
+        // Ids far from real items so the test does not clash with crawled data
+        let ids = [-1001i64, -1002];
+        store_client
+            .backend_client
+            .execute("DELETE FROM items WHERE id = ANY($1)", &[&&ids[..]])
+            .unwrap();
+        store_client
+            .backend_client
+            .execute("DELETE FROM item_versions WHERE id = ANY($1)", &[&&ids[..]])
+            .unwrap();
+        store_client
+            .backend_client
+            .execute(
+                "DELETE FROM item_tombstones WHERE id = ANY($1)",
+                &[&&ids[..]],
+            )
+            .unwrap();
+        store_client
+            .backend_client
+            .execute("DELETE FROM item_outbox WHERE id = ANY($1)", &[&&ids[..]])
+            .unwrap();
+        let outbox_start: i64 = store_client
+            .backend_client
+            .query_one("SELECT COALESCE(MAX(seq), 0) FROM item_outbox", &[])
+            .unwrap()
+            .get(0);
+
+        let story = Item::from(String::from(
+            r#"{"id": -1001, "type": "story", "time": 1, "title": "Tabs\tand\nnewlines \\o/", "kids": [1, 2]}"#,
+        ));
+        let comment = Item::from(String::from(
+            r#"{"id": -1002, "type": "comment", "time": 1, "by": "pg", "text": "it's fine"}"#,
+        ));
+        assert_eq!(
+            store_client
+                .store_items(vec![story.clone(), comment.clone()])
+                .unwrap(),
+            2
+        );
+        // Storing the same items again upserts without new versions
+        assert_eq!(store_client.store_items(vec![story.clone()]).unwrap(), 1);
+        let title: String = store_client
+            .backend_client
+            .query_one("SELECT title FROM items WHERE id = -1001", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(title, story.title);
+        assert_eq!(store_client.item_versions(-1001).unwrap().len(), 1);
+
+        let deleted = Item::from(String::from(
+            r#"{"id": -1002, "type": "comment", "time": 1, "deleted": true}"#,
+        ));
+        store_client.store_items(vec![deleted]).unwrap();
+        assert_eq!(
+            store_client.get_tombstone(-1002).unwrap().unwrap().text,
+            "it's fine"
+        );
+        assert_eq!(store_client.item_versions(-1002).unwrap().len(), 2);
+
+        let mut edited = comment;
+        edited.text = String::from("edited");
+        store_client.store_item(edited).unwrap();
+        assert_eq!(store_client.item_versions(-1002).unwrap().len(), 3);
+        let changes: Vec<(i64, ChangeType, Vec<String>)> = store_client
+            .outbox(outbox_start)
+            .map(|e| e.unwrap())
+            .filter(|e| ids.contains(&e.item_id))
+            .map(|e| (e.item_id, e.change, e.fields))
+            .collect();
+        assert_eq!(
+            changes.iter().map(|c| (c.0, c.1)).collect::<Vec<_>>(),
+            vec![
+                (-1002, ChangeType::Insert),
+                (-1001, ChangeType::Insert),
+                (-1002, ChangeType::Delete),
+                (-1002, ChangeType::Update)
+            ]
+        );
+        assert_eq!(changes[3].2, vec!["by", "deleted", "text"]);
+
+        assert_eq!(store_client.get_item(-1001).unwrap(), Some(story));
+        assert_eq!(
+            store_client.contains(&[-1001, -1003]).unwrap(),
+            [-1001].into()
+        );
+        assert_eq!(
+            store_client.missing_in_range(-1003..=-1001).unwrap(),
+            vec![-1003]
+        );
+        let scanned: Vec<i64> = store_client
+            .scan(-1002..=-1001)
+            .map(|i| i.unwrap().id)
+            .collect();
+        assert_eq!(scanned, vec![-1002, -1001]);
+        assert!(store_client.count().unwrap() >= 2);
+
+        // The index follows the edit of -1002, the words are stemmed
+        let mut query = SearchQuery::new("editing");
+        query.author = Some(String::from("pg"));
+        query.until = Some(1);
+        let hits = store_client.search(&query).unwrap();
+        assert_eq!(
+            hits.iter().map(|h| h.item_id).collect::<Vec<_>>(),
+            vec![-1002]
+        );
+        assert_eq!(hits[0].snippet, "[edited]");
+        let hits = store_client
+            .search(&SearchQuery::new("newlines tabs"))
+            .unwrap();
+        assert!(hits.iter().any(|h| h.item_id == -1001));
+        assert!(store_client
+            .search(&SearchQuery::new("it's fine"))
+            .unwrap()
+            .iter()
+            .all(|h| h.item_id != -1002));
+    }
+}
@@ -0,0 +1,532 @@
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::history::{GenericItemHistory, ItemVersion};
+use crate::item::Item;
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::retry::{GenericRetryQueue, RetryEntry, RetryState};
+use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use crate::utils::now;
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition, WriteTransaction};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// Latest JSON of every item, keyed by the big-endian id so keys sort like ids.
+/// Hub ids are never negative
+const ITEMS: TableDefinition<[u8; 8], &str> = TableDefinition::new("items");
+/// Every distinct version of an item, keyed by id and a global sequence
+const VERSIONS: TableDefinition<([u8; 8], u64), (i64, &str)> =
+    TableDefinition::new("item_versions");
+/// Index of the stories by `time` for the refresh job
+const STORY_TIMES: TableDefinition<(i64, [u8; 8]), ()> = TableDefinition::new("story_times");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+const CHECKPOINTS: TableDefinition<&str, i64> = TableDefinition::new("checkpoints");
+const RETRY_QUEUE: TableDefinition<[u8; 8], &str> = TableDefinition::new("retry_queue");
+/// (score, descendants) by id and observation time
+const SNAPSHOTS: TableDefinition<([u8; 8], i64), (i64, i64)> =
+    TableDefinition::new("item_snapshots");
+/// JSON array of the ranked ids by list and observation time
+const RANKINGS: TableDefinition<(&str, i64), &str> = TableDefinition::new("story_rankings");
+const TOMBSTONES: TableDefinition<[u8; 8], &str> = TableDefinition::new("item_tombstones");
+
+const VERSION_SEQ: &str = "version_seq";
+
+fn key(id: i64) -> [u8; 8] {
+    id.to_be_bytes()
+}
+
+fn id_of(key: [u8; 8]) -> i64 {
+    i64::from_be_bytes(key)
+}
+
+/// Keys of the non-negative ids of `range`, `None` when there is none
+fn key_range(range: &RangeInclusive<i64>) -> Option<RangeInclusive<[u8; 8]>> {
+    if *range.end() < 0 || range.start() > range.end() {
+        return None;
+    }
+    Some(key((*range.start()).max(0))..=key(*range.end()))
+}
+
+// Implement the Store with redb
+impl Store<Database> {
+    pub fn new(path: &str) -> Self {
+        let db = Database::create(path).unwrap();
+        init_tables(&db).unwrap();
+        Self { backend_client: db }
+    }
+}
+
+/// Create every table, read transactions fail on missing tables
+fn init_tables(db: &Database) -> Result<(), CrawlerError> {
+    let tx = db.begin_write()?;
+    tx.open_table(ITEMS)?;
+    tx.open_table(VERSIONS)?;
+    tx.open_table(STORY_TIMES)?;
+    tx.open_table(META)?;
+    tx.open_table(CHECKPOINTS)?;
+    tx.open_table(RETRY_QUEUE)?;
+    tx.open_table(SNAPSHOTS)?;
+    tx.open_table(RANKINGS)?;
+    tx.open_table(TOMBSTONES)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Write the items which changed as their latest value and a new version, tracking
+/// their tombstones. Returns the number of recorded versions
+fn write_items(
+    tx: &WriteTransaction,
+    items: &[Item],
+    observed_at: i64,
+) -> Result<usize, CrawlerError> {
+    let mut latest = tx.open_table(ITEMS)?;
+    let mut versions = tx.open_table(VERSIONS)?;
+    let mut stories = tx.open_table(STORY_TIMES)?;
+    let mut tombstones = tx.open_table(TOMBSTONES)?;
+    let mut meta = tx.open_table(META)?;
+    let mut seq = meta.get(VERSION_SEQ)?.map_or(0, |v| v.value());
+    let mut recorded = 0;
+    for item in items {
+        if item.id < 0 {
+            return Err(CrawlerError::Store {
+                reason: format!("negative item id {}", item.id),
+            });
+        }
+        let data = serde_json::to_string(item)?;
+        let previous = latest.get(key(item.id))?.map(|v| v.value().to_string());
+        if previous.as_ref() == Some(&data) {
+            continue;
+        }
+        let previous = previous.map(|p| Item::parse(item.id, &p)).transpose()?;
+        let existing = tombstones
+            .get(key(item.id))?
+            .map(|t| serde_json::from_str::<Tombstone>(t.value()))
+            .transpose()?;
+        if let Some(tombstone) = Tombstone::track(existing, previous.as_ref(), item, observed_at) {
+            tombstones.insert(key(item.id), serde_json::to_string(&tombstone)?.as_str())?;
+        }
+        latest.insert(key(item.id), data.as_str())?;
+        seq += 1;
+        versions.insert((key(item.id), seq), (observed_at, data.as_str()))?;
+        if item.tp == "story" {
+            stories.insert((item.time, key(item.id)), ())?;
+        }
+        recorded += 1;
+    }
+    meta.insert(VERSION_SEQ, seq)?;
+    Ok(recorded)
+}
+
+// Implement trait StoreItem, every write is one transaction
+impl GenericStoreItem for Store<Database> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let items = tx.open_table(ITEMS)?;
+        let last = items.last()?.map_or(0, |(k, _)| id_of(k.value()));
+        Ok(last)
+    }
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.upsert_item(item)?;
+        Ok(true)
+    }
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        self.record_version(&item, now())?;
+        Ok(())
+    }
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        if item_id < 0 {
+            return Ok(None);
+        }
+        let tx = self.backend_client.begin_read()?;
+        let items = tx.open_table(ITEMS)?;
+        let data = items.get(key(item_id))?;
+        data.map(|d| Item::parse(item_id, d.value())).transpose()
+    }
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        let Some(keys) = key_range(&range) else {
+            return Ok(vec![]);
+        };
+        let tx = self.backend_client.begin_read()?;
+        let items = tx.open_table(ITEMS)?;
+        let mut found = vec![];
+        for entry in items.range(keys)?.take(limit) {
+            let (k, v) = entry?;
+            found.push(Item::parse(id_of(k.value()), v.value())?);
+        }
+        Ok(found)
+    }
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        let tx = self.backend_client.begin_write()?;
+        write_items(&tx, &items, now())?;
+        tx.commit()?;
+        Ok(items.len())
+    }
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let items = tx.open_table(ITEMS)?;
+        let mut stored = HashSet::new();
+        for id in ids.iter().filter(|id| **id >= 0) {
+            if items.get(key(*id))?.is_some() {
+                stored.insert(*id);
+            }
+        }
+        Ok(stored)
+    }
+    fn missing_in_range(&mut self, range: RangeInclusive<i64>) -> Result<Vec<i64>, CrawlerError> {
+        let mut stored = HashSet::new();
+        if let Some(keys) = key_range(&range) {
+            let tx = self.backend_client.begin_read()?;
+            let items = tx.open_table(ITEMS)?;
+            for entry in items.range(keys)? {
+                stored.insert(id_of(entry?.0.value()));
+            }
+        }
+        Ok(range.filter(|id| !stored.contains(id)).collect())
+    }
+    fn count(&mut self) -> Result<u64, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        Ok(tx.open_table(ITEMS)?.len()?)
+    }
+}
+
+// Implement the checkpoints in the `checkpoints` table
+impl GenericCheckpoint for Store<Database> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let checkpoints = tx.open_table(CHECKPOINTS)?;
+        let value = checkpoints.get(name)?.map(|v| v.value());
+        Ok(value)
+    }
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        let tx = self.backend_client.begin_write()?;
+        tx.open_table(CHECKPOINTS)?.insert(name, value)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+// Implement the retry queue in the `retry_queue` table
+impl GenericRetryQueue for Store<Database> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let queue = tx.open_table(RETRY_QUEUE)?;
+        let entry = queue.get(key(item_id))?;
+        Ok(entry.map(|e| serde_json::from_str(e.value())).transpose()?)
+    }
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        let tx = self.backend_client.begin_write()?;
+        tx.open_table(RETRY_QUEUE)?
+            .insert(key(entry.item_id), serde_json::to_string(entry)?.as_str())?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        let tx = self.backend_client.begin_write()?;
+        tx.open_table(RETRY_QUEUE)?.remove(key(item_id))?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let queue = tx.open_table(RETRY_QUEUE)?;
+        let mut due = vec![];
+        for entry in queue.iter()? {
+            let entry: RetryEntry = serde_json::from_str(entry?.1.value())?;
+            if entry.state == RetryState::Pending && entry.next_attempt_at <= now {
+                due.push(entry);
+            }
+        }
+        due.sort_by_key(|e| e.next_attempt_at);
+        due.truncate(limit);
+        Ok(due)
+    }
+}
+
+// Implement the snapshots in the `item_snapshots` table
+impl GenericSnapshotStore for Store<Database> {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        let tx = self.backend_client.begin_write()?;
+        tx.open_table(SNAPSHOTS)?.insert(
+            (key(snapshot.item_id), snapshot.observed_at),
+            (snapshot.score, snapshot.descendants),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let stories = tx.open_table(STORY_TIMES)?;
+        let snapshots = tx.open_table(SNAPSHOTS)?;
+        let mut candidates = vec![];
+        for entry in stories.range((since, [0u8; 8])..)? {
+            let (time, id) = entry?.0.value();
+            let last = snapshots
+                .range((id, i64::MIN)..=(id, i64::MAX))?
+                .next_back()
+                .transpose()?;
+            candidates.push(RefreshCandidate {
+                item_id: id_of(id),
+                time,
+                last_observed_at: last.map(|(k, _)| k.value().1),
+            });
+        }
+        Ok(candidates)
+    }
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let snapshots = tx.open_table(SNAPSHOTS)?;
+        let mut found = vec![];
+        for entry in snapshots.range((key(item_id), i64::MIN)..=(key(item_id), i64::MAX))? {
+            let (k, v) = entry?;
+            let (score, descendants) = v.value();
+            found.push(ItemSnapshot {
+                item_id,
+                observed_at: k.value().1,
+                score,
+                descendants,
+            });
+        }
+        Ok(found)
+    }
+}
+
+// Implement the rankings in the `story_rankings` table
+impl GenericRankingStore for Store<Database> {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        if snapshot.ids.is_empty() {
+            return Ok(());
+        }
+        let tx = self.backend_client.begin_write()?;
+        tx.open_table(RANKINGS)?.insert(
+            (snapshot.list.as_str(), snapshot.observed_at),
+            serde_json::to_string(&snapshot.ids)?.as_str(),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let rankings = tx.open_table(RANKINGS)?;
+        let last = rankings
+            .range((list.as_str(), i64::MIN)..=(list.as_str(), at))?
+            .next_back()
+            .transpose()?;
+        match last {
+            Some((k, v)) => Ok(Some(RankingSnapshot {
+                list,
+                observed_at: k.value().1,
+                ids: serde_json::from_str(v.value())?,
+            })),
+            None => Ok(None),
+        }
+    }
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let rankings = tx.open_table(RANKINGS)?;
+        let mut history = vec![];
+        for entry in rankings.range((list.as_str(), i64::MIN)..=(list.as_str(), i64::MAX))? {
+            let (k, v) = entry?;
+            let ids: Vec<i64> = serde_json::from_str(v.value())?;
+            if let Some(position) = ids.iter().position(|id| *id == item_id) {
+                history.push(RankPosition {
+                    observed_at: k.value().1,
+                    position: position as i64,
+                });
+            }
+        }
+        Ok(history)
+    }
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let rankings = tx.open_table(RANKINGS)?;
+        let mut observations = vec![];
+        for entry in rankings.range((list.as_str(), i64::MIN)..=(list.as_str(), i64::MAX))? {
+            observations.push(entry?.0.value().1);
+        }
+        Ok(observations)
+    }
+}
+
+// The `items` table holds the latest version, `item_versions` all of them
+impl GenericItemHistory for Store<Database> {
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError> {
+        let tx = self.backend_client.begin_write()?;
+        let recorded = write_items(&tx, std::slice::from_ref(item), observed_at)?;
+        tx.commit()?;
+        Ok(recorded > 0)
+    }
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let versions = tx.open_table(VERSIONS)?;
+        let mut found = vec![];
+        for entry in versions.range((key(item_id), 0)..=(key(item_id), u64::MAX))? {
+            let (_, version) = entry?;
+            let (observed_at, data) = version.value();
+            found.push(ItemVersion {
+                observed_at,
+                item: Item::parse(item_id, data)?,
+            });
+        }
+        found.sort_by_key(|v| v.observed_at);
+        Ok(found)
+    }
+}
+
+// Implement the tombstones in the `item_tombstones` table
+impl GenericTombstoneStore for Store<Database> {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        let tx = self.backend_client.begin_read()?;
+        let tombstones = tx.open_table(TOMBSTONES)?;
+        let tombstone = tombstones.get(key(item_id))?;
+        Ok(tombstone
+            .map(|t| serde_json::from_str(t.value()))
+            .transpose()?)
+    }
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        let tx = self.backend_client.begin_write()?;
+        tx.open_table(TOMBSTONES)?.insert(
+            key(tombstone.item_id),
+            serde_json::to_string(tombstone)?.as_str(),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+    use crate::history::GenericItemHistory;
+    use crate::item::Item;
+    use crate::ranking::{GenericRankingStore, RankingSnapshot, StoryList};
+    use crate::refresh::{GenericSnapshotStore, ItemSnapshot};
+    use crate::store::Store;
+    use crate::testing::TempDir;
+    use crate::tombstone::GenericTombstoneStore;
+    use redb::Database;
+
+    fn mock_redb(name: &str) -> (TempDir, Store<Database>) {
+        let dir = TempDir::new(&format!("redb-{}", name));
+        let store = Store::<Database>::new(&dir.join("items.redb"));
+        (dir, store)
+    }
+
+    fn story(id: i64, time: i64) -> Item {
+        Item::parse(
+            id,
+            &format!(
+                r#"{{"id":{},"type":"story","by":"pg","time":{}}}"#,
+                id, time
+            ),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_store_items_redb() {
+        let (dir, mut store_client) = mock_redb("items");
+        assert_eq!(store_client.get_last_item().unwrap(), 0);
+        // Big-endian keys sort 256 after 255, little-endian ones would not
+        store_client
+            .store_items(vec![story(256, 10), story(255, 20), story(1, 30)])
+            .unwrap();
+        store_client.store_item(story(1, 30)).unwrap();
+        let mut deleted = story(255, 20);
+        deleted.deleted = true;
+        store_client.store_item(deleted.clone()).unwrap();
+        store_client.save_checkpoint("sync", 256).unwrap();
+        drop(store_client);
+
+        let mut store_client = Store::<Database>::new(&dir.join("items.redb"));
+        assert_eq!(store_client.get_last_item().unwrap(), 256);
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), Some(256));
+        assert_eq!(store_client.get_item(255).unwrap(), Some(deleted));
+        assert_eq!(store_client.count().unwrap(), 3);
+        let ids: Vec<i64> = store_client.scan(-5..=300).map(|i| i.unwrap().id).collect();
+        assert_eq!(ids, vec![1, 255, 256]);
+        assert_eq!(
+            store_client.missing_in_range(254..=257).unwrap(),
+            vec![254, 257]
+        );
+        assert_eq!(store_client.item_versions(1).unwrap().len(), 1);
+        assert_eq!(store_client.item_versions(255).unwrap().len(), 2);
+        assert!(store_client
+            .get_tombstone(255)
+            .unwrap()
+            .unwrap()
+            .deleted_at
+            .is_some());
+        assert!(store_client.store_item(story(-1, 0)).is_err());
+    }
+
+    #[test]
+    fn test_snapshots_and_rankings_redb() {
+        let (_dir, mut store_client) = mock_redb("aux");
+        store_client
+            .store_items(vec![story(1, 100), story(2, 200)])
+            .unwrap();
+        store_client
+            .save_snapshot(&ItemSnapshot {
+                item_id: 2,
+                observed_at: 500,
+                score: 3,
+                descendants: 1,
+            })
+            .unwrap();
+        let candidates = store_client.refresh_candidates(150).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(
+            (candidates[0].item_id, candidates[0].last_observed_at),
+            (2, Some(500))
+        );
+        assert_eq!(store_client.get_snapshots(2).unwrap().len(), 1);
+
+        store_client
+            .save_ranking(&RankingSnapshot {
+                list: StoryList::Top,
+                observed_at: 10,
+                ids: vec![2, 1],
+            })
+            .unwrap();
+        store_client
+            .save_ranking(&RankingSnapshot {
+                list: StoryList::Top,
+                observed_at: 20,
+                ids: vec![1, 2],
+            })
+            .unwrap();
+        assert_eq!(
+            store_client
+                .ranking_at(StoryList::Top, 15)
+                .unwrap()
+                .unwrap()
+                .ids,
+            vec![2, 1]
+        );
+        assert_eq!(store_client.ranking_at(StoryList::New, 15).unwrap(), None);
+        assert_eq!(
+            store_client.ranking_observations(StoryList::Top).unwrap(),
+            vec![10, 20]
+        );
+        let positions: Vec<i64> = store_client
+            .ranking_history(StoryList::Top, 2)
+            .unwrap()
+            .iter()
+            .map(|p| p.position)
+            .collect();
+        assert_eq!(positions, vec![0, 1]);
+    }
+}
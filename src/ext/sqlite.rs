@@ -1,13 +1,38 @@
-use sqlite::{Connection, State};
+use sqlite::{Connection, OpenFlags, State};
+use sqlite3_sys as ffi;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-use crate::crawler::{GenericStoreItem, ItemsCrawler};
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::history::{GenericItemHistory, ItemVersion};
 use crate::item::Item;
+use crate::migrate::{AppliedMigration, GenericMigrations, Migration};
+use crate::outbox::{ChangeType, GenericOutbox, OutboxEntry};
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::replicate::{Change, GenericChangeFeed};
+use crate::retry::{GenericRetryQueue, RetryEntry, RetryState};
+use crate::search::{GenericSearch, SearchHit, SearchQuery, HIGHLIGHT_END, HIGHLIGHT_START};
 use crate::store::Store;
-use crate::utils::MAX_BATCH_ITEMS;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use crate::utils::{
+    now, SQLITE_BUSY_TIMEOUT, SQLITE_CACHE_SIZE, SQLITE_JOURNAL_MODE, SQLITE_MMAP_SIZE,
+    SQLITE_SYNCHRONOUS,
+};
 
-/// Init needed db which store items data
-fn init_items_table(conn: &Connection) {
-    let query = r###"
+/// Migrations of the sqlite schema, see `GenericMigrations`
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        sql: r###"
+            -- Init needed db which store items data
             CREATE TABLE IF NOT EXISTS `items` (
               `id` int(10) NOT NULL,
               `deleted` tinyint(4) DEFAULT '0',
@@ -21,110 +46,1188 @@ fn init_items_table(conn: &Connection) {
               `score` int(10) DEFAULT NULL,
               `url` text DEFAULT NULL,
               `parent` int(10) DEFAULT NULL
-            )"###;
-    conn.execute(query).unwrap()
+            );
+            -- Init the queue of items which failed to be fetched or stored
+            CREATE TABLE IF NOT EXISTS `retry_queue` (
+              `id` int(10) NOT NULL PRIMARY KEY,
+              `attempts` int(10) NOT NULL DEFAULT '0',
+              `last_error` text DEFAULT NULL,
+              `next_attempt_at` int(11) NOT NULL,
+              `state` varchar(16) NOT NULL DEFAULT 'pending'
+            );
+            -- Init the named positions of the crawl flows
+            CREATE TABLE IF NOT EXISTS `checkpoints` (
+              `name` varchar(64) NOT NULL PRIMARY KEY,
+              `value` int(10) NOT NULL,
+              `updated_at` int(11) NOT NULL
+            );
+            -- Init the observations of the young stories
+            CREATE TABLE IF NOT EXISTS `item_snapshots` (
+              `id` int(10) NOT NULL,
+              `observed_at` int(11) NOT NULL,
+              `score` int(10) DEFAULT NULL,
+              `descendants` int(10) DEFAULT NULL,
+              PRIMARY KEY (`id`, `observed_at`)
+            );
+            -- Init the observations of the story lists, one row per ranked story
+            CREATE TABLE IF NOT EXISTS `story_rankings` (
+              `list` varchar(16) NOT NULL,
+              `observed_at` int(11) NOT NULL,
+              `position` int(10) NOT NULL,
+              `id` int(10) NOT NULL,
+              PRIMARY KEY (`list`, `observed_at`, `position`)
+            );
+            -- Init the append-only history of the items, `data` is the item as JSON
+            CREATE TABLE IF NOT EXISTS `item_versions` (
+              `id` int(10) NOT NULL,
+              `observed_at` int(11) NOT NULL,
+              `data` text NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS `item_versions_id_observed_at`
+              ON `item_versions` (`id`, `observed_at`);
+            -- Init the deleted/dead transitions with the last known content of the items
+            CREATE TABLE IF NOT EXISTS `item_tombstones` (
+              `id` int(10) NOT NULL PRIMARY KEY,
+              `deleted_at` int(11) DEFAULT NULL,
+              `dead_at` int(11) DEFAULT NULL,
+              `who` varchar(255) DEFAULT NULL,
+              `title` text DEFAULT NULL,
+              `content` text DEFAULT NULL,
+              `url` text DEFAULT NULL
+            );"###,
+    },
+    Migration {
+        version: 2,
+        name: "item lookup indexes",
+        sql: r###"
+            CREATE INDEX IF NOT EXISTS `items_parent` ON `items` (`parent`);
+            CREATE INDEX IF NOT EXISTS `items_who` ON `items` (`who`);
+            CREATE INDEX IF NOT EXISTS `items_time` ON `items` (`time`);
+            CREATE INDEX IF NOT EXISTS `items_type` ON `items` (`type`);"###,
+    },
+    Migration {
+        version: 3,
+        name: "item id index",
+        // `items` has no primary key, range reads by id scanned the whole table
+        sql: r###"
+            CREATE INDEX IF NOT EXISTS `items_id` ON `items` (`id`);"###,
+    },
+    Migration {
+        version: 4,
+        name: "item change outbox",
+        // AUTOINCREMENT so the sequence of the consumers is never reused
+        sql: r###"
+            CREATE TABLE IF NOT EXISTS `item_outbox` (
+              `seq` INTEGER PRIMARY KEY AUTOINCREMENT,
+              `id` int(10) NOT NULL,
+              `change_type` varchar(8) NOT NULL,
+              `fields` text NOT NULL,
+              `changed_at` int(11) NOT NULL
+            );"###,
+    },
+    Migration {
+        version: 5,
+        name: "full-text search",
+        // An external content index of `items`, kept in sync by triggers and built for the existing rows
+        sql: r###"
+            CREATE VIRTUAL TABLE IF NOT EXISTS `items_fts` USING fts5(`title`, `content`, content = 'items');
+            CREATE TRIGGER IF NOT EXISTS `items_fts_insert` AFTER INSERT ON `items` BEGIN
+              INSERT INTO `items_fts` (rowid, `title`, `content`) VALUES (new.rowid, new.`title`, new.`content`);
+            END;
+            CREATE TRIGGER IF NOT EXISTS `items_fts_delete` AFTER DELETE ON `items` BEGIN
+              INSERT INTO `items_fts` (`items_fts`, rowid, `title`, `content`) VALUES ('delete', old.rowid, old.`title`, old.`content`);
+            END;
+            CREATE TRIGGER IF NOT EXISTS `items_fts_update` AFTER UPDATE ON `items` BEGIN
+              INSERT INTO `items_fts` (`items_fts`, rowid, `title`, `content`) VALUES ('delete', old.rowid, old.`title`, old.`content`);
+              INSERT INTO `items_fts` (rowid, `title`, `content`) VALUES (new.rowid, new.`title`, new.`content`);
+            END;
+            INSERT INTO `items_fts` (`items_fts`) VALUES ('rebuild');"###,
+    },
+    Migration {
+        version: 6,
+        name: "item primary key",
+        // `items` is rebuilt with its id as primary key, keeping the latest row of the duplicated ids.
+        // Its indexes and triggers go with the old table, the id index is the primary key now
+        sql: r###"
+            CREATE TABLE `items_keyed` (
+              `id` INTEGER NOT NULL PRIMARY KEY,
+              `deleted` tinyint(4) DEFAULT '0',
+              `type` varchar(16) DEFAULT NULL,
+              `who` varchar(255) DEFAULT NULL,
+              `time` int(11) DEFAULT NULL,
+              `dead` tinyint(4) DEFAULT '0',
+              `kids` text DEFAULT NULL,
+              `title` text DEFAULT NULL,
+              `content` text DEFAULT NULL,
+              `score` int(10) DEFAULT NULL,
+              `url` text DEFAULT NULL,
+              `parent` int(10) DEFAULT NULL
+            );
+            INSERT INTO `items_keyed`
+              SELECT * FROM `items` WHERE rowid IN (SELECT max(rowid) FROM `items` GROUP BY `id`);
+            DROP TABLE `items`;
+            ALTER TABLE `items_keyed` RENAME TO `items`;
+            CREATE INDEX `items_parent` ON `items` (`parent`);
+            CREATE INDEX `items_who` ON `items` (`who`);
+            CREATE INDEX `items_time` ON `items` (`time`);
+            CREATE INDEX `items_type` ON `items` (`type`);
+            CREATE TRIGGER `items_fts_insert` AFTER INSERT ON `items` BEGIN
+              INSERT INTO `items_fts` (rowid, `title`, `content`) VALUES (new.rowid, new.`title`, new.`content`);
+            END;
+            CREATE TRIGGER `items_fts_delete` AFTER DELETE ON `items` BEGIN
+              INSERT INTO `items_fts` (`items_fts`, rowid, `title`, `content`) VALUES ('delete', old.rowid, old.`title`, old.`content`);
+            END;
+            CREATE TRIGGER `items_fts_update` AFTER UPDATE ON `items` BEGIN
+              INSERT INTO `items_fts` (`items_fts`, rowid, `title`, `content`) VALUES ('delete', old.rowid, old.`title`, old.`content`);
+              INSERT INTO `items_fts` (rowid, `title`, `content`) VALUES (new.rowid, new.`title`, new.`content`);
+            END;
+            INSERT INTO `items_fts` (`items_fts`) VALUES ('rebuild');"###,
+    },
+    Migration {
+        version: 7,
+        name: "full-text search by item id",
+        // The index is keyed by the item id rather than the implicit rowid, which a VACUUM may renumber
+        sql: r###"
+            DROP TRIGGER `items_fts_insert`;
+            DROP TRIGGER `items_fts_delete`;
+            DROP TRIGGER `items_fts_update`;
+            DROP TABLE `items_fts`;
+            CREATE VIRTUAL TABLE `items_fts` USING fts5(`title`, `content`, content = 'items', content_rowid = 'id');
+            CREATE TRIGGER `items_fts_insert` AFTER INSERT ON `items` BEGIN
+              INSERT INTO `items_fts` (rowid, `title`, `content`) VALUES (new.`id`, new.`title`, new.`content`);
+            END;
+            CREATE TRIGGER `items_fts_delete` AFTER DELETE ON `items` BEGIN
+              INSERT INTO `items_fts` (`items_fts`, rowid, `title`, `content`) VALUES ('delete', old.`id`, old.`title`, old.`content`);
+            END;
+            CREATE TRIGGER `items_fts_update` AFTER UPDATE ON `items` BEGIN
+              INSERT INTO `items_fts` (`items_fts`, rowid, `title`, `content`) VALUES ('delete', old.`id`, old.`title`, old.`content`);
+              INSERT INTO `items_fts` (rowid, `title`, `content`) VALUES (new.`id`, new.`title`, new.`content`);
+            END;
+            INSERT INTO `items_fts` (`items_fts`) VALUES ('rebuild');"###,
+    },
+];
+
+/// Upsert clause of the `items` inserts, an item stored again replaces its row
+const ITEMS_UPSERT: &str = "ON CONFLICT (`id`) DO UPDATE SET `deleted` = excluded.`deleted`, `type` = excluded.`type`, \
+    `who` = excluded.`who`, `time` = excluded.`time`, `dead` = excluded.`dead`, `kids` = excluded.`kids`, \
+    `title` = excluded.`title`, `content` = excluded.`content`, `score` = excluded.`score`, `url` = excluded.`url`, \
+    `parent` = excluded.`parent`";
+
+const JOURNAL_MODES: [&str; 6] = ["delete", "truncate", "persist", "memory", "wal", "off"];
+const SYNCHRONOUS_LEVELS: [&str; 4] = ["off", "normal", "full", "extra"];
+
+/// Connection settings of the sqlite store. Pragmas can't be bound as parameters,
+/// so the values are checked against their allowed keywords
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlitePragmas {
+    journal_mode: String,
+    synchronous: String,
+    cache_size: i64,
+    mmap_size: i64,
+    busy_timeout: usize,
+}
+
+impl SqlitePragmas {
+    pub fn new(
+        journal_mode: &str,
+        synchronous: &str,
+        cache_size: i64,
+        mmap_size: i64,
+        busy_timeout: usize,
+    ) -> Result<Self, CrawlerError> {
+        let journal_mode = journal_mode.trim().to_lowercase();
+        let synchronous = synchronous.trim().to_lowercase();
+        if !JOURNAL_MODES.contains(&journal_mode.as_str()) {
+            return Err(CrawlerError::Store {
+                reason: format!("unknown sqlite journal mode {:?}", journal_mode),
+            });
+        }
+        if !SYNCHRONOUS_LEVELS.contains(&synchronous.as_str()) {
+            return Err(CrawlerError::Store {
+                reason: format!("unknown sqlite synchronous level {:?}", synchronous),
+            });
+        }
+        Ok(Self {
+            journal_mode,
+            synchronous,
+            cache_size,
+            mmap_size,
+            busy_timeout,
+        })
+    }
+
+    /// The settings of the `SQLITE_*` environment variables
+    pub fn from_env() -> Result<Self, CrawlerError> {
+        Self::new(
+            &SQLITE_JOURNAL_MODE,
+            &SQLITE_SYNCHRONOUS,
+            *SQLITE_CACHE_SIZE,
+            *SQLITE_MMAP_SIZE,
+            *SQLITE_BUSY_TIMEOUT,
+        )
+    }
+
+    fn apply(&self, conn: &mut Connection) -> Result<(), CrawlerError> {
+        // Set first, switching to `wal` needs a lock too
+        conn.set_busy_timeout(self.busy_timeout)?;
+        conn.execute(format!(
+            "PRAGMA journal_mode = {}; PRAGMA synchronous = {}; PRAGMA cache_size = {}; PRAGMA mmap_size = {};",
+            self.journal_mode, self.synchronous, self.cache_size, self.mmap_size))?;
+        Ok(())
+    }
 }
 
 // Implement Store for postgres
 impl Store<Connection> {
+    /// Run `f` inside a savepoint, which is rolled back when `f` fails.
+    /// Savepoints behave like a transaction and can be nested
+    fn in_savepoint<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<R, CrawlerError>,
+    ) -> Result<R, CrawlerError> {
+        self.backend_client.execute("SAVEPOINT store_batch")?;
+        match f(self) {
+            Ok(r) => {
+                self.backend_client.execute("RELEASE store_batch")?;
+                Ok(r)
+            }
+            Err(e) => {
+                let _ = self
+                    .backend_client
+                    .execute("ROLLBACK TO store_batch; RELEASE store_batch");
+                Err(e)
+            }
+        }
+    }
+
+    /// Open the database and apply the pending migrations
     pub fn new(uri: &str) -> Self {
-        let conn = Connection::open(uri).unwrap();
-        init_items_table(&conn);
-        Self {
-            backend_client: conn
+        let mut store = Self::open(uri).unwrap();
+        store.migrate_up().unwrap();
+        store
+    }
+
+    /// Open the database as it is, without migrating it, with the pragmas of `SqlitePragmas::from_env`
+    pub fn open(uri: &str) -> Result<Self, CrawlerError> {
+        Self::open_with(uri, &SqlitePragmas::from_env()?)
+    }
+
+    pub fn open_with(uri: &str, pragmas: &SqlitePragmas) -> Result<Self, CrawlerError> {
+        let mut conn = Connection::open(uri)?;
+        pragmas.apply(&mut conn)?;
+        Ok(Self {
+            backend_client: conn,
+        })
+    }
+
+    /// Copy the live database to `to` with the online backup API, `pages_per_step` pages at
+    /// a time (all of them when negative) with a pause in between. Writes of other connections
+    /// restart the copy, in `wal` mode a single step never blocks them.
+    /// The copy is written next to `to` and renamed once complete (and sound with `check`),
+    /// returns its size in bytes
+    pub fn backup_to(
+        &self,
+        to: &Path,
+        pages_per_step: i32,
+        pause: Duration,
+        check: bool,
+    ) -> Result<u64, CrawlerError> {
+        let partial = partial_path(to);
+        let _ = fs::remove_file(&partial);
+        let dest = Connection::open(&partial)?;
+        let main = c"main";
+        // SAFETY: both handles outlive the backup, which is finished before they are dropped
+        let backup = unsafe {
+            ffi::sqlite3_backup_init(
+                dest.as_raw(),
+                main.as_ptr(),
+                self.backend_client.as_raw(),
+                main.as_ptr(),
+            )
+        };
+        if backup.is_null() {
+            return Err(last_error(&dest));
+        }
+        let mut rc = ffi::SQLITE_OK;
+        while rc != ffi::SQLITE_DONE {
+            rc = unsafe { ffi::sqlite3_backup_step(backup, pages_per_step) };
+            match rc {
+                ffi::SQLITE_DONE => {}
+                ffi::SQLITE_OK | ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => thread::sleep(pause),
+                _ => break,
+            }
+        }
+        let finished = unsafe { ffi::sqlite3_backup_finish(backup) };
+        if rc != ffi::SQLITE_DONE || finished != ffi::SQLITE_OK {
+            let e = last_error(&dest);
+            drop(dest);
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        drop(dest);
+        finish_copy(&partial, to, check)
+    }
+
+    /// Write a compacted copy of the live database to `to` with `VACUUM INTO`,
+    /// which reads a consistent snapshot. Returns the size of the copy in bytes
+    pub fn vacuum_into(&self, to: &Path, check: bool) -> Result<u64, CrawlerError> {
+        let partial = partial_path(to);
+        let _ = fs::remove_file(&partial);
+        let target = partial.to_string_lossy().replace('\'', "''");
+        if let Err(e) = self
+            .backend_client
+            .execute(format!("VACUUM INTO '{target}'"))
+        {
+            let _ = fs::remove_file(&partial);
+            return Err(e.into());
         }
+        finish_copy(&partial, to, check)
+    }
+
+    /// Run `PRAGMA integrity_check` on the database at `path`, opened read-only.
+    /// Returns the problems found, empty when the database is sound
+    pub fn integrity_check(path: &Path) -> Result<Vec<String>, CrawlerError> {
+        let conn = Connection::open_with_flags(path, OpenFlags::new().set_read_only())?;
+        let mut statement = conn.prepare("PRAGMA integrity_check")?;
+        let mut problems = vec![];
+        while let State::Row = statement.next()? {
+            let row = statement.read::<String, _>(0)?;
+            if row != "ok" {
+                problems.push(row);
+            }
+        }
+        Ok(problems)
+    }
+}
+
+fn partial_path(to: &Path) -> std::path::PathBuf {
+    let mut name = to.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    to.with_file_name(name)
+}
+
+/// Move the complete copy in place of `to`, unless `check` finds it corrupt
+fn finish_copy(partial: &Path, to: &Path, check: bool) -> Result<u64, CrawlerError> {
+    if check {
+        let problems = Store::<Connection>::integrity_check(partial)?;
+        if !problems.is_empty() {
+            let _ = fs::remove_file(partial);
+            return Err(CrawlerError::Store {
+                reason: format!(
+                    "the copy failed the integrity check: {}",
+                    problems.join("; ")
+                ),
+            });
+        }
+    }
+    fs::rename(partial, to)?;
+    Ok(fs::metadata(to)?.len())
+}
+
+/// The error of the latest failed call on `conn`
+fn last_error(conn: &Connection) -> CrawlerError {
+    // SAFETY: the message is owned by the connection and copied right away
+    let (code, message) = unsafe {
+        let raw = conn.as_raw();
+        (
+            ffi::sqlite3_errcode(raw),
+            CStr::from_ptr(ffi::sqlite3_errmsg(raw))
+                .to_string_lossy()
+                .into_owned(),
+        )
+    };
+    CrawlerError::Sqlite {
+        source: sqlite::Error {
+            code: Some(code as isize),
+            message: Some(message),
+        },
     }
 }
 
 // Implement item data to Postgres
-impl GenericStoreItem<Store<Connection>> for Store<Connection> {
-    fn get_last_item(&mut self) -> Result<i64, ()> {
+impl GenericStoreItem for Store<Connection> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
         let sql = "select max(id) as max_id from items";
 
-        let mut statement = self.backend_client
-            .prepare(sql)
-            .unwrap();
-        match statement.next().unwrap() {
+        let mut statement = self.backend_client.prepare(sql)?;
+        match statement.next()? {
             State::Row => {
-                let max_id = statement.read::<i64, _>("max_id").unwrap();
+                let max_id = statement.read::<i64, _>("max_id")?;
                 Ok(max_id)
             }
-            State::Done => {Err(())}
+            State::Done => Err(CrawlerError::Store {
+                reason: "no row for max(id)".into(),
+            }),
+        }
+    }
+    /// The item and its version are committed together
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.in_savepoint(|store| {
+            let sql = format!(
+                "INSERT INTO `items` VALUES {} {}",
+                item.to_sql_value(),
+                ITEMS_UPSERT
+            );
+            store.backend_client.execute(sql)?;
+            store.record_version(&item, now())?;
+            Ok(true)
+        })
+    }
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        self.store_item(item)?;
+        Ok(())
+    }
+    fn get_item(&mut self, item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT * FROM `items` WHERE `id` = ?")?;
+        statement.bind((1, item_id))?;
+        match statement.next()? {
+            State::Row => Ok(Some(read_item(&statement)?)),
+            State::Done => Ok(None),
+        }
+    }
+    fn get_items_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        let sql = "SELECT * FROM `items` WHERE `id` BETWEEN ? AND ? ORDER BY `id` LIMIT ?";
+        let mut statement = self.backend_client.prepare(sql)?;
+        statement.bind((1, *range.start()))?;
+        statement.bind((2, *range.end()))?;
+        statement.bind((3, limit as i64))?;
+        let mut items = vec![];
+        while let State::Row = statement.next()? {
+            items.push(read_item(&statement)?);
+        }
+        Ok(items)
+    }
+    /// Insert all items with a single multi-rows statement in one transaction
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        if items.is_empty() {
+            return Ok(0);
+        }
+        self.in_savepoint(|store| {
+            let batched_values = items
+                .iter()
+                .map(|i| i.to_sql_value())
+                .collect::<Vec<String>>()
+                .join(",");
+            let sql = format!(
+                "INSERT INTO `items` VALUES {} {}",
+                batched_values, ITEMS_UPSERT
+            );
+            store.backend_client.execute(sql)?;
+            let observed_at = now();
+            for item in &items {
+                store.record_version(item, observed_at)?;
+            }
+            Ok(items.len())
+        })
+    }
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        let mut stored = HashSet::new();
+        if ids.is_empty() {
+            return Ok(stored);
+        }
+        let in_list = ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        let mut statement = self.backend_client.prepare(format!(
+            "SELECT `id` FROM `items` WHERE `id` IN ({})",
+            in_list
+        ))?;
+        while let State::Row = statement.next()? {
+            stored.insert(statement.read::<i64, _>("id")?);
+        }
+        Ok(stored)
+    }
+    fn missing_in_range(&mut self, range: RangeInclusive<i64>) -> Result<Vec<i64>, CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT `id` FROM `items` WHERE `id` BETWEEN ? AND ?")?;
+        statement.bind((1, *range.start()))?;
+        statement.bind((2, *range.end()))?;
+        let mut stored = HashSet::new();
+        while let State::Row = statement.next()? {
+            stored.insert(statement.read::<i64, _>("id")?);
+        }
+        Ok(range.filter(|id| !stored.contains(id)).collect())
+    }
+    fn count(&mut self) -> Result<u64, CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT count(*) AS cnt FROM `items`")?;
+        statement.next()?;
+        Ok(statement.read::<i64, _>("cnt")? as u64)
+    }
+}
+
+/// Read the current row of an `items` statement
+fn read_item(statement: &sqlite::Statement) -> Result<Item, CrawlerError> {
+    let text = |column: &str| -> Result<String, CrawlerError> {
+        Ok(statement
+            .read::<Option<String>, _>(column)?
+            .unwrap_or_default())
+    };
+    Ok(Item {
+        id: statement.read::<i64, _>("id")?,
+        deleted: statement.read::<i64, _>("deleted")? != 0,
+        tp: text("type")?,
+        who: text("who")?,
+        time: statement.read::<i64, _>("time")?,
+        dead: statement.read::<i64, _>("dead")? != 0,
+        kids: Item::parse_kids(&text("kids")?),
+        title: text("title")?,
+        score: statement.read::<i64, _>("score")?,
+        text: text("content")?,
+        url: text("url")?,
+        parent: statement.read::<i64, _>("parent")?,
+        descendants: 0,
+    })
+}
+
+// Implement the retry queue in the `retry_queue` table
+impl GenericRetryQueue for Store<Connection> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        let sql = "SELECT * FROM `retry_queue` WHERE `id` = ?";
+        let mut statement = self.backend_client.prepare(sql)?;
+        statement.bind((1, item_id))?;
+        match statement.next()? {
+            State::Row => Ok(Some(read_retry_entry(&statement)?)),
+            State::Done => Ok(None),
+        }
+    }
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        let sql = "INSERT OR REPLACE INTO `retry_queue` \
+            (`id`, `attempts`, `last_error`, `next_attempt_at`, `state`) VALUES (?, ?, ?, ?, ?)";
+        let mut statement = self.backend_client.prepare(sql)?;
+        statement.bind((1, entry.item_id))?;
+        statement.bind((2, entry.attempts))?;
+        statement.bind((3, entry.last_error.as_str()))?;
+        statement.bind((4, entry.next_attempt_at))?;
+        statement.bind((5, entry.state.as_str()))?;
+        statement.next()?;
+        Ok(())
+    }
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("DELETE FROM `retry_queue` WHERE `id` = ?")?;
+        statement.bind((1, item_id))?;
+        statement.next()?;
+        Ok(())
+    }
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        let sql = "SELECT * FROM `retry_queue` \
+            WHERE `state` = ? AND `next_attempt_at` <= ? \
+            ORDER BY `next_attempt_at` LIMIT ?";
+        let mut statement = self.backend_client.prepare(sql)?;
+        statement.bind((1, RetryState::Pending.as_str()))?;
+        statement.bind((2, now))?;
+        statement.bind((3, limit as i64))?;
+        let mut entries = vec![];
+        while let State::Row = statement.next()? {
+            entries.push(read_retry_entry(&statement)?);
+        }
+        Ok(entries)
+    }
+}
+
+// Implement the checkpoints in the `checkpoints` table
+impl GenericCheckpoint for Store<Connection> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT `value` FROM `checkpoints` WHERE `name` = ?")?;
+        statement.bind((1, name))?;
+        match statement.next()? {
+            State::Row => Ok(Some(statement.read::<i64, _>("value")?)),
+            State::Done => Ok(None),
+        }
+    }
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        let mut statement = self.backend_client.prepare(
+            "INSERT OR REPLACE INTO `checkpoints` (`name`, `value`, `updated_at`) VALUES (?, ?, ?)",
+        )?;
+        statement.bind((1, name))?;
+        statement.bind((2, value))?;
+        statement.bind((3, now()))?;
+        statement.next()?;
+        Ok(())
+    }
+}
+
+// Implement the snapshots in the `item_snapshots` table
+impl GenericSnapshotStore for Store<Connection> {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("INSERT OR REPLACE INTO `item_snapshots` VALUES (?, ?, ?, ?)")?;
+        statement.bind((1, snapshot.item_id))?;
+        statement.bind((2, snapshot.observed_at))?;
+        statement.bind((3, snapshot.score))?;
+        statement.bind((4, snapshot.descendants))?;
+        statement.next()?;
+        Ok(())
+    }
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        let sql = "SELECT i.`id`, i.`time`, max(s.`observed_at`) AS last_observed_at \
+            FROM `items` i LEFT JOIN `item_snapshots` s ON s.`id` = i.`id` \
+            WHERE i.`type` = 'story' AND i.`time` >= ? \
+            GROUP BY i.`id`, i.`time`";
+        let mut statement = self.backend_client.prepare(sql)?;
+        statement.bind((1, since))?;
+        let mut candidates = vec![];
+        while let State::Row = statement.next()? {
+            candidates.push(RefreshCandidate {
+                item_id: statement.read::<i64, _>("id")?,
+                time: statement.read::<i64, _>("time")?,
+                last_observed_at: statement.read::<Option<i64>, _>("last_observed_at")?,
+            });
+        }
+        Ok(candidates)
+    }
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT * FROM `item_snapshots` WHERE `id` = ? ORDER BY `observed_at`")?;
+        statement.bind((1, item_id))?;
+        let mut snapshots = vec![];
+        while let State::Row = statement.next()? {
+            snapshots.push(ItemSnapshot {
+                item_id: statement.read::<i64, _>("id")?,
+                observed_at: statement.read::<i64, _>("observed_at")?,
+                score: statement.read::<i64, _>("score")?,
+                descendants: statement.read::<i64, _>("descendants")?,
+            });
+        }
+        Ok(snapshots)
+    }
+}
+
+// Implement the story lists observations in the `story_rankings` table
+impl GenericRankingStore for Store<Connection> {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        if snapshot.ids.is_empty() {
+            return Ok(());
+        }
+        let values = snapshot
+            .ids
+            .iter()
+            .enumerate()
+            .map(|(position, id)| {
+                format!(
+                    "('{}', {}, {}, {})",
+                    snapshot.list.as_str(),
+                    snapshot.observed_at,
+                    position,
+                    id
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        self.backend_client.execute(format!(
+            "INSERT OR REPLACE INTO `story_rankings` VALUES {}",
+            values
+        ))?;
+        Ok(())
+    }
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        let sql = "SELECT `observed_at`, `id` FROM `story_rankings` \
+            WHERE `list` = ?1 AND `observed_at` = ( \
+                SELECT max(`observed_at`) FROM `story_rankings` WHERE `list` = ?1 AND `observed_at` <= ?2) \
+            ORDER BY `position`";
+        let mut statement = self.backend_client.prepare(sql)?;
+        statement.bind((1, list.as_str()))?;
+        statement.bind((2, at))?;
+        let mut snapshot: Option<RankingSnapshot> = None;
+        while let State::Row = statement.next()? {
+            let observed_at = statement.read::<i64, _>("observed_at")?;
+            snapshot
+                .get_or_insert(RankingSnapshot {
+                    list,
+                    observed_at,
+                    ids: vec![],
+                })
+                .ids
+                .push(statement.read::<i64, _>("id")?);
+        }
+        Ok(snapshot)
+    }
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        let mut statement = self.backend_client.prepare(
+            "SELECT `observed_at`, `position` FROM `story_rankings` \
+            WHERE `list` = ? AND `id` = ? ORDER BY `observed_at`",
+        )?;
+        statement.bind((1, list.as_str()))?;
+        statement.bind((2, item_id))?;
+        let mut history = vec![];
+        while let State::Row = statement.next()? {
+            history.push(RankPosition {
+                observed_at: statement.read::<i64, _>("observed_at")?,
+                position: statement.read::<i64, _>("position")?,
+            });
+        }
+        Ok(history)
+    }
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        let mut statement = self.backend_client.prepare(
+            "SELECT DISTINCT `observed_at` FROM `story_rankings` WHERE `list` = ? ORDER BY `observed_at`")?;
+        statement.bind((1, list.as_str()))?;
+        let mut observations = vec![];
+        while let State::Row = statement.next()? {
+            observations.push(statement.read::<i64, _>("observed_at")?);
         }
+        Ok(observations)
     }
-    fn store_item(&mut self, item: Item) -> Result<bool, ()> {
-        let sql = format!("INSERT INTO `items` VALUES {}", item.to_sql_value());
-        self.backend_client.execute(sql).unwrap();
+}
+
+// Implement the item history in the `item_versions` table
+impl GenericItemHistory for Store<Connection> {
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError> {
+        let data = serde_json::to_string(item)?;
+        let mut latest = self.backend_client.prepare(
+            "SELECT `data` FROM `item_versions` WHERE `id` = ? \
+            ORDER BY `observed_at` DESC, rowid DESC LIMIT 1",
+        )?;
+        latest.bind((1, item.id))?;
+        let previous = match latest.next()? {
+            State::Row => Some(latest.read::<String, _>("data")?),
+            State::Done => None,
+        };
+        drop(latest);
+        if previous.as_ref() == Some(&data) {
+            return Ok(false);
+        }
+        let previous = previous.map(|p| Item::parse(item.id, &p)).transpose()?;
+        let existing = self.get_tombstone(item.id)?;
+        if let Some(tombstone) = Tombstone::track(existing, previous.as_ref(), item, observed_at) {
+            self.save_tombstone(&tombstone)?;
+        }
+        if let Some(entry) = OutboxEntry::track(previous.as_ref(), item, observed_at) {
+            let mut statement = self.backend_client.prepare(
+                "INSERT INTO `item_outbox` (`id`, `change_type`, `fields`, `changed_at`) VALUES (?, ?, ?, ?)")?;
+            statement.bind((1, entry.item_id))?;
+            statement.bind((2, entry.change.as_str()))?;
+            statement.bind((3, serde_json::to_string(&entry.fields)?.as_str()))?;
+            statement.bind((4, entry.changed_at))?;
+            statement.next()?;
+        }
+        let mut statement = self
+            .backend_client
+            .prepare("INSERT INTO `item_versions` VALUES (?, ?, ?)")?;
+        statement.bind((1, item.id))?;
+        statement.bind((2, observed_at))?;
+        statement.bind((3, data.as_str()))?;
+        statement.next()?;
         Ok(true)
     }
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        let mut statement = self.backend_client.prepare(
+            "SELECT `observed_at`, `data` FROM `item_versions` WHERE `id` = ? \
+            ORDER BY `observed_at`, rowid",
+        )?;
+        statement.bind((1, item_id))?;
+        let mut versions = vec![];
+        while let State::Row = statement.next()? {
+            versions.push(ItemVersion {
+                observed_at: statement.read::<i64, _>("observed_at")?,
+                item: Item::parse(item_id, &statement.read::<String, _>("data")?)?,
+            });
+        }
+        Ok(versions)
+    }
 }
 
-impl Store<Connection> {
-    fn store_items_batch(&mut self, items: Vec<Item>) {
-        let batched_values = items.iter()
-            .fold("".to_string(),
-                |i1, i2| 
-                format!("{},{}", i1, i2.to_sql_value()))
-            .split_at(1).1 // Remove surplus of delimeters in the first time folding
-            .to_string();
-        let sql = format!("INSERT INTO `items` VALUES {}", batched_values);
-        self.backend_client.execute(sql).unwrap();
-    }
-}
-
-// This implementation will override the GenericCrawlerFlow above
-impl ItemsCrawler<Store<Connection>> {
-    pub fn run_many(&mut self) {
-        let latest_item_id = self.fetch_latest_item().unwrap();
-        let mut last_item_id = self.client.get_last_item().unwrap();
-        let mut counter: u16 = 0;
-        while (latest_item_id > last_item_id) && (counter < *MAX_BATCH_ITEMS) {
-            let item = self.fetch_item(last_item_id).unwrap();
-            self.client.store_item(item).unwrap();
-            last_item_id += 1;
-            counter += 1;
-        }
-        println!("Inserted {counter} items!")
-    }
-
-    /// Fetch items synchronously and insert into Sqlite in a batched of all items
-    pub fn run_many_insert_batch(&mut self) {
-        let latest_item_id = self.fetch_latest_item().unwrap();
-        let mut last_item_id = self.client.get_last_item().unwrap();
-        let mut counter = 0;
-        let mut items: Vec<Item> = vec![];
-        while (latest_item_id > last_item_id) && (counter < MAX_BATCH_ITEMS.clone()) {
-            let item= self.fetch_item(last_item_id).unwrap();
-            items.push(item);
-            last_item_id += 1;
-            counter += 1;
-        }
-        self.client.store_items_batch(items);
-        println!("Inserted {counter} items!")
+// Implement the change feed with the rowid of `item_versions`, which only grows as the
+// versions are never deleted
+impl GenericChangeFeed for Store<Connection> {
+    fn changes_since(&mut self, after_seq: i64, limit: usize) -> Result<Vec<Change>, CrawlerError> {
+        let mut statement = self.backend_client.prepare(
+            "SELECT rowid AS `seq`, `id`, `observed_at`, `data` FROM `item_versions` \
+            WHERE rowid > ? ORDER BY rowid LIMIT ?",
+        )?;
+        statement.bind((1, after_seq))?;
+        statement.bind((2, limit as i64))?;
+        let mut changes = vec![];
+        while let State::Row = statement.next()? {
+            changes.push(Change {
+                seq: statement.read::<i64, _>("seq")?,
+                observed_at: statement.read::<i64, _>("observed_at")?,
+                item: Item::parse(
+                    statement.read::<i64, _>("id")?,
+                    &statement.read::<String, _>("data")?,
+                )?,
+            });
+        }
+        Ok(changes)
+    }
+}
+
+// Implement the outbox in the `item_outbox` table, appended by `record_version`
+impl GenericOutbox for Store<Connection> {
+    fn outbox_since(
+        &mut self,
+        after_seq: i64,
+        limit: usize,
+    ) -> Result<Vec<OutboxEntry>, CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT * FROM `item_outbox` WHERE `seq` > ? ORDER BY `seq` LIMIT ?")?;
+        statement.bind((1, after_seq))?;
+        statement.bind((2, limit as i64))?;
+        let mut entries = vec![];
+        while let State::Row = statement.next()? {
+            let change = statement.read::<String, _>("change_type")?;
+            entries.push(OutboxEntry {
+                seq: statement.read::<i64, _>("seq")?,
+                item_id: statement.read::<i64, _>("id")?,
+                change: ChangeType::parse(&change).ok_or_else(|| CrawlerError::Store {
+                    reason: format!("unknown change type {change:?}"),
+                })?,
+                fields: serde_json::from_str(&statement.read::<String, _>("fields")?)?,
+                changed_at: statement.read::<i64, _>("changed_at")?,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+// Implement the search with the FTS5 index `items_fts`
+impl GenericSearch for Store<Connection> {
+    fn search(&mut self, query: &SearchQuery) -> Result<Vec<SearchHit>, CrawlerError> {
+        // Every word is quoted so the FTS5 syntax (`OR`, `-`, `*`, ...) of the user is not interpreted
+        let terms = query
+            .words()?
+            .iter()
+            .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mut statement = self.backend_client.prepare(format!(
+            "SELECT i.`id`, i.`type`, i.`who`, i.`time`, i.`title`, -bm25(`items_fts`, 2.0, 1.0) AS `rank`, \
+            snippet(`items_fts`, -1, '{HIGHLIGHT_START}', '{HIGHLIGHT_END}', '...', 16) AS `snippet` \
+            FROM `items_fts` JOIN `items` i ON i.`id` = `items_fts`.rowid \
+            WHERE `items_fts` MATCH :terms \
+            AND (:type IS NULL OR i.`type` = :type) AND (:author IS NULL OR i.`who` = :author) \
+            AND (:since IS NULL OR i.`time` >= :since) AND (:until IS NULL OR i.`time` <= :until) \
+            ORDER BY bm25(`items_fts`, 2.0, 1.0) LIMIT :limit"))?;
+        statement.bind((":terms", terms.as_str()))?;
+        statement.bind((":type", query.tp.as_deref()))?;
+        statement.bind((":author", query.author.as_deref()))?;
+        statement.bind((":since", query.since))?;
+        statement.bind((":until", query.until))?;
+        statement.bind((":limit", query.limit as i64))?;
+        let mut hits = vec![];
+        while let State::Row = statement.next()? {
+            hits.push(SearchHit {
+                item_id: statement.read::<i64, _>("id")?,
+                tp: statement
+                    .read::<Option<String>, _>("type")?
+                    .unwrap_or_default(),
+                who: statement
+                    .read::<Option<String>, _>("who")?
+                    .unwrap_or_default(),
+                time: statement
+                    .read::<Option<i64>, _>("time")?
+                    .unwrap_or_default(),
+                title: statement
+                    .read::<Option<String>, _>("title")?
+                    .unwrap_or_default(),
+                rank: statement.read::<f64, _>("rank")?,
+                snippet: statement
+                    .read::<Option<String>, _>("snippet")?
+                    .unwrap_or_default(),
+            });
+        }
+        Ok(hits)
+    }
+}
+
+// Implement the tombstones in the `item_tombstones` table
+impl GenericTombstoneStore for Store<Connection> {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT * FROM `item_tombstones` WHERE `id` = ?")?;
+        statement.bind((1, item_id))?;
+        match statement.next()? {
+            State::Row => Ok(Some(Tombstone {
+                item_id,
+                deleted_at: statement.read::<Option<i64>, _>("deleted_at")?,
+                dead_at: statement.read::<Option<i64>, _>("dead_at")?,
+                who: statement
+                    .read::<Option<String>, _>("who")?
+                    .unwrap_or_default(),
+                title: statement
+                    .read::<Option<String>, _>("title")?
+                    .unwrap_or_default(),
+                text: statement
+                    .read::<Option<String>, _>("content")?
+                    .unwrap_or_default(),
+                url: statement
+                    .read::<Option<String>, _>("url")?
+                    .unwrap_or_default(),
+            })),
+            State::Done => Ok(None),
+        }
+    }
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        let mut statement = self
+            .backend_client
+            .prepare("INSERT OR REPLACE INTO `item_tombstones` VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+        statement.bind((1, tombstone.item_id))?;
+        statement.bind((2, tombstone.deleted_at))?;
+        statement.bind((3, tombstone.dead_at))?;
+        statement.bind((4, tombstone.who.as_str()))?;
+        statement.bind((5, tombstone.title.as_str()))?;
+        statement.bind((6, tombstone.text.as_str()))?;
+        statement.bind((7, tombstone.url.as_str()))?;
+        statement.next()?;
+        Ok(())
+    }
+}
+
+// Implement the migrations with the `schema_version` table
+impl GenericMigrations for Store<Connection> {
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+    fn applied_migrations(&mut self) -> Result<Vec<AppliedMigration>, CrawlerError> {
+        self.backend_client.execute(
+            r###"
+            CREATE TABLE IF NOT EXISTS `schema_version` (
+              `version` int(10) NOT NULL PRIMARY KEY,
+              `name` varchar(255) NOT NULL,
+              `applied_at` int(11) NOT NULL
+            )"###,
+        )?;
+        let mut statement = self
+            .backend_client
+            .prepare("SELECT * FROM `schema_version` ORDER BY `version`")?;
+        let mut applied = vec![];
+        while let State::Row = statement.next()? {
+            applied.push(AppliedMigration {
+                version: statement.read::<i64, _>("version")?,
+                name: statement.read::<String, _>("name")?,
+                applied_at: statement.read::<i64, _>("applied_at")?,
+            });
+        }
+        Ok(applied)
+    }
+    fn apply_migration(&mut self, migration: &Migration) -> Result<bool, CrawlerError> {
+        // The write lock is taken upfront, so a concurrent crawler waits and then skips it
+        self.backend_client.execute("BEGIN IMMEDIATE")?;
+        let applied = (|| {
+            let mut statement = self
+                .backend_client
+                .prepare("SELECT 1 FROM `schema_version` WHERE `version` = ?")?;
+            statement.bind((1, migration.version))?;
+            if let State::Row = statement.next()? {
+                return Ok(false);
+            }
+            self.backend_client.execute(migration.sql)?;
+            let mut statement = self.backend_client.prepare(
+                "INSERT INTO `schema_version` (`version`, `name`, `applied_at`) VALUES (?, ?, ?)",
+            )?;
+            statement.bind((1, migration.version))?;
+            statement.bind((2, migration.name))?;
+            statement.bind((3, now()))?;
+            statement.next()?;
+            Ok(true)
+        })();
+        match applied {
+            Ok(applied) => {
+                self.backend_client.execute("COMMIT")?;
+                Ok(applied)
+            }
+            Err(e) => {
+                let _ = self.backend_client.execute("ROLLBACK");
+                Err(e)
+            }
+        }
     }
 }
 
+/// Read the current row of a `retry_queue` statement
+fn read_retry_entry(statement: &sqlite::Statement) -> Result<RetryEntry, CrawlerError> {
+    let state = statement.read::<String, _>("state")?;
+    Ok(RetryEntry {
+        item_id: statement.read::<i64, _>("id")?,
+        attempts: statement.read::<i64, _>("attempts")?,
+        last_error: statement
+            .read::<Option<String>, _>("last_error")?
+            .unwrap_or_default(),
+        next_attempt_at: statement.read::<i64, _>("next_attempt_at")?,
+        state: RetryState::parse(&state).ok_or_else(|| CrawlerError::Store {
+            reason: format!("unknown retry state {state:?}"),
+        })?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use sqlite::{Connection, State};
-    use crate::crawler::{GenericCrawlerFlow, ItemsCrawler};
+    use crate::crawler::GenericStoreItem;
+    use crate::crawler::{GenericCheckpoint, GenericCrawlerFlow, ItemsCrawler};
+    use crate::error::CrawlerError;
+    use crate::ext::sqlite::SqlitePragmas;
+    use crate::history::GenericItemHistory;
     use crate::hub::NewsHub;
+    use crate::item::Item;
+    use crate::migrate::GenericMigrations;
+    use crate::ranking::{ranked_duration, GenericRankingStore, RankingSnapshot, StoryList};
+    use crate::refresh::{GenericSnapshotStore, ItemSnapshot};
+    use crate::retry::{GenericRetryQueue, RetryState};
     use crate::store::Store;
-    use crate::utils::CRAWLER_HUB;
+    use crate::testing::TempDir;
+    use crate::tombstone::{GenericTombstoneStore, TombstonePolicy};
+    use crate::utils::{CRAWLER_HUB, MAX_RETRY_ATTEMPTS};
+    use sqlite::{Connection, State};
 
     fn mock_sqlite_memory() -> Store<Connection> {
-        let url = format!(":memory:");
+        let url = String::from(":memory:");
         Store::<Connection>::new(&url)
     }
 
     fn mock_tmp_db() -> Store<Connection> {
-        let url = format!("resources/items.db");
+        let url = String::from("resources/items.db");
         Store::<Connection>::new(&url)
     }
 
+    #[test]
+    fn test_pragmas_and_concurrent_reader() {
+        assert!(SqlitePragmas::new("wal; DROP TABLE items", "normal", 0, 0, 0).is_err());
+        assert!(SqlitePragmas::new("wal", "sometimes", 0, 0, 0).is_err());
+
+        let dir = TempDir::new("sqlite-wal");
+        let path = dir.join("items.db");
+        let pragmas = SqlitePragmas::new("WAL", "normal", -2000, 1 << 20, 100).unwrap();
+        let mut store_client = Store::<Connection>::open_with(&path, &pragmas).unwrap();
+        store_client.migrate_up().unwrap();
+        let pragma = |conn: &Connection, name: &str| {
+            let mut statement = conn.prepare(format!("PRAGMA {name}")).unwrap();
+            statement.next().unwrap();
+            statement.read::<String, _>(0).unwrap()
+        };
+        assert_eq!(pragma(&store_client.backend_client, "journal_mode"), "wal");
+        assert_eq!(pragma(&store_client.backend_client, "synchronous"), "1");
+        assert_eq!(pragma(&store_client.backend_client, "cache_size"), "-2000");
+
+        // A reader in the middle of a read transaction doesn't block the writer
+        let mut reader = Store::<Connection>::open_with(&path, &pragmas).unwrap();
+        reader
+            .backend_client
+            .execute("BEGIN; SELECT count(*) FROM `items`;")
+            .unwrap();
+        let items: Vec<Item> = (1..=3)
+            .map(|id| Item::from(format!(r#"{{"id": {id}, "type": "comment", "time": 1}}"#)))
+            .collect();
+        assert_eq!(store_client.store_items(items).unwrap(), 3);
+        reader.backend_client.execute("COMMIT").unwrap();
+        assert_eq!(reader.count().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_backup_and_vacuum_into() {
+        let dir = TempDir::new("sqlite-backup");
+        let mut store_client = Store::<Connection>::new(&dir.join("items.db"));
+        let items: Vec<Item> = (1..=50)
+            .map(|id| {
+                Item::from(format!(
+                    r#"{{"id": {id}, "type": "comment", "time": 1, "text": "{}"}}"#,
+                    "x".repeat(200)
+                ))
+            })
+            .collect();
+        store_client.store_items(items).unwrap();
+
+        // One page per step, as a live store would be copied
+        let backup = dir.path().join("backup.db");
+        assert!(
+            store_client
+                .backup_to(&backup, 1, std::time::Duration::ZERO, true)
+                .unwrap()
+                > 0
+        );
+        let compacted = dir.path().join("compacted.db");
+        store_client.vacuum_into(&compacted, true).unwrap();
+        for copy in [&backup, &compacted] {
+            let mut copy = Store::<Connection>::open(&copy.to_string_lossy()).unwrap();
+            assert_eq!(copy.count().unwrap(), 50);
+            assert_eq!(copy.schema_version().unwrap(), 7);
+        }
+        assert!(!dir.path().join("backup.db.partial").exists());
+
+        let corrupt = dir.path().join("corrupt.db");
+        let mut data = std::fs::read(&backup).unwrap();
+        data.truncate(data.len() / 2);
+        std::fs::write(&corrupt, data).unwrap();
+        assert!(Store::<Connection>::integrity_check(&corrupt)
+            .map_or(true, |problems| !problems.is_empty()));
+    }
+
+    #[test]
+    fn test_migrations_sqlite() {
+        let mut store_client = Store::<Connection>::open(":memory:").unwrap();
+        // A database created before the migrations, by the former `CREATE TABLE IF NOT EXISTS`
+        store_client
+            .backend_client
+            .execute(super::MIGRATIONS[0].sql)
+            .unwrap();
+        // which let an id be inserted twice, the latest row is kept
+        let item = Item::from(String::from(
+            r#"{"id": 1, "type": "story", "by": "pg", "time": 1, "title": "Y"}"#,
+        ));
+        let mut former = item.clone();
+        former.title = String::from("X");
+        for row in [&former, &item] {
+            store_client
+                .backend_client
+                .execute(format!("INSERT INTO `items` VALUES {}", row.to_sql_value()))
+                .unwrap();
+        }
+        assert_eq!(store_client.schema_version().unwrap(), 0);
+        assert!(store_client
+            .migration_status()
+            .unwrap()
+            .iter()
+            .all(|(_, applied)| applied.is_none()));
+
+        assert_eq!(
+            store_client.migrate_up().unwrap(),
+            vec![1, 2, 3, 4, 5, 6, 7]
+        );
+        assert_eq!(store_client.schema_version().unwrap(), 7);
+        assert!(store_client.migrate_up().unwrap().is_empty());
+        let mut statement = store_client.backend_client
+            .prepare("SELECT count(*) AS n FROM sqlite_master WHERE type = 'index' AND name LIKE 'items_%'").unwrap();
+        statement.next().unwrap();
+        assert_eq!(statement.read::<i64, _>("n").unwrap(), 4);
+        drop(statement);
+        // The existing data is kept, an id is stored once
+        assert_eq!(store_client.get_item(1).unwrap(), Some(item.clone()));
+        assert_eq!(store_client.count().unwrap(), 1);
+        store_client
+            .store_items(vec![item, former.clone()])
+            .unwrap();
+        assert_eq!(store_client.count().unwrap(), 1);
+        assert_eq!(store_client.get_item(1).unwrap(), Some(former));
+    }
+
     #[test]
     fn test_run_one() {
-        let hub = NewsHub::new(&**CRAWLER_HUB);
+        let hub = NewsHub::new(&CRAWLER_HUB);
         let store_client = mock_tmp_db();
         let mut crawler = ItemsCrawler::new(hub, store_client);
         let _ = crawler.run_one();
@@ -132,7 +1235,7 @@ mod tests {
 
     #[test]
     fn test_run_many() {
-        let hub = NewsHub::new(&**CRAWLER_HUB);
+        let hub = NewsHub::new(&CRAWLER_HUB);
         let store_client = mock_tmp_db();
         let mut crawler = ItemsCrawler::new(hub, store_client);
         let _ = crawler.run_many();
@@ -140,23 +1243,21 @@ mod tests {
 
     #[test]
     fn test_run_many_insert_batch() {
-        let hub = NewsHub::new(&**CRAWLER_HUB);
+        let hub = NewsHub::new(&CRAWLER_HUB);
         let store_client = mock_tmp_db();
         let mut crawler = ItemsCrawler::new(hub, store_client);
         let _ = crawler.run_many_insert_batch();
-
     }
 
     #[test]
     fn test_init_sql_store() {
         let store_client = mock_sqlite_memory();
-        let query =
-            "SELECT count(1) \
+        let query = "SELECT count(1) \
             as cnt FROM sqlite_master \
             where \
                 type='table' \
                 and name='items'";
-        let mut statement =store_client.backend_client.prepare(query).unwrap();
+        let mut statement = store_client.backend_client.prepare(query).unwrap();
         let mut is_existed: bool = false;
         match statement.next().unwrap() {
             State::Row => {
@@ -170,13 +1271,12 @@ mod tests {
     #[test]
     fn test_init_tmp_store() {
         let store_client = mock_tmp_db();
-        let query =
-            "SELECT count(1) \
+        let query = "SELECT count(1) \
             as cnt FROM sqlite_master \
             where \
                 type='table' \
                 and name='items'";
-        let mut statement =store_client.backend_client.prepare(query).unwrap();
+        let mut statement = store_client.backend_client.prepare(query).unwrap();
         let mut is_existed: bool = false;
         match statement.next().unwrap() {
             State::Row => {
@@ -186,5 +1286,240 @@ mod tests {
         }
         assert!(is_existed);
     }
-}
 
+    #[test]
+    fn test_retry_queue() {
+        let hub = NewsHub::new(&CRAWLER_HUB);
+        let mut crawler = ItemsCrawler::new(hub, mock_sqlite_memory());
+        let error = CrawlerError::EmptyItem { item_id: 42 };
+        let entry = crawler.record_failure(42, &error).unwrap();
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(crawler.client.get_retry(42).unwrap(), Some(entry.clone()));
+
+        // Not due yet because of the backoff
+        assert!(crawler
+            .client
+            .due_retries(entry.next_attempt_at - 1, 10)
+            .unwrap()
+            .is_empty());
+        let due = crawler
+            .client
+            .due_retries(entry.next_attempt_at, 10)
+            .unwrap();
+        assert_eq!(due, vec![entry]);
+
+        crawler.client.remove_retry(42).unwrap();
+        assert_eq!(crawler.client.get_retry(42).unwrap(), None);
+    }
+
+    #[test]
+    fn test_retry_queue_dead_letter() {
+        let hub = NewsHub::new(&CRAWLER_HUB);
+        let mut crawler = ItemsCrawler::new(hub, mock_sqlite_memory());
+        let error = CrawlerError::Timeout { item_id: 7 };
+        for _ in 0..*MAX_RETRY_ATTEMPTS {
+            crawler.record_failure(7, &error).unwrap();
+        }
+        let entry = crawler.client.get_retry(7).unwrap().unwrap();
+        assert_eq!(entry.state, RetryState::Dead);
+        assert_eq!(entry.last_error, error.to_string());
+        assert!(crawler.client.due_retries(i64::MAX, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_retry_queue_unknown_state() {
+        let mut store_client = mock_sqlite_memory();
+        store_client
+            .backend_client
+            .execute("INSERT INTO retry_queue VALUES (9, 1, 'boom', 0, 'pendng')")
+            .unwrap();
+        assert!(matches!(
+            store_client.get_retry(9),
+            Err(CrawlerError::Store { .. })
+        ));
+        // Nor is it retried
+        assert!(store_client.due_retries(i64::MAX, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let mut store_client = mock_sqlite_memory();
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), None);
+        store_client.save_checkpoint("sync", 10).unwrap();
+        store_client.save_checkpoint("sync", 20).unwrap();
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), Some(20));
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let mut store_client = mock_sqlite_memory();
+        let story = Item::from(String::from(
+            r#"{"id": 1, "type": "story", "time": 1000, "score": 3, "descendants": 2}"#,
+        ));
+        let comment = Item::from(String::from(
+            r#"{"id": 2, "type": "comment", "time": 1000}"#,
+        ));
+        store_client.store_item(story).unwrap();
+        store_client.store_item(comment).unwrap();
+
+        let candidates = store_client.refresh_candidates(0).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].last_observed_at, None);
+
+        let snapshot = ItemSnapshot {
+            item_id: 1,
+            observed_at: 1300,
+            score: 5,
+            descendants: 4,
+        };
+        store_client.save_snapshot(&snapshot).unwrap();
+        assert_eq!(
+            store_client.refresh_candidates(0).unwrap()[0].last_observed_at,
+            Some(1300)
+        );
+        assert_eq!(store_client.get_snapshots(1).unwrap(), vec![snapshot]);
+        assert!(store_client.refresh_candidates(2000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rankings() {
+        let mut store_client = mock_sqlite_memory();
+        let first = RankingSnapshot {
+            list: StoryList::Top,
+            observed_at: 100,
+            ids: vec![3, 1, 2],
+        };
+        let second = RankingSnapshot {
+            list: StoryList::Top,
+            observed_at: 200,
+            ids: vec![1, 4],
+        };
+        let third = RankingSnapshot {
+            list: StoryList::Top,
+            observed_at: 300,
+            ids: vec![4],
+        };
+        for snapshot in [&first, &second, &third] {
+            store_client.save_ranking(snapshot).unwrap();
+        }
+
+        assert_eq!(store_client.ranking_at(StoryList::Top, 50).unwrap(), None);
+        assert_eq!(
+            store_client.ranking_at(StoryList::Top, 250).unwrap(),
+            Some(second)
+        );
+        assert_eq!(store_client.ranking_at(StoryList::Best, 250).unwrap(), None);
+
+        let history = store_client.ranking_history(StoryList::Top, 1).unwrap();
+        assert_eq!(
+            history.iter().map(|p| p.position).collect::<Vec<i64>>(),
+            vec![1, 0]
+        );
+        let observations = store_client.ranking_observations(StoryList::Top).unwrap();
+        assert_eq!(ranked_duration(&observations, &history), 200);
+
+        store_client
+            .store_item(Item::from(String::from(
+                r#"{"id": 3, "type": "story", "time": 1}"#,
+            )))
+            .unwrap();
+        assert_eq!(store_client.contains(&[1, 2, 3, 4]).unwrap(), [3].into());
+    }
+
+    #[test]
+    fn test_item_history() {
+        let mut store_client = mock_sqlite_memory();
+        let mut item = Item::from(String::from(
+            r#"{"id": 1, "type": "story", "time": 1, "title": "Show HN"}"#,
+        ));
+        assert!(store_client.record_version(&item, 100).unwrap());
+        assert!(!store_client.record_version(&item, 200).unwrap());
+        item.title = String::from("Show HN: edited");
+        assert!(store_client.record_version(&item, 300).unwrap());
+        item.dead = true;
+        assert!(store_client.record_version(&item, 400).unwrap());
+
+        assert_eq!(store_client.item_versions(1).unwrap().len(), 3);
+        assert_eq!(store_client.item_as_of(1, 50).unwrap(), None);
+        assert_eq!(
+            store_client.item_as_of(1, 250).unwrap().unwrap().title,
+            "Show HN"
+        );
+        let latest = store_client.item_as_of(1, 1000).unwrap().unwrap();
+        assert_eq!(latest, item);
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let mut store_client = mock_sqlite_memory();
+        let alive = Item::from(String::from(
+            r#"{"id": 5, "type": "comment", "time": 1, "by": "pg", "text": "first!"}"#,
+        ));
+        let deleted = Item::from(String::from(
+            r#"{"id": 5, "type": "comment", "time": 1, "deleted": true}"#,
+        ));
+        store_client.record_version(&alive, 100).unwrap();
+        assert_eq!(store_client.get_tombstone(5).unwrap(), None);
+        store_client.record_version(&deleted, 200).unwrap();
+
+        let tombstone = store_client.get_tombstone(5).unwrap().unwrap();
+        assert_eq!(tombstone.deleted_at, Some(200));
+        assert_eq!(tombstone.text, "first!");
+
+        let exported = |store_client: &mut Store<Connection>, policy| {
+            store_client.export_item(deleted.clone(), policy).unwrap()
+        };
+        assert_eq!(
+            exported(&mut store_client, TombstonePolicy::Include),
+            Some(deleted.clone())
+        );
+        assert_eq!(exported(&mut store_client, TombstonePolicy::Exclude), None);
+        let restored = exported(&mut store_client, TombstonePolicy::Restore).unwrap();
+        assert_eq!(
+            (restored.who.as_str(), restored.text.as_str()),
+            ("pg", "first!")
+        );
+    }
+
+    #[test]
+    fn test_store_items_batch() {
+        let mut store_client = mock_sqlite_memory();
+        let items: Vec<Item> = (1..=3)
+            .map(|id| {
+                Item::from(format!(
+                    r#"{{"id": {id}, "type": "comment", "time": 1, "text": "it's {id}"}}"#
+                ))
+            })
+            .collect();
+        assert_eq!(store_client.store_items(items).unwrap(), 3);
+        assert_eq!(store_client.store_items(vec![]).unwrap(), 0);
+        assert_eq!(store_client.get_last_item().unwrap(), 3);
+        assert_eq!(store_client.item_versions(2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_store_reads() {
+        let mut store_client = mock_sqlite_memory();
+        let item = |id: i64, title: &str| {
+            Item::from(format!(
+                r#"{{"id": {id}, "type": "story", "time": 1, "title": "{title}", "kids": [{}], "score": 2}}"#,
+                id + 1
+            ))
+        };
+        let items: Vec<Item> = [1, 2, 4, 7].iter().map(|id| item(*id, "first")).collect();
+        store_client.store_items(items).unwrap();
+
+        assert_eq!(store_client.get_item(4).unwrap(), Some(item(4, "first")));
+        assert_eq!(store_client.get_item(3).unwrap(), None);
+        store_client.upsert_item(item(4, "edited")).unwrap();
+        store_client.upsert_item(item(5, "new")).unwrap();
+        assert_eq!(store_client.get_item(4).unwrap().unwrap().title, "edited");
+
+        assert_eq!(store_client.count().unwrap(), 5);
+        assert_eq!(store_client.contains(&[1, 3, 5]).unwrap(), [1, 5].into());
+        assert_eq!(store_client.missing_in_range(1..=8).unwrap(), vec![3, 6, 8]);
+        let scanned: Vec<i64> = store_client.scan(2..=5).map(|i| i.unwrap().id).collect();
+        assert_eq!(scanned, vec![2, 4, 5]);
+        assert_eq!(store_client.get_items_range(1..=7, 2).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,562 @@
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::ext::file::write_atomic;
+use crate::history::{GenericItemHistory, ItemVersion};
+use crate::item::Item;
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::retry::{GenericRetryQueue, RetryEntry, RetryState};
+use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone, TombstonePolicy};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// Columns of the CSV and TSV outputs, `kids` is a JSON array
+const COLUMNS: [&str; 13] = [
+    "id",
+    "deleted",
+    "type",
+    "by",
+    "time",
+    "dead",
+    "kids",
+    "title",
+    "score",
+    "text",
+    "url",
+    "parent",
+    "descendants",
+];
+
+/// Line format of the stdout store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One JSON object per line
+    Json,
+    /// RFC 4180 CSV with a header line
+    Csv,
+    /// Tab separated values with a header line, tabs, newlines and backslashes are escaped
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            "tsv" => Some(OutputFormat::Tsv),
+            _ => None,
+        }
+    }
+
+    fn field(&self, value: &str) -> String {
+        match self {
+            OutputFormat::Csv if value.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            }
+            OutputFormat::Tsv => value
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r"),
+            _ => value.to_string(),
+        }
+    }
+
+    fn separator(&self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "\t",
+            _ => ",",
+        }
+    }
+
+    fn header(&self) -> Option<String> {
+        match self {
+            OutputFormat::Json => None,
+            _ => Some(COLUMNS.join(self.separator())),
+        }
+    }
+
+    fn line(&self, item: &Item) -> Result<String, CrawlerError> {
+        if *self == OutputFormat::Json {
+            return Ok(serde_json::to_string(item)?);
+        }
+        let row = [
+            item.id.to_string(),
+            item.deleted.to_string(),
+            item.tp.clone(),
+            item.who.clone(),
+            item.time.to_string(),
+            item.dead.to_string(),
+            serde_json::to_string(&item.kids)?,
+            item.title.clone(),
+            item.score.to_string(),
+            item.text.clone(),
+            item.url.clone(),
+            item.parent.to_string(),
+            item.descendants.to_string(),
+        ];
+        Ok(row
+            .iter()
+            .map(|value| self.field(value))
+            .collect::<Vec<String>>()
+            .join(self.separator()))
+    }
+}
+
+/// What the stdout store remembers between invocations
+#[derive(Serialize, Deserialize, Default)]
+struct Cursor {
+    last_item: i64,
+    checkpoints: BTreeMap<String, i64>,
+    retries: BTreeMap<i64, RetryEntry>,
+    /// The emitted ids as runs of consecutive ids, the first id of a run to its last one
+    #[serde(default)]
+    emitted: BTreeMap<i64, i64>,
+}
+
+impl Cursor {
+    /// Add `id` to the emitted runs, merging it with its neighbours
+    fn emit(&mut self, id: i64) {
+        if self.was_emitted(id) {
+            return;
+        }
+        let start = match self.emitted.range(..id).next_back() {
+            Some((start, end)) if *end == id - 1 => *start,
+            _ => id,
+        };
+        let end = match self.emitted.remove(&(id + 1)) {
+            Some(end) => end,
+            None => id,
+        };
+        self.emitted.insert(start, end);
+    }
+
+    fn was_emitted(&self, id: i64) -> bool {
+        self.emitted
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(_, end)| *end >= id)
+    }
+}
+
+/// Streams the stored items to stdout, one per line, for Unix pipelines.
+/// Only the crawl position and the emitted ids are kept, in an optional cursor file,
+/// so that repeated invocations only emit the new items. Nothing can be read back
+pub struct StdoutClient {
+    out: Box<dyn Write>,
+    format: OutputFormat,
+    cursor_path: Option<PathBuf>,
+    cursor: Cursor,
+    header_written: bool,
+    /// How the deleted and dead items are emitted, the store keeps no tombstone to restore
+    tombstones: TombstonePolicy,
+}
+
+impl StdoutClient {
+    pub fn open(
+        out: Box<dyn Write>,
+        format: OutputFormat,
+        cursor_path: Option<PathBuf>,
+        tombstones: TombstonePolicy,
+    ) -> Result<Self, CrawlerError> {
+        let mut cursor: Cursor = match cursor_path.as_ref().map(fs::read) {
+            Some(Ok(data)) => serde_json::from_slice(&data)?,
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => Cursor::default(),
+        };
+        // The cursors written before the emitted ids were kept
+        if cursor.emitted.is_empty() && cursor.last_item > 0 {
+            cursor.emitted.insert(1, cursor.last_item);
+        }
+        Ok(Self {
+            out,
+            format,
+            cursor_path,
+            cursor,
+            header_written: false,
+            tombstones,
+        })
+    }
+
+    /// Write `items` and flush them as one batch, then move the cursor past `ids`,
+    /// the ids of the items handled including the ones left out of the export
+    fn write(&mut self, items: &[Item], ids: &[i64]) -> Result<(), CrawlerError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        if !self.header_written && !items.is_empty() {
+            if let Some(header) = self.format.header() {
+                lines.push_str(&header);
+                lines.push('\n');
+            }
+        }
+        for item in items {
+            lines.push_str(&self.format.line(item)?);
+            lines.push('\n');
+        }
+        self.out.write_all(lines.as_bytes())?;
+        self.out.flush()?;
+        self.header_written |= !items.is_empty();
+        self.cursor.last_item = ids.iter().copied().fold(self.cursor.last_item, i64::max);
+        ids.iter().for_each(|id| self.cursor.emit(*id));
+        self.save_cursor()
+    }
+
+    fn save_cursor(&self) -> Result<(), CrawlerError> {
+        match &self.cursor_path {
+            Some(path) => write_atomic(path, &serde_json::to_vec(&self.cursor)?),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Store<StdoutClient> {
+    /// Write to stdout, keeping the cursor in `cursor_path` unless it is empty
+    pub fn new(cursor_path: &str, format: OutputFormat, tombstones: TombstonePolicy) -> Self {
+        let cursor_path =
+            Some(PathBuf::from(cursor_path.trim())).filter(|path| !path.as_os_str().is_empty());
+        Self {
+            backend_client: StdoutClient::open(
+                Box::new(io::stdout()),
+                format,
+                cursor_path,
+                tombstones,
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Write the export of `items` following the tombstone policy
+    fn emit(&mut self, items: Vec<Item>) -> Result<(), CrawlerError> {
+        let ids: Vec<i64> = items.iter().map(|i| i.id).collect();
+        let policy = self.backend_client.tombstones;
+        let mut exported = vec![];
+        for item in items {
+            exported.extend(self.export_item(item, policy)?);
+        }
+        self.backend_client.write(&exported, &ids)
+    }
+}
+
+impl GenericStoreItem for Store<StdoutClient> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        Ok(self.backend_client.cursor.last_item)
+    }
+
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.emit(vec![item])?;
+        Ok(true)
+    }
+
+    fn upsert_item(&mut self, item: Item) -> Result<(), CrawlerError> {
+        self.emit(vec![item])
+    }
+
+    fn get_item(&mut self, _item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        Ok(None)
+    }
+
+    fn get_items_range(
+        &mut self,
+        _range: RangeInclusive<i64>,
+        _limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        Ok(vec![])
+    }
+
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        let count = items.len();
+        self.emit(items)?;
+        Ok(count)
+    }
+
+    /// The ids already emitted, so the rankings do not emit their stories again
+    fn contains(&mut self, ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        Ok(ids
+            .iter()
+            .copied()
+            .filter(|id| self.backend_client.cursor.was_emitted(*id))
+            .collect())
+    }
+}
+
+impl GenericCheckpoint for Store<StdoutClient> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        Ok(self.backend_client.cursor.checkpoints.get(name).copied())
+    }
+
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        self.backend_client
+            .cursor
+            .checkpoints
+            .insert(name.to_string(), value);
+        self.backend_client.save_cursor()
+    }
+}
+
+/// The retry queue lives in the cursor, so the failed items are emitted by a later `retry_failed`
+impl GenericRetryQueue for Store<StdoutClient> {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        Ok(self.backend_client.cursor.retries.get(&item_id).cloned())
+    }
+
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError> {
+        self.backend_client
+            .cursor
+            .retries
+            .insert(entry.item_id, entry.clone());
+        self.backend_client.save_cursor()
+    }
+
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError> {
+        self.backend_client.cursor.retries.remove(&item_id);
+        self.backend_client.save_cursor()
+    }
+
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        let mut due: Vec<RetryEntry> = self
+            .backend_client
+            .cursor
+            .retries
+            .values()
+            .filter(|e| e.state == RetryState::Pending && e.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|e| e.next_attempt_at);
+        due.truncate(limit);
+        Ok(due)
+    }
+}
+
+impl GenericSnapshotStore for Store<StdoutClient> {
+    fn save_snapshot(&mut self, _snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+
+    fn refresh_candidates(&mut self, _since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        Ok(vec![])
+    }
+
+    fn get_snapshots(&mut self, _item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        Ok(vec![])
+    }
+}
+
+impl GenericRankingStore for Store<StdoutClient> {
+    fn save_ranking(&mut self, _snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+
+    fn ranking_at(
+        &mut self,
+        _list: StoryList,
+        _at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        Ok(None)
+    }
+
+    fn ranking_history(
+        &mut self,
+        _list: StoryList,
+        _item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        Ok(vec![])
+    }
+
+    fn ranking_observations(&mut self, _list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        Ok(vec![])
+    }
+}
+
+impl GenericItemHistory for Store<StdoutClient> {
+    fn record_version(&mut self, _item: &Item, _observed_at: i64) -> Result<bool, CrawlerError> {
+        Ok(false)
+    }
+
+    fn item_versions(&mut self, _item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        Ok(vec![])
+    }
+}
+
+impl GenericTombstoneStore for Store<StdoutClient> {
+    fn get_tombstone(&mut self, _item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        Ok(None)
+    }
+
+    fn save_tombstone(&mut self, _tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+    use crate::ext::stdout::{OutputFormat, StdoutClient};
+    use crate::item::Item;
+    use crate::store::Store;
+    use crate::testing::TempDir;
+    use crate::tombstone::TombstonePolicy;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    /// Stands in for stdout, sharing what is written with the test
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn mock_stdout(
+        format: OutputFormat,
+        cursor: Option<PathBuf>,
+    ) -> (Captured, Store<StdoutClient>) {
+        let out = Captured::default();
+        let client = StdoutClient::open(
+            Box::new(out.clone()),
+            format,
+            cursor,
+            TombstonePolicy::Include,
+        )
+        .unwrap();
+        (
+            out,
+            Store {
+                backend_client: client,
+            },
+        )
+    }
+
+    fn comment(id: i64, text: &str) -> Item {
+        Item::parse(id, &format!(r#"{{"id":{},"type":"comment","by":"pg","time":1700000000,"parent":1,"kids":[7,8]}}"#, id))
+            .map(|mut item| { item.text = text.to_string(); item })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let items = vec![
+            comment(2, "plain"),
+            comment(3, "a \"quoted\", multi\nline\ttext"),
+        ];
+
+        let (out, mut store_client) = mock_stdout(OutputFormat::Json, None);
+        store_client.store_items(items.clone()).unwrap();
+        let lines: Vec<Item> = out
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines, items);
+
+        let (out, mut store_client) = mock_stdout(OutputFormat::Csv, None);
+        store_client.store_items(items.clone()).unwrap();
+        store_client.store_item(comment(4, "")).unwrap();
+        assert_eq!(out.text(), "id,deleted,type,by,time,dead,kids,title,score,text,url,parent,descendants\n\
+            2,false,comment,pg,1700000000,false,\"[7,8]\",,0,plain,,1,0\n\
+            3,false,comment,pg,1700000000,false,\"[7,8]\",,0,\"a \"\"quoted\"\", multi\nline\ttext\",,1,0\n\
+            4,false,comment,pg,1700000000,false,\"[7,8]\",,0,,,1,0\n");
+
+        let (out, mut store_client) = mock_stdout(OutputFormat::Tsv, None);
+        store_client.store_items(items).unwrap();
+        let lines: Vec<String> = out.text().lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2], "3\tfalse\tcomment\tpg\t1700000000\tfalse\t[7,8]\t\t0\ta \"quoted\", multi\\nline\\ttext\t\t1\t0");
+    }
+
+    #[test]
+    fn test_cursor_keeps_position() {
+        let dir = TempDir::new("stdout-cursor");
+        let cursor = dir.path().join("cursor.json");
+        let (_, mut store_client) = mock_stdout(OutputFormat::Json, Some(cursor.clone()));
+        assert_eq!(store_client.get_last_item().unwrap(), 0);
+        store_client
+            .store_items(vec![comment(2, "a"), comment(5, "b")])
+            .unwrap();
+        store_client.save_checkpoint("sync", 6).unwrap();
+
+        let (out, mut store_client) = mock_stdout(OutputFormat::Json, Some(cursor.clone()));
+        assert_eq!(store_client.get_last_item().unwrap(), 5);
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), Some(6));
+        assert!(out.text().is_empty());
+    }
+
+    #[test]
+    fn test_contains_emitted_ids() {
+        let dir = TempDir::new("stdout-emitted");
+        let cursor = dir.path().join("cursor.json");
+        let (_, mut store_client) = mock_stdout(OutputFormat::Json, Some(cursor.clone()));
+        store_client
+            .store_items((1..=3).map(|id| comment(id, "")).collect())
+            .unwrap();
+        // Stories of the rankings, far above the synced items
+        store_client
+            .store_items(vec![comment(10, ""), comment(5, "")])
+            .unwrap();
+        store_client.store_item(comment(4, "")).unwrap();
+        assert_eq!(
+            store_client
+                .backend_client
+                .cursor
+                .emitted
+                .iter()
+                .map(|(s, e)| (*s, *e))
+                .collect::<Vec<_>>(),
+            vec![(1, 5), (10, 10)]
+        );
+
+        let (_, mut store_client) = mock_stdout(OutputFormat::Json, Some(cursor.clone()));
+        let mut held: Vec<i64> = store_client
+            .contains(&[1, 4, 5, 6, 9, 10, 11])
+            .unwrap()
+            .into_iter()
+            .collect();
+        held.sort();
+        assert_eq!(held, vec![1, 4, 5, 10]);
+    }
+
+    #[test]
+    fn test_exclude_tombstones() {
+        let (out, mut store_client) = mock_stdout(OutputFormat::Json, None);
+        store_client.backend_client.tombstones = TombstonePolicy::Exclude;
+        let mut dead = comment(3, "spam");
+        dead.dead = true;
+        store_client
+            .store_items(vec![comment(2, "a"), dead])
+            .unwrap();
+        store_client
+            .store_item(Item::from(String::from(
+                r#"{"id": 4, "type": "comment", "time": 1, "deleted": true}"#,
+            )))
+            .unwrap();
+        let ids: Vec<i64> = out
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str::<Item>(line).unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![2]);
+        // Left out, but not emitted again by the rankings
+        assert_eq!(store_client.get_last_item().unwrap(), 4);
+        assert_eq!(store_client.contains(&[3, 4]).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,560 @@
+use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+use crate::error::CrawlerError;
+use crate::ext::file::write_atomic;
+use crate::history::{GenericItemHistory, ItemVersion};
+use crate::item::Item;
+use crate::ranking::{GenericRankingStore, RankPosition, RankingSnapshot, StoryList};
+use crate::refresh::{GenericSnapshotStore, ItemSnapshot, RefreshCandidate};
+use crate::retry::{GenericRetryQueue, RetryEntry};
+use crate::store::Store;
+use crate::tombstone::{GenericTombstoneStore, Tombstone};
+use crate::utils::{
+    WEBHOOK_ATTEMPTS, WEBHOOK_BATCH_ITEMS, WEBHOOK_MAX_RETRY_DELAY_MS, WEBHOOK_RETRY_DELAY_MS,
+    WEBHOOK_SECRET, WEBHOOK_SPOOL_DIR,
+};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+const CHECKPOINTS_FILE: &str = "checkpoints.json";
+/// Payloads the receiver rejected, one per line, kept apart so they don't block the spool
+const DEAD_LETTER_FILE: &str = "dead-letter.jsonl";
+/// Header holding `sha256=<hex HMAC of the body>` when a secret is configured
+pub const SIGNATURE_HEADER: &str = "X-Crawler-Signature";
+
+/// Body POSTed to the webhook
+#[derive(Serialize)]
+struct Payload<'a> {
+    items: &'a [Item],
+}
+
+/// Outcome of a POST the receiver answered
+#[derive(Debug, PartialEq, Eq)]
+enum Delivery {
+    Sent,
+    /// A 4xx status other than 408 and 429: sending the payload again won't help
+    Rejected(reqwest::StatusCode),
+}
+
+/// The delay after `delay`, doubled up to `max`
+fn next_delay(delay: Duration, max: Duration) -> Duration {
+    delay.saturating_mul(2).min(max)
+}
+
+/// POSTs the newly stored items as JSON to a URL. Payloads which can't be delivered after
+/// `attempts` are spooled to disk and sent first, in order, once the receiver is back.
+/// Payloads the receiver rejects are moved to a dead-letter file instead of being retried.
+/// The webhook only pushes, it serves no reads: it is meant as a sink of the fan-out store
+pub struct WebhookClient {
+    url: String,
+    secret: Option<String>,
+    spool_path: PathBuf,
+    http: reqwest::blocking::Client,
+    checkpoints: BTreeMap<String, i64>,
+    /// Sequence number of the next spooled payload
+    next_spool: u64,
+    batch_items: usize,
+    attempts: u32,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+}
+
+impl WebhookClient {
+    pub fn open(url: &str, spool_dir: &str, secret: Option<String>) -> Result<Self, CrawlerError> {
+        let spool_path = PathBuf::from(spool_dir);
+        fs::create_dir_all(&spool_path)?;
+        let checkpoints = match fs::read(spool_path.join(CHECKPOINTS_FILE)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let http = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| CrawlerError::Webhook {
+                reason: e.to_string(),
+            })?;
+        let mut client = Self {
+            url: url.to_string(),
+            secret,
+            spool_path,
+            http,
+            checkpoints,
+            next_spool: 0,
+            batch_items: *WEBHOOK_BATCH_ITEMS,
+            attempts: *WEBHOOK_ATTEMPTS,
+            retry_delay: Duration::from_millis(*WEBHOOK_RETRY_DELAY_MS),
+            max_retry_delay: Duration::from_millis(*WEBHOOK_MAX_RETRY_DELAY_MS),
+        };
+        client.next_spool = client.spooled()?.last().map_or(0, |(seq, _)| seq + 1);
+        Ok(client)
+    }
+
+    /// The spooled payloads ordered by sequence number
+    fn spooled(&self) -> Result<Vec<(u64, PathBuf)>, CrawlerError> {
+        let mut spooled = vec![];
+        for entry in fs::read_dir(&self.spool_path)? {
+            let path = entry?.path();
+            let seq = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| {
+                    name.strip_prefix("spool-")?
+                        .strip_suffix(".json")?
+                        .parse::<u64>()
+                        .ok()
+                });
+            if let Some(seq) = seq {
+                spooled.push((seq, path));
+            }
+        }
+        spooled.sort();
+        Ok(spooled)
+    }
+
+    /// `sha256=<hex>` HMAC of `body` with the secret
+    fn signature(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// POST `body` once. A rejected payload is returned as such, the other
+    /// non-2xx statuses are errors worth a retry
+    fn post(&self, body: &[u8]) -> Result<Delivery, CrawlerError> {
+        let mut request = self
+            .http
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, Self::signature(secret, body));
+        }
+        let response = request.send().map_err(|e| CrawlerError::Webhook {
+            reason: e.to_string(),
+        })?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(Delivery::Sent);
+        }
+        let retryable = [
+            reqwest::StatusCode::REQUEST_TIMEOUT,
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+        ];
+        if status.is_client_error() && !retryable.contains(&status) {
+            return Ok(Delivery::Rejected(status));
+        }
+        Err(CrawlerError::Webhook {
+            reason: format!("{} answered {}", self.url, status),
+        })
+    }
+
+    /// POST `body` up to `attempts` times with an exponential backoff capped at `max_retry_delay`
+    fn post_with_retry(&self, body: &[u8]) -> Result<Delivery, CrawlerError> {
+        let mut delay = self.retry_delay.min(self.max_retry_delay);
+        let mut attempt = 1;
+        loop {
+            match self.post(body) {
+                Ok(delivery) => return Ok(delivery),
+                Err(e) if attempt >= self.attempts => return Err(e),
+                Err(e) => {
+                    eprintln!(
+                        "Webhook attempt {} failed, retrying in {:?}: {}",
+                        attempt, delay, e
+                    );
+                    thread::sleep(delay);
+                    delay = next_delay(delay, self.max_retry_delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Send the spooled payloads in order, stopping at the first failure. The rejected
+    /// payloads are moved to the dead-letter file. Returns whether the spool is empty
+    fn drain_spool(&mut self) -> Result<bool, CrawlerError> {
+        for (_, path) in self.spooled()? {
+            let body = fs::read(&path)?;
+            match self.post(&body) {
+                Ok(Delivery::Sent) => {}
+                Ok(Delivery::Rejected(status)) => self.dead_letter(&body, status)?,
+                Err(e) => {
+                    eprintln!("Webhook is still down, keeping the spool: {}", e);
+                    return Ok(false);
+                }
+            }
+            fs::remove_file(&path)?;
+        }
+        Ok(true)
+    }
+
+    /// Append the payload `body` rejected with `status` to the dead-letter file
+    fn dead_letter(&self, body: &[u8], status: reqwest::StatusCode) -> Result<(), CrawlerError> {
+        eprintln!(
+            "Webhook rejected a payload with {}, moving it to {}",
+            status, DEAD_LETTER_FILE
+        );
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.spool_path.join(DEAD_LETTER_FILE))?;
+        file.write_all(&[body, b"\n"].concat())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn spool(&mut self, body: &[u8]) -> Result<(), CrawlerError> {
+        let path = self
+            .spool_path
+            .join(format!("spool-{:012}.json", self.next_spool));
+        write_atomic(&path, body)?;
+        self.next_spool += 1;
+        Ok(())
+    }
+
+    /// Deliver `items` in payloads of at most `batch_items`. While the spool can't be drained
+    /// the new payloads are spooled behind it, so the receiver gets them in order
+    fn send(&mut self, items: &[Item]) -> Result<(), CrawlerError> {
+        let mut down = !self.drain_spool()?;
+        for chunk in items.chunks(self.batch_items.max(1)) {
+            let body = serde_json::to_vec(&Payload { items: chunk })?;
+            if !down {
+                match self.post_with_retry(&body) {
+                    Ok(Delivery::Sent) => continue,
+                    Ok(Delivery::Rejected(status)) => {
+                        self.dead_letter(&body, status)?;
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Webhook is down, spooling {} items: {}", chunk.len(), e);
+                        down = true;
+                    }
+                }
+            }
+            self.spool(&body)?;
+        }
+        Ok(())
+    }
+}
+
+impl Store<WebhookClient> {
+    /// Open the webhook of `url`, spooling to `WEBHOOK_SPOOL_DIR` and signing with `WEBHOOK_SECRET`
+    pub fn new(url: &str) -> Self {
+        let secret = Some(WEBHOOK_SECRET.clone()).filter(|secret| !secret.is_empty());
+        Self {
+            backend_client: WebhookClient::open(url, &WEBHOOK_SPOOL_DIR, secret).unwrap(),
+        }
+    }
+
+    fn spool_path(&self) -> &Path {
+        &self.backend_client.spool_path
+    }
+}
+
+impl GenericStoreItem for Store<WebhookClient> {
+    fn get_last_item(&mut self) -> Result<i64, CrawlerError> {
+        Ok(0)
+    }
+
+    fn store_item(&mut self, item: Item) -> Result<bool, CrawlerError> {
+        self.backend_client.send(&[item])?;
+        Ok(true)
+    }
+
+    /// Only new items are pushed
+    fn upsert_item(&mut self, _item: Item) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+
+    fn get_item(&mut self, _item_id: i64) -> Result<Option<Item>, CrawlerError> {
+        Ok(None)
+    }
+
+    fn get_items_range(
+        &mut self,
+        _range: RangeInclusive<i64>,
+        _limit: usize,
+    ) -> Result<Vec<Item>, CrawlerError> {
+        Ok(vec![])
+    }
+
+    fn store_items(&mut self, items: Vec<Item>) -> Result<usize, CrawlerError> {
+        self.backend_client.send(&items)?;
+        Ok(items.len())
+    }
+
+    fn contains(&mut self, _ids: &[i64]) -> Result<HashSet<i64>, CrawlerError> {
+        Ok(HashSet::new())
+    }
+}
+
+impl GenericCheckpoint for Store<WebhookClient> {
+    fn get_checkpoint(&mut self, name: &str) -> Result<Option<i64>, CrawlerError> {
+        Ok(self.backend_client.checkpoints.get(name).copied())
+    }
+
+    /// Also retries the spool, so it drains even when no new items arrive
+    fn save_checkpoint(&mut self, name: &str, value: i64) -> Result<(), CrawlerError> {
+        self.backend_client.drain_spool()?;
+        self.backend_client
+            .checkpoints
+            .insert(name.to_string(), value);
+        let data = serde_json::to_vec(&self.backend_client.checkpoints)?;
+        write_atomic(&self.spool_path().join(CHECKPOINTS_FILE), &data)
+    }
+}
+
+impl GenericRetryQueue for Store<WebhookClient> {
+    fn get_retry(&mut self, _item_id: i64) -> Result<Option<RetryEntry>, CrawlerError> {
+        Ok(None)
+    }
+
+    fn save_retry(&mut self, _entry: &RetryEntry) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+
+    fn remove_retry(&mut self, _item_id: i64) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+
+    fn due_retries(&mut self, _now: i64, _limit: usize) -> Result<Vec<RetryEntry>, CrawlerError> {
+        Ok(vec![])
+    }
+}
+
+impl GenericSnapshotStore for Store<WebhookClient> {
+    fn save_snapshot(&mut self, _snapshot: &ItemSnapshot) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+
+    fn refresh_candidates(&mut self, _since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError> {
+        Ok(vec![])
+    }
+
+    fn get_snapshots(&mut self, _item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError> {
+        Ok(vec![])
+    }
+}
+
+impl GenericRankingStore for Store<WebhookClient> {
+    fn save_ranking(&mut self, _snapshot: &RankingSnapshot) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+
+    fn ranking_at(
+        &mut self,
+        _list: StoryList,
+        _at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError> {
+        Ok(None)
+    }
+
+    fn ranking_history(
+        &mut self,
+        _list: StoryList,
+        _item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError> {
+        Ok(vec![])
+    }
+
+    fn ranking_observations(&mut self, _list: StoryList) -> Result<Vec<i64>, CrawlerError> {
+        Ok(vec![])
+    }
+}
+
+impl GenericItemHistory for Store<WebhookClient> {
+    fn record_version(&mut self, _item: &Item, _observed_at: i64) -> Result<bool, CrawlerError> {
+        Ok(false)
+    }
+
+    fn item_versions(&mut self, _item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError> {
+        Ok(vec![])
+    }
+}
+
+impl GenericTombstoneStore for Store<WebhookClient> {
+    fn get_tombstone(&mut self, _item_id: i64) -> Result<Option<Tombstone>, CrawlerError> {
+        Ok(None)
+    }
+
+    fn save_tombstone(&mut self, _tombstone: &Tombstone) -> Result<(), CrawlerError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::{GenericCheckpoint, GenericStoreItem};
+    use crate::ext::webhook::{next_delay, WebhookClient, DEAD_LETTER_FILE, SIGNATURE_HEADER};
+    use crate::item::Item;
+    use crate::store::Store;
+    use crate::testing::TempDir;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn story(id: i64) -> Item {
+        Item::parse(
+            id,
+            &format!(
+                r#"{{"id":{},"type":"story","by":"pg","time":{}}}"#,
+                id,
+                1_700_000_000 + id
+            ),
+        )
+        .unwrap()
+    }
+
+    /// A local HTTP receiver answering the given statuses in turn,
+    /// which forwards the headers and body of every request
+    fn mock_receiver(statuses: Vec<u16>) -> (String, mpsc::Receiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(statuses) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    headers.push_str(&line.to_lowercase());
+                }
+                let length = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |length| length.trim().parse::<usize>().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Webhook\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                tx.send((headers, body)).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn mock_webhook(name: &str, url: &str) -> (TempDir, Store<WebhookClient>) {
+        let dir = TempDir::new(&format!("webhook-{}", name));
+        let mut client = WebhookClient::open(url, &dir.uri(), Some("s3cret".to_string())).unwrap();
+        client.attempts = 2;
+        client.retry_delay = Duration::from_millis(1);
+        (
+            dir,
+            Store {
+                backend_client: client,
+            },
+        )
+    }
+
+    fn items_of(body: &[u8]) -> Vec<i64> {
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        payload["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_i64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_signed_batches() {
+        let (url, requests) = mock_receiver(vec![200, 200]);
+        let (_dir, mut store_client) = mock_webhook("signed", &url);
+        store_client.backend_client.batch_items = 2;
+        assert_eq!(
+            store_client
+                .store_items(vec![story(1), story(2), story(3)])
+                .unwrap(),
+            3
+        );
+
+        let (headers, body) = requests.recv().unwrap();
+        assert_eq!(items_of(&body), vec![1, 2]);
+        let signature = WebhookClient::signature("s3cret", &body);
+        assert!(headers.contains(&format!(
+            "{}: {}",
+            SIGNATURE_HEADER.to_lowercase(),
+            signature
+        )));
+        assert_eq!(items_of(&requests.recv().unwrap().1), vec![3]);
+    }
+
+    #[test]
+    fn test_spool_while_down() {
+        // Both attempts of the first payload fail, then the receiver is back
+        let (url, requests) = mock_receiver(vec![503, 503, 200, 200]);
+        let (dir, mut store_client) = mock_webhook("spool", &url);
+        store_client.store_items(vec![story(1), story(2)]).unwrap();
+        assert_eq!(store_client.backend_client.spooled().unwrap().len(), 1);
+
+        // The spool is delivered before the new items
+        store_client.store_items(vec![story(3)]).unwrap();
+        assert!(store_client.backend_client.spooled().unwrap().is_empty());
+        let bodies: Vec<Vec<i64>> = requests
+            .iter()
+            .take(4)
+            .map(|(_, body)| items_of(&body))
+            .collect();
+        assert_eq!(bodies, vec![vec![1, 2], vec![1, 2], vec![1, 2], vec![3]]);
+        store_client.save_checkpoint("sync", 3).unwrap();
+
+        let mut store_client = Store {
+            backend_client: WebhookClient::open(&url, &dir.uri(), None).unwrap(),
+        };
+        assert_eq!(store_client.get_checkpoint("sync").unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_rejected_payloads_dead_lettered() {
+        // The first payload is rejected without a retry, the second is spooled and
+        // rejected while draining, and neither blocks the payloads behind them
+        let (url, requests) = mock_receiver(vec![422, 503, 503, 400, 200]);
+        let (dir, mut store_client) = mock_webhook("dead-letter", &url);
+        store_client.backend_client.batch_items = 1;
+        store_client.store_items(vec![story(1), story(2)]).unwrap();
+        assert_eq!(store_client.backend_client.spooled().unwrap().len(), 1);
+        store_client.store_items(vec![story(3)]).unwrap();
+        assert!(store_client.backend_client.spooled().unwrap().is_empty());
+
+        let bodies: Vec<Vec<i64>> = requests
+            .iter()
+            .take(5)
+            .map(|(_, body)| items_of(&body))
+            .collect();
+        assert_eq!(bodies, vec![vec![1], vec![2], vec![2], vec![2], vec![3]]);
+        let dead = fs::read_to_string(dir.path().join(DEAD_LETTER_FILE)).unwrap();
+        let dead: Vec<Vec<i64>> = dead.lines().map(|line| items_of(line.as_bytes())).collect();
+        assert_eq!(dead, vec![vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn test_retry_delay_capped() {
+        let max = Duration::from_secs(60);
+        assert_eq!(
+            next_delay(Duration::from_secs(1), max),
+            Duration::from_secs(2)
+        );
+        assert_eq!(next_delay(Duration::from_secs(45), max), max);
+        assert_eq!(next_delay(Duration::MAX, max), max);
+    }
+}
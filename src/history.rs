@@ -0,0 +1,27 @@
+use crate::error::CrawlerError;
+use crate::item::Item;
+
+/// One distinct version of an item, first observed at `observed_at`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemVersion {
+    pub observed_at: i64,
+    pub item: Item,
+}
+
+/// Storing every distinct version of the items into generic Store
+pub trait GenericItemHistory {
+    /// Append `item` to its history unless it equals the latest version,
+    /// returns whether a new version was recorded
+    fn record_version(&mut self, item: &Item, observed_at: i64) -> Result<bool, CrawlerError>;
+    /// All versions of an item, oldest first
+    fn item_versions(&mut self, item_id: i64) -> Result<Vec<ItemVersion>, CrawlerError>;
+    /// The item as it was at `at`, `None` if it was not observed yet
+    fn item_as_of(&mut self, item_id: i64, at: i64) -> Result<Option<Item>, CrawlerError> {
+        Ok(self
+            .item_versions(item_id)?
+            .into_iter()
+            .take_while(|v| v.observed_at <= at)
+            .last()
+            .map(|v| v.item))
+    }
+}
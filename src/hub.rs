@@ -14,19 +14,26 @@ impl NewsHub {
         self.fetch_res_by_uri("/maxitem.json?print=pretty")
     }
 
+    /// Fetch the ids of a story list such as `topstories`, ordered by rank
+    pub fn fetch_story_list(&self, list: &str) -> Result<String, reqwest::Error> {
+        self.fetch_res_by_uri(&("/".to_owned() + list + ".json?print=pretty"))
+    }
+
     pub async fn fetch_item_async(&self, item_id: i64) -> Result<String, reqwest::Error> {
         self.fetch_res_by_uri_async(
             &("/item/".to_owned() + &item_id.to_string() + ".json?print=pretty"),
-        ).await
+        )
+        .await
     }
 
     pub fn fetch_item(&self, item_id: i64) -> Result<String, reqwest::Error> {
-        self.fetch_res_by_uri(
-            &("/item/".to_owned() + &item_id.to_string() + ".json?print=pretty"),
-        )
+        self.fetch_res_by_uri(&("/item/".to_owned() + &item_id.to_string() + ".json?print=pretty"))
     }
 
-    pub fn blocking_fetch<T: IntoUrl>(&self, url: T) -> Result<reqwest::blocking::Response, reqwest::Error> {
+    pub fn blocking_fetch<T: IntoUrl>(
+        &self,
+        url: T,
+    ) -> Result<reqwest::blocking::Response, reqwest::Error> {
         reqwest::blocking::Client::builder()
             .danger_accept_invalid_certs(true)
             .build()
@@ -42,9 +49,12 @@ impl NewsHub {
     async fn fetch_res_by_uri_async(&self, query: &str) -> Result<String, reqwest::Error> {
         reqwest::Client::builder()
             .danger_accept_invalid_certs(true)
-            .build().unwrap()
+            .build()
+            .unwrap()
             .get(&(self.base_uri.clone() + query))
             .send()
-            .await?.text().await
+            .await?
+            .text()
+            .await
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,9 @@
+use crate::error::CrawlerError;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Item {
     pub id: i64,
     #[serde(default)]
@@ -25,6 +28,9 @@ pub struct Item {
     pub url: String,
     #[serde(default)]
     pub parent: i64,
+    /// Total comment count of a story or poll, not stored in `items`
+    #[serde(default)]
+    pub descendants: i64,
 }
 
 impl From<String> for Item {
@@ -35,43 +41,78 @@ impl From<String> for Item {
 }
 
 impl Item {
+    /// Parse the hub response of an item, the hub answers `null`
+    /// for the ids which have no data (yet)
+    pub fn parse(item_id: i64, s: &str) -> Result<Self, CrawlerError> {
+        if s.trim() == "null" {
+            return Err(CrawlerError::EmptyItem { item_id });
+        }
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// The fields of the item by their name in the hub JSON (`by`, `type`, ...)
+    pub fn fields(&self) -> Map<String, Value> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(fields)) => fields,
+            _ => unreachable!("an item serializes to a JSON object"),
+        }
+    }
+
+    /// Sorted names of the fields which differ between the item and `other`
+    pub fn diff(&self, other: &Item) -> Vec<String> {
+        let (ours, theirs) = (self.fields(), other.fields());
+        let mut fields: Vec<String> = ours
+            .iter()
+            .filter(|(name, value)| theirs.get(*name) != Some(*value))
+            .map(|(name, _)| name.clone())
+            .collect();
+        fields.sort();
+        fields
+    }
+
+    /// Parse the `kids` column as written by `to_sql_value`
+    pub fn parse_kids(s: &str) -> Vec<i64> {
+        serde_json::from_str(s).unwrap_or_default()
+    }
+
     /// Parsed all item values into `()` sql query string
     /// followed the order of item attributes
     /// but not contains the INSERT INO... prefix
     /// the string will safty wrapped in single quote
-    /// 
+    ///
     /// ex:\n
-    /// 
+    ///
     ///({id}, {deleted}, '{item_type}', '{who}', {time}, {dead}, '{kids:?}',
     /// '{title}', '{content}', {score}, '{url}', {parent})",
     pub fn to_sql_value(&self) -> String {
         // safety process single quote
         let fmt_str = |the_str: String| the_str.replace("'", "''");
         // convert bool into number
-        let fmt_bool = |the_bool: bool| {
-            match the_bool { true => 1, _ => 0 }
+        let fmt_bool = |the_bool: bool| match the_bool {
+            true => 1,
+            _ => 0,
         };
         format!(
             "({id}, {deleted}, '{item_type}', '{who}', {time}, {dead}, '{kids:?}', \
             '{title}', '{content}', {score}, '{url}', {parent})",
-            id=self.id,
-            deleted=fmt_bool(self.deleted),
-            item_type=fmt_str(self.tp.to_string()),
-            who=fmt_str(self.who.to_string()),
-            time=self.time,
-            dead=fmt_bool(self.dead),
-            kids=self.kids,
-            title=fmt_str(self.title.to_string()),
-            content=fmt_str(self.text.to_string()),
-            score=self.score,
-            url=fmt_str(self.url.to_string()),
-            parent=self.parent
-        )       
+            id = self.id,
+            deleted = fmt_bool(self.deleted),
+            item_type = fmt_str(self.tp.to_string()),
+            who = fmt_str(self.who.to_string()),
+            time = self.time,
+            dead = fmt_bool(self.dead),
+            kids = self.kids,
+            title = fmt_str(self.title.to_string()),
+            content = fmt_str(self.text.to_string()),
+            score = self.score,
+            url = fmt_str(self.url.to_string()),
+            parent = self.parent
+        )
     }
 }
 
-impl ToString for Item {
-    fn to_string(&self) -> String {
-        self.to_sql_value()
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_sql_value())
     }
-}
\ No newline at end of file
+}
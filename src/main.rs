@@ -1,67 +1,690 @@
 // #![allow(dead_code, unused_imports, unused_variables, unused_qualifications)]
 
-use clap::Parser;
-use crate::crawler::{GenericCrawlerFlow, ItemsCrawler};
+use crate::crawler::{GenericCheckpoint, GenericCrawlerFlow, GenericStore, ItemsCrawler};
+use crate::ext::fanout::FanOutClient;
+use crate::ext::file::FileClient;
+use crate::ext::parquet::ParquetClient;
+use crate::ext::stdout::{OutputFormat, StdoutClient};
 use crate::hub::NewsHub;
+use crate::migrate::GenericMigrations;
+use crate::outbox::GenericOutbox;
+use crate::replicate::GenericChangeFeed;
+use crate::search::{GenericSearch, SearchQuery};
 use crate::store::Store;
+use crate::tombstone::TombstonePolicy;
 use crate::utils::CRAWLER_HUB;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub mod store;
-pub mod item;
-pub mod hub;
+pub mod copy;
 pub mod crawler;
+pub mod error;
 pub mod ext;
+pub mod history;
+pub mod hub;
+pub mod item;
+pub mod migrate;
+pub mod outbox;
+pub mod ranking;
+pub mod refresh;
+pub mod replicate;
+pub mod retry;
+pub mod search;
+pub mod store;
+#[cfg(test)]
+mod testing;
+pub mod tombstone;
 mod utils;
+pub mod verify;
 
-#[derive(Parser,Default,Debug)]
+#[derive(Parser, Default, Debug)]
 struct Args {
-    #[clap(short, long, default_value_t = String::from("sqlite"))]
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(short, long, global = true, default_value_t = String::from("sqlite"))]
     store: String,
-    #[clap(long)]
+    #[clap(long, global = true, default_value_t = String::new())]
     store_uri: String,
-    #[clap(short, long, default_value_t=5)]
+    /// Sinks of the `fanout` store, `<store>=<uri>[;policy=fail-fast|best-effort|retry-later]`,
+    /// the first one is the primary
+    #[clap(long)]
+    sink: Vec<String>,
+    #[clap(short, long, default_value_t = 5)]
     n_items: u16,
     #[clap(long, default_value_t = String::from("run_one"))]
-    run_type: String
+    run_type: String,
+    /// Seconds between two polls of new items in `daemon` mode
+    #[clap(long, default_value_t = 30)]
+    poll_interval: u64,
+    /// Seconds between two runs of the refresh jobs in `daemon` mode
+    #[clap(long, default_value_t = 300)]
+    refresh_interval: u64,
+    /// Item to look up in `item_as_of` mode
+    #[clap(long)]
+    item_id: Option<i64>,
+    /// Unix timestamp to look up in `item_as_of` mode, defaults to now
+    #[clap(long)]
+    at: Option<i64>,
+    /// How `item_as_of` and the `stdout` store handle deleted and dead items: `include`, `restore`
+    /// or `exclude`. Only `include` is accepted elsewhere, the other stores, the exports to
+    /// `file` and `parquet` and `migrate --from --to` keep the items as crawled
+    #[clap(long, default_value_t = String::from("include"))]
+    tombstones: String,
+    /// Line format of the `stdout` store: `json`, `csv` or `tsv`
+    #[clap(long, default_value_t = String::from("json"))]
+    format: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the versioned schema of the sqlite and postgres stores,
+    /// or copy the items between two stores with `--from` and `--to`
+    #[clap(args_conflicts_with_subcommands = true)]
+    Migrate {
+        #[clap(subcommand)]
+        action: Option<MigrateAction>,
+        /// Store to copy the items from, `<store>:<uri>` like `sqlite:items.db`
+        #[clap(long, requires = "to")]
+        from: Option<String>,
+        /// Store to copy the items to, `<store>:<uri>` like `postgres:postgresql://...`
+        #[clap(long, requires = "from")]
+        to: Option<String>,
+        /// Ids copied per batch
+        #[clap(long, default_value_t = 1000)]
+        batch_items: usize,
+    },
+    /// Copy the live sqlite store consistently while the crawler keeps writing
+    Backup {
+        /// Path of the copy, replaced once the copy is complete
+        #[clap(long)]
+        to: String,
+        /// Write a compacted copy with `VACUUM INTO` instead of the online backup API
+        #[clap(long)]
+        vacuum: bool,
+        /// Run `PRAGMA integrity_check` on the copy before putting it in place
+        #[clap(long)]
+        check: bool,
+        /// Pages copied per step of the online backup, all of them at once when negative
+        #[clap(long, default_value_t = -1, allow_negative_numbers = true)]
+        step_pages: i32,
+    },
+    /// Keep a replica current with the new and updated items of a sqlite or postgres store
+    Replicate {
+        /// Store to follow, `<store>:<uri>` like `sqlite:items.db`
+        #[clap(long)]
+        from: String,
+        /// Replica, `<store>:<uri>` like `postgres:postgresql://...`
+        #[clap(long)]
+        to: String,
+        /// Seconds between two polls of the source once the replica is current
+        #[clap(long, default_value_t = 5)]
+        poll_interval: u64,
+        /// Exit once the replica is current instead of following the source
+        #[clap(long)]
+        once: bool,
+        /// Changes applied per batch
+        #[clap(long, default_value_t = 1000)]
+        batch_items: usize,
+    },
+    /// Print the item changes of the sqlite or postgres store since the last run of a consumer
+    Outbox {
+        /// Name of the consumer, its offset is kept in the `outbox:<consumer>` checkpoint
+        #[clap(long)]
+        consumer: String,
+        /// Entries printed between two saves of the offset
+        #[clap(long, default_value_t = 1000)]
+        batch_items: usize,
+        /// Wait for new entries instead of exiting at the end of the outbox
+        #[clap(long)]
+        follow: bool,
+        /// Seconds between two polls of the outbox with `--follow`
+        #[clap(long, default_value_t = 5)]
+        poll_interval: u64,
+    },
+    /// Compare the items of the store with the hub, or with another store with `--against`,
+    /// and report the missing ids, the differing fields and the duplicates
+    Verify {
+        /// Store to compare with instead of the hub, `<store>:<uri>` like `postgres:postgresql://...`
+        #[clap(long)]
+        against: Option<String>,
+        /// First id compared
+        #[clap(long, default_value_t = 1)]
+        from_id: i64,
+        /// Last id compared, the last stored item by default
+        #[clap(long)]
+        to_id: Option<i64>,
+        /// Compare this many ids spread over the range with the hub instead of every id
+        #[clap(long, conflicts_with = "against")]
+        sample: Option<usize>,
+        /// Ids compared per batch, fetched concurrently from the hub
+        #[clap(long, default_value_t = 100)]
+        batch_items: usize,
+        /// Fields left out of the comparison, `descendants` is not stored by the SQL stores
+        #[clap(long, value_delimiter = ',', default_value = "descendants")]
+        ignore: Vec<String>,
+    },
+    /// Search the titles and texts of the items of the sqlite or postgres store, best matches first
+    Search {
+        /// Words to look for, all of them must match
+        query: String,
+        /// Only the items of this type, like `story` or `comment`
+        #[clap(long = "type")]
+        tp: Option<String>,
+        /// Only the items of this author
+        #[clap(long)]
+        author: Option<String>,
+        /// Only the items posted at or after this unix timestamp
+        #[clap(long)]
+        since: Option<i64>,
+        /// Only the items posted at or before this unix timestamp
+        #[clap(long)]
+        until: Option<i64>,
+        #[clap(long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MigrateAction {
+    /// Apply the pending migrations
+    Up,
+    /// List the migrations with their version and when they were applied
+    Status,
+}
+
+/// Run a `migrate` action on a store opened without migrating it
+fn run_migrate<T: GenericMigrations>(mut store: T, action: &MigrateAction) {
+    match action {
+        MigrateAction::Up => {
+            let applied = store.migrate_up().unwrap();
+            println!(
+                "Applied {} migrations, schema version {}",
+                applied.len(),
+                store.schema_version().unwrap()
+            );
+        }
+        MigrateAction::Status => {
+            for (migration, applied) in store.migration_status().unwrap() {
+                match applied {
+                    Some(applied) => println!(
+                        "{:>4} {:<32} applied at {}",
+                        migration.version, migration.name, applied.applied_at
+                    ),
+                    None => println!("{:>4} {:<32} pending", migration.version, migration.name),
+                }
+            }
+        }
+    }
+}
+
+/// Run the `run_type` flow of the crawler, the same for every store backend
+fn run_crawler<T>(mut crawler: ItemsCrawler<T>, args: &Args, tombstones: TombstonePolicy)
+where
+    T: GenericStore,
+{
+    match args.run_type.as_str() {
+        "run_one" => {
+            crawler.run_one().unwrap();
+        }
+        "run_many" => {
+            crawler.run_many().unwrap();
+        }
+        "run_many_insert_batch" => {
+            crawler.run_many_insert_batch().unwrap();
+        }
+        "sync_data" => {
+            crawler.run_sync_data().unwrap();
+        }
+        "retry_failed" => {
+            crawler.run_retry().unwrap();
+        }
+        "refresh" => {
+            crawler.run_refresh_snapshots().unwrap();
+        }
+        "rankings" => {
+            crawler.run_rankings().unwrap();
+        }
+        "item_as_of" => {
+            let item_id = args.item_id.expect("`item_as_of` requires `--item-id`");
+            let at = args.at.unwrap_or_else(utils::now);
+            let item = crawler
+                .client
+                .item_as_of(item_id, at)
+                .unwrap()
+                .map(|item| crawler.client.export_item(item, tombstones).unwrap());
+            match item {
+                Some(Some(item)) => println!("{}", serde_json::to_string_pretty(&item).unwrap()),
+                Some(None) => println!("Item {item_id} is a tombstone at {at}"),
+                None => println!("Item {item_id} was not observed at {at}"),
+            }
+        }
+        "daemon" => {
+            crawler.handle_signals().unwrap();
+            crawler
+                .run_daemon(
+                    Duration::from_secs(args.poll_interval),
+                    Duration::from_secs(args.refresh_interval),
+                )
+                .unwrap();
+        }
+        &_ => {
+            panic!("`run_type` {:?} is not supported!", args.run_type);
+        }
+    }
+}
+
+/// Open the store of a `<store>:<uri>` spec
+fn open_spec(spec: &str) -> Box<dyn GenericStore> {
+    let (store, uri) = spec
+        .split_once(':')
+        .unwrap_or_else(|| panic!("{:?} is not a `<store>:<uri>` spec!", spec));
+    ext::open_readable_store(store, uri.trim()).unwrap()
+}
+
+/// Copy the items of the `from` store into the `to` store, then check that
+/// both hold the same number of items with the same checksum
+fn run_copy(from: &str, to: &str, batch_items: usize) {
+    let mut source = open_spec(from);
+    let mut target = open_spec(to);
+    let copied = copy::copy_items(source.as_mut(), target.as_mut(), batch_items).unwrap();
+    let last_id = target
+        .get_checkpoint(copy::COPY_CHECKPOINT)
+        .unwrap()
+        .unwrap_or(0);
+    let expected = copy::digest_items(source.as_mut(), last_id, batch_items).unwrap();
+    let actual = copy::digest_items(target.as_mut(), last_id, batch_items).unwrap();
+    println!(
+        "Copied {copied} items, {} items up to {last_id} with checksum {}",
+        expected.count, expected.checksum
+    );
+    if actual != expected {
+        panic!(
+            "{to} holds {} items with checksum {} instead!",
+            actual.count, actual.checksum
+        );
+    }
+}
+
+/// Apply the changes of `source` to the `to` replica until SIGINT/SIGTERM, or until it is current with `once`
+fn run_replicate<S: GenericChangeFeed>(
+    mut source: S,
+    to: &str,
+    poll_interval: u64,
+    once: bool,
+    batch_items: usize,
+) {
+    let mut replica = open_spec(to);
+    let shutdown = shutdown_on_signals();
+    while !shutdown.load(Ordering::Relaxed) {
+        let applied = replicate::replicate(&mut source, replica.as_mut(), batch_items).unwrap();
+        let seq = replica
+            .get_checkpoint(replicate::REPLICATION_CHECKPOINT)
+            .unwrap()
+            .unwrap_or(0);
+        eprintln!("Applied {applied} changes, replica at change {seq}");
+        if once {
+            return;
+        }
+        pause(&shutdown, Duration::from_secs(poll_interval));
+    }
+}
+
+/// Print the outbox entries after the offset of `consumer` as JSON lines, saving the
+/// offset after every batch. With `follow`, wait for new entries until SIGINT/SIGTERM
+fn run_outbox<S: GenericOutbox + GenericCheckpoint>(
+    mut store: S,
+    consumer: &str,
+    batch_items: usize,
+    follow: bool,
+    poll_interval: u64,
+) {
+    let checkpoint = outbox::consumer_checkpoint(consumer);
+    let mut offset = store.get_checkpoint(&checkpoint).unwrap().unwrap_or(0);
+    let shutdown = shutdown_on_signals();
+    let mut out = std::io::stdout().lock();
+    while !shutdown.load(Ordering::Relaxed) {
+        let mut last = None;
+        for entry in store.outbox(offset).take(batch_items.max(1)) {
+            let entry = entry.unwrap();
+            writeln!(out, "{}", serde_json::to_string(&entry).unwrap()).unwrap();
+            last = Some(entry.seq);
+        }
+        out.flush().unwrap();
+        match last {
+            Some(seq) => {
+                store.save_checkpoint(&checkpoint, seq).unwrap();
+                offset = seq;
+            }
+            None if follow => pause(&shutdown, Duration::from_secs(poll_interval)),
+            None => return,
+        }
+    }
+}
+
+/// Compare the store of `args` with the hub or the `against` store, exit with 1 on differences
+fn run_verify(
+    args: &Args,
+    against: &Option<String>,
+    from_id: i64,
+    to_id: Option<i64>,
+    sample: Option<usize>,
+    batch_items: usize,
+    ignore: &[String],
+) {
+    let mut store = ext::open_readable_store(&args.store, args.store_uri.trim()).unwrap();
+    let report = match against {
+        Some(spec) => {
+            let mut reference = open_spec(spec);
+            let to_id = to_id.unwrap_or_else(|| {
+                std::cmp::max(
+                    store.get_last_item().unwrap(),
+                    reference.get_last_item().unwrap(),
+                )
+            });
+            verify::verify_stores(
+                store.as_mut(),
+                reference.as_mut(),
+                from_id..=to_id,
+                batch_items,
+                ignore,
+            )
+        }
+        None => {
+            let to_id = to_id.unwrap_or_else(|| store.get_last_item().unwrap());
+            let mut crawler = ItemsCrawler::new(NewsHub::new(&CRAWLER_HUB), store);
+            match sample {
+                Some(n) => verify::verify_hub(
+                    &mut crawler,
+                    verify::sample_ids(from_id..=to_id, n, utils::now() as u64),
+                    batch_items,
+                    ignore,
+                ),
+                None => verify::verify_hub(&mut crawler, from_id..=to_id, batch_items, ignore),
+            }
+        }
+    }
+    .unwrap();
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    if !report.is_consistent() {
+        eprintln!(
+            "{} missing, {} unexpected, {} differing and {} duplicated items out of {}",
+            report.missing.len(),
+            report.unexpected.len(),
+            report.differing.len(),
+            report.duplicates.len(),
+            report.checked
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Print the hits of `query`, one line per item followed by its snippet
+fn run_search<S: GenericSearch>(mut store: S, query: &SearchQuery) {
+    for hit in store.search(query).unwrap() {
+        println!(
+            "{:>8.3}  {}  {} by {} at {}  {}",
+            hit.rank, hit.item_id, hit.tp, hit.who, hit.time, hit.title
+        );
+        println!("          {}", hit.snippet.replace('\n', " "));
+    }
+}
+
+/// A flag raised on SIGINT/SIGTERM
+fn shutdown_on_signals() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, shutdown.clone()).unwrap();
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, shutdown.clone()).unwrap();
+    shutdown
+}
+
+/// Sleep for `duration` but wake up early on shutdown
+fn pause(shutdown: &AtomicBool, duration: Duration) {
+    let started = Instant::now();
+    while !shutdown.load(Ordering::Relaxed) && started.elapsed() < duration {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Run a maintenance `command` instead of crawling
+fn run_command(command: &Command, args: &Args) {
+    match command {
+        Command::Migrate {
+            action: Some(action),
+            ..
+        } => match args.store.as_str() {
+            "sqlite" => run_migrate(
+                Store::<sqlite::Connection>::open(args.store_uri.trim()).unwrap(),
+                action,
+            ),
+            "postgres" => run_migrate(
+                Store::<postgres::Client>::open(&args.store_uri).unwrap(),
+                action,
+            ),
+            _ => Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("`store` {:?} has no migrations", args.store),
+                )
+                .exit(),
+        },
+        Command::Migrate {
+            action: None,
+            from: Some(from),
+            to: Some(to),
+            batch_items,
+        } => {
+            run_copy(from, to, *batch_items);
+        }
+        Command::Migrate { .. } => Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "`migrate` needs `up`, `status` or `--from` and `--to`",
+            )
+            .exit(),
+        Command::Backup {
+            to,
+            vacuum,
+            check,
+            step_pages,
+        } => {
+            if args.store != "sqlite" {
+                Args::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!(
+                            "`backup` only supports the sqlite store, not {:?}",
+                            args.store
+                        ),
+                    )
+                    .exit();
+            }
+            let store = Store::<sqlite::Connection>::open(args.store_uri.trim()).unwrap();
+            let to = std::path::Path::new(to);
+            let bytes = if *vacuum {
+                store.vacuum_into(to, *check)
+            } else {
+                store.backup_to(to, *step_pages, Duration::from_millis(10), *check)
+            }
+            .unwrap();
+            println!("Copied {} bytes to {}", bytes, to.display());
+        }
+        Command::Replicate {
+            from,
+            to,
+            poll_interval,
+            once,
+            batch_items,
+        } => {
+            let (store, uri) = from.split_once(':').unwrap_or_else(|| {
+                Args::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!("{:?} is not a `<store>:<uri>` spec", from),
+                    )
+                    .exit()
+            });
+            match store {
+                "sqlite" => run_replicate(
+                    Store::<sqlite::Connection>::new(uri.trim()),
+                    to,
+                    *poll_interval,
+                    *once,
+                    *batch_items,
+                ),
+                "postgres" => run_replicate(
+                    Store::<postgres::Client>::new(uri),
+                    to,
+                    *poll_interval,
+                    *once,
+                    *batch_items,
+                ),
+                _ => Args::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!("`store` {:?} has no change feed", store),
+                    )
+                    .exit(),
+            }
+        }
+        Command::Verify {
+            against,
+            from_id,
+            to_id,
+            sample,
+            batch_items,
+            ignore,
+        } => {
+            run_verify(
+                args,
+                against,
+                *from_id,
+                *to_id,
+                *sample,
+                *batch_items,
+                ignore,
+            );
+        }
+        Command::Search {
+            query,
+            tp,
+            author,
+            since,
+            until,
+            limit,
+        } => {
+            let query = SearchQuery {
+                text: query.clone(),
+                tp: tp.clone(),
+                author: author.clone(),
+                since: *since,
+                until: *until,
+                limit: *limit,
+            };
+            match args.store.as_str() {
+                "sqlite" => run_search(
+                    Store::<sqlite::Connection>::new(args.store_uri.trim()),
+                    &query,
+                ),
+                "postgres" => run_search(Store::<postgres::Client>::new(&args.store_uri), &query),
+                _ => Args::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!("`store` {:?} has no full-text index", args.store),
+                    )
+                    .exit(),
+            }
+        }
+        Command::Outbox {
+            consumer,
+            batch_items,
+            follow,
+            poll_interval,
+        } => match args.store.as_str() {
+            "sqlite" => run_outbox(
+                Store::<sqlite::Connection>::new(args.store_uri.trim()),
+                consumer,
+                *batch_items,
+                *follow,
+                *poll_interval,
+            ),
+            "postgres" => run_outbox(
+                Store::<postgres::Client>::new(&args.store_uri),
+                consumer,
+                *batch_items,
+                *follow,
+                *poll_interval,
+            ),
+            _ => Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("`store` {:?} has no outbox", args.store),
+                )
+                .exit(),
+        },
+    }
 }
 
 fn main() {
     let args = Args::parse();
-    println!("{:?}", args);
+    eprintln!("{:?}", args);
+    let tombstones = TombstonePolicy::parse(&args.tombstones)
+        .unwrap_or_else(|| panic!("`tombstones` {:?} is not supported!", args.tombstones));
+    let exported = args.command.is_none()
+        && (matches!(args.store.as_str(), "stdout" | "-") || args.run_type == "item_as_of");
+    if tombstones != TombstonePolicy::Include && !exported {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "`--tombstones` only applies to `--run-type item_as_of` and the `stdout` store",
+            )
+            .exit();
+    }
+    if let Some(command) = &args.command {
+        run_command(command, &args);
+        return;
+    }
     let hub = NewsHub::new(&CRAWLER_HUB);
     match args.store.as_str() {
         "sqlite" => {
             let store = Store::<sqlite::Connection>::new(args.store_uri.trim());
-            let mut crawler = ItemsCrawler::new(hub, store);
-            match args.run_type.as_str() {
-                "run_one" => {
-                    crawler.run_one().unwrap();
-                }
-                "run_many" => {
-                   crawler.run_many();
-                }
-                "sync_data" => {
-                    crawler.run_sync_data();
-                }
-                &_ => {
-                    panic!("`run_type` {:?} is not supported for sqlite!", args.run_type);
-                }
-            }
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
         }
         "postgres" => {
             let store = Store::<postgres::Client>::new(&args.store_uri);
-            let mut crawler = ItemsCrawler::new(hub, store);
-            match args.run_type.as_str() {
-                "run_one" => {
-                    crawler.run_one().unwrap();
-                }
-                &_ => {
-                    panic!("`run_type` {:?} is not supported for `postgres`! ", args.run_type);
-                }
-            }
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
+        }
+        "mysql" => {
+            let store = Store::<mysql::Conn>::new(&args.store_uri);
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
+        }
+        "file" => {
+            let store = Store::<FileClient>::new(args.store_uri.trim());
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
+        }
+        "parquet" => {
+            let store = Store::<ParquetClient>::new(args.store_uri.trim());
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
+        }
+        "redb" => {
+            let store = Store::<redb::Database>::new(args.store_uri.trim());
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
+        }
+        "stdout" | "-" => {
+            let format = OutputFormat::parse(&args.format)
+                .unwrap_or_else(|| panic!("`format` {:?} is not supported!", args.format));
+            let store = Store::<StdoutClient>::new(&args.store_uri, format, tombstones);
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
+        }
+        "fanout" => {
+            let store = Store::<FanOutClient>::new(&args.sink);
+            run_crawler(ItemsCrawler::new(hub, store), &args, tombstones);
         }
         &_ => {
             panic!("`store` {:?} is not supported!", args.store)
         }
     };
-}
\ No newline at end of file
+}
@@ -0,0 +1,64 @@
+use crate::error::CrawlerError;
+
+/// One numbered schema change of a backend. Applied migrations are never edited,
+/// schema changes are new migrations appended with the next version
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// A migration recorded in the `schema_version` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    /// Unix timestamp (seconds) of the migration
+    pub applied_at: i64,
+}
+
+/// Versioned schema of a SQL backend, tracked in a `schema_version` table
+pub trait GenericMigrations {
+    /// The migrations of the backend, ordered by version
+    fn migrations(&self) -> &'static [Migration];
+    /// The applied migrations ordered by version, `schema_version` is created if needed
+    fn applied_migrations(&mut self) -> Result<Vec<AppliedMigration>, CrawlerError>;
+    /// Run `migration` and record it in `schema_version` in one transaction, unless a
+    /// concurrent crawler applied it first. Returns whether it was applied
+    fn apply_migration(&mut self, migration: &Migration) -> Result<bool, CrawlerError>;
+    /// Version of the latest applied migration, 0 for a new database
+    fn schema_version(&mut self) -> Result<i64, CrawlerError> {
+        Ok(self.applied_migrations()?.last().map_or(0, |m| m.version))
+    }
+    /// Apply the pending migrations in order, returns the versions applied
+    fn migrate_up(&mut self) -> Result<Vec<i64>, CrawlerError> {
+        let current = self.schema_version()?;
+        let latest = self.migrations().last().map_or(0, |m| m.version);
+        if current > latest {
+            return Err(CrawlerError::Store {
+                reason: format!(
+                    "schema version {current} is newer than the latest known migration {latest}"
+                ),
+            });
+        }
+        let mut applied = vec![];
+        for migration in self.migrations().iter().filter(|m| m.version > current) {
+            if self.apply_migration(migration)? {
+                applied.push(migration.version);
+            }
+        }
+        Ok(applied)
+    }
+    /// Every migration of the backend with its application, `None` when pending
+    fn migration_status(
+        &mut self,
+    ) -> Result<Vec<(&'static Migration, Option<AppliedMigration>)>, CrawlerError> {
+        let applied = self.applied_migrations()?;
+        Ok(self
+            .migrations()
+            .iter()
+            .map(|m| (m, applied.iter().find(|a| a.version == m.version).cloned()))
+            .collect())
+    }
+}
@@ -0,0 +1,210 @@
+use crate::error::CrawlerError;
+use crate::item::Item;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Entries read per query by `OutboxIter`
+const OUTBOX_PAGE_SIZE: usize = 500;
+
+/// How an item changed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeType {
+    /// First version of the item
+    Insert,
+    Update,
+    /// The item became deleted, the hub removed its content
+    Delete,
+}
+
+impl ChangeType {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "insert" => Some(Self::Insert),
+            "update" => Some(Self::Update),
+            "delete" => Some(Self::Delete),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Insert => "insert",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// One change of an item appended to the outbox by the store write which made it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    /// Position in the outbox, assigned by the store, increasing
+    pub seq: i64,
+    pub item_id: i64,
+    pub change: ChangeType,
+    /// Sorted names of the fields which changed as in the hub JSON (`by`, `type`, ...), all of them on insert
+    pub fields: Vec<String>,
+    pub changed_at: i64,
+}
+
+impl OutboxEntry {
+    /// The entry to append when an item changed from `previous` to `current` at `at`,
+    /// `None` when no field changed. `seq` is 0 until the store assigns it
+    pub fn track(previous: Option<&Item>, current: &Item, at: i64) -> Option<OutboxEntry> {
+        let change = match previous {
+            Some(p) if p == current => return None,
+            _ if current.deleted && !previous.is_some_and(|p| p.deleted) => ChangeType::Delete,
+            Some(_) => ChangeType::Update,
+            None => ChangeType::Insert,
+        };
+        let mut fields: Vec<String> = match previous {
+            Some(p) => p.diff(current),
+            None => current.fields().keys().cloned().collect(),
+        };
+        fields.sort();
+        Some(OutboxEntry {
+            seq: 0,
+            item_id: current.id,
+            change,
+            fields,
+            changed_at: at,
+        })
+    }
+}
+
+/// Name of the checkpoint holding the offset of the outbox `consumer`
+pub fn consumer_checkpoint(consumer: &str) -> String {
+    format!("outbox:{consumer}")
+}
+
+/// Stores appending every change of the items to an outbox, in the same transaction as the write
+pub trait GenericOutbox {
+    /// The entries appended after `after_seq`, ordered by sequence, at most `limit` of them
+    fn outbox_since(
+        &mut self,
+        after_seq: i64,
+        limit: usize,
+    ) -> Result<Vec<OutboxEntry>, CrawlerError>;
+    /// Stream the entries appended after `after_seq`, page by page
+    fn outbox(&mut self, after_seq: i64) -> OutboxIter<'_, Self>
+    where
+        Self: Sized,
+    {
+        OutboxIter {
+            store: self,
+            next_seq: Some(after_seq),
+            page: VecDeque::new(),
+        }
+    }
+}
+
+/// Iterator over the outbox entries of a store, see `GenericOutbox::outbox`
+pub struct OutboxIter<'a, S: GenericOutbox> {
+    store: &'a mut S,
+    next_seq: Option<i64>,
+    page: VecDeque<OutboxEntry>,
+}
+
+impl<S: GenericOutbox> Iterator for OutboxIter<'_, S> {
+    type Item = Result<OutboxEntry, CrawlerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() {
+            let after = self.next_seq?;
+            match self.store.outbox_since(after, OUTBOX_PAGE_SIZE) {
+                Ok(entries) => {
+                    // A partial page means the outbox is exhausted
+                    self.next_seq = match entries.last() {
+                        Some(last) if entries.len() == OUTBOX_PAGE_SIZE => Some(last.seq),
+                        _ => None,
+                    };
+                    self.page = entries.into();
+                }
+                Err(e) => {
+                    self.next_seq = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::GenericStoreItem;
+    use crate::item::Item;
+    use crate::outbox::{ChangeType, GenericOutbox, OutboxEntry};
+    use crate::store::Store;
+
+    fn comment(id: i64, text: &str) -> Item {
+        Item::from(format!(
+            r#"{{"id": {id}, "type": "comment", "by": "pg", "time": 1, "text": "{text}"}}"#
+        ))
+    }
+
+    #[test]
+    fn test_track_changes() {
+        let first = comment(1, "hi");
+        let entry = OutboxEntry::track(None, &first, 10).unwrap();
+        assert_eq!(entry.change, ChangeType::Insert);
+        assert!(
+            entry.fields.contains(&String::from("by"))
+                && entry.fields.contains(&String::from("text"))
+        );
+        assert_eq!(OutboxEntry::track(Some(&first), &first, 11), None);
+
+        let mut edited = first.clone();
+        edited.text = String::from("edited");
+        edited.score = 2;
+        let entry = OutboxEntry::track(Some(&first), &edited, 12).unwrap();
+        assert_eq!(
+            (entry.change, entry.fields),
+            (
+                ChangeType::Update,
+                vec![String::from("score"), String::from("text")]
+            )
+        );
+
+        let deleted = Item::from(String::from(
+            r#"{"id": 1, "type": "comment", "time": 1, "deleted": true}"#,
+        ));
+        let entry = OutboxEntry::track(Some(&edited), &deleted, 13).unwrap();
+        assert_eq!(entry.change, ChangeType::Delete);
+        assert_eq!(
+            entry.fields,
+            vec![
+                String::from("by"),
+                String::from("deleted"),
+                String::from("score"),
+                String::from("text")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_outbox_sqlite() {
+        let mut store = Store::<sqlite::Connection>::new(":memory:");
+        store
+            .store_items((1..=600).map(|id| comment(id, "hi")).collect())
+            .unwrap();
+        store.upsert_item(comment(7, "edited")).unwrap();
+        // Unchanged items are not changes
+        store.upsert_item(comment(8, "hi")).unwrap();
+
+        let entries: Vec<OutboxEntry> = store.outbox(0).map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 601);
+        assert!(entries.windows(2).all(|w| w[0].seq < w[1].seq));
+        let last = entries.last().unwrap();
+        assert_eq!(
+            (last.item_id, last.change, last.fields.clone()),
+            (7, ChangeType::Update, vec![String::from("text")])
+        );
+        let resumed: Vec<i64> = store
+            .outbox(entries[598].seq)
+            .map(|e| e.unwrap().item_id)
+            .collect();
+        assert_eq!(resumed, vec![600, 7]);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::error::CrawlerError;
+
+/// The story lists published by the hub
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoryList {
+    Top,
+    New,
+    Best,
+}
+
+impl StoryList {
+    pub const ALL: [StoryList; 3] = [StoryList::Top, StoryList::New, StoryList::Best];
+
+    /// Name of the list in the hub API and in the store
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StoryList::Top => "topstories",
+            StoryList::New => "newstories",
+            StoryList::Best => "beststories",
+        }
+    }
+}
+
+/// A story list as observed at `observed_at`, `ids` ordered by rank
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RankingSnapshot {
+    pub list: StoryList,
+    pub observed_at: i64,
+    pub ids: Vec<i64>,
+}
+
+/// The position of a story in one observation of a list, 0 being the top
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RankPosition {
+    pub observed_at: i64,
+    pub position: i64,
+}
+
+/// Seconds a story stayed in a list: each observation where it was ranked
+/// counts until the next observation of the list
+pub fn ranked_duration(observations: &[i64], history: &[RankPosition]) -> i64 {
+    observations
+        .windows(2)
+        .filter(|w| history.iter().any(|p| p.observed_at == w[0]))
+        .map(|w| w[1] - w[0])
+        .sum()
+}
+
+/// Storing observations of the story lists into generic Store
+pub trait GenericRankingStore {
+    fn save_ranking(&mut self, snapshot: &RankingSnapshot) -> Result<(), CrawlerError>;
+    /// The latest observation of `list` at or before `at`
+    fn ranking_at(
+        &mut self,
+        list: StoryList,
+        at: i64,
+    ) -> Result<Option<RankingSnapshot>, CrawlerError>;
+    /// Every position of `item_id` in `list`, oldest first
+    fn ranking_history(
+        &mut self,
+        list: StoryList,
+        item_id: i64,
+    ) -> Result<Vec<RankPosition>, CrawlerError>;
+    /// Times at which `list` was observed, oldest first
+    fn ranking_observations(&mut self, list: StoryList) -> Result<Vec<i64>, CrawlerError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ranking::{ranked_duration, RankPosition};
+
+    #[test]
+    fn test_ranked_duration() {
+        let observations = [0, 60, 120, 180, 240];
+        let history = [
+            RankPosition {
+                observed_at: 60,
+                position: 3,
+            },
+            RankPosition {
+                observed_at: 120,
+                position: 1,
+            },
+            RankPosition {
+                observed_at: 240,
+                position: 8,
+            },
+        ];
+        // The last observation has no end yet
+        assert_eq!(ranked_duration(&observations, &history), 120);
+        assert_eq!(ranked_duration(&observations, &[]), 0);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::error::CrawlerError;
+use crate::item::Item;
+use serde::{Deserialize, Serialize};
+
+/// One observation of the fast changing values of an item
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ItemSnapshot {
+    pub item_id: i64,
+    /// Unix timestamp (seconds) of the observation
+    pub observed_at: i64,
+    pub score: i64,
+    pub descendants: i64,
+}
+
+impl ItemSnapshot {
+    pub fn observe(item: &Item, observed_at: i64) -> Self {
+        Self {
+            item_id: item.id,
+            observed_at,
+            score: item.score,
+            descendants: item.descendants,
+        }
+    }
+}
+
+/// A story which is still young enough to be refreshed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshCandidate {
+    pub item_id: i64,
+    /// Posting time of the story
+    pub time: i64,
+    /// Time of the latest snapshot, if any
+    pub last_observed_at: Option<i64>,
+}
+
+impl RefreshCandidate {
+    /// A story is due when the latest passed offset of `schedule` (seconds after posting)
+    /// has not been observed yet. Missed offsets collapse into a single refresh.
+    pub fn is_due(&self, now: i64, schedule: &[i64]) -> bool {
+        let latest_mark = schedule
+            .iter()
+            .map(|offset| self.time + offset)
+            .filter(|mark| *mark <= now)
+            .max();
+        match (latest_mark, self.last_observed_at) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(mark), Some(observed)) => observed < mark,
+        }
+    }
+}
+
+/// Storing snapshots of items into generic Store
+pub trait GenericSnapshotStore {
+    fn save_snapshot(&mut self, snapshot: &ItemSnapshot) -> Result<(), CrawlerError>;
+    /// Stories posted after `since` with the time of their latest snapshot
+    fn refresh_candidates(&mut self, since: i64) -> Result<Vec<RefreshCandidate>, CrawlerError>;
+    /// All snapshots of an item, oldest first
+    fn get_snapshots(&mut self, item_id: i64) -> Result<Vec<ItemSnapshot>, CrawlerError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::refresh::RefreshCandidate;
+
+    const SCHEDULE: [i64; 3] = [300, 900, 3600];
+
+    fn candidate(last_observed_at: Option<i64>) -> RefreshCandidate {
+        RefreshCandidate {
+            item_id: 1,
+            time: 1000,
+            last_observed_at,
+        }
+    }
+
+    #[test]
+    fn test_not_due_before_first_offset() {
+        assert!(!candidate(None).is_due(1200, &SCHEDULE));
+    }
+
+    #[test]
+    fn test_due_after_each_offset() {
+        assert!(candidate(None).is_due(1300, &SCHEDULE));
+        assert!(!candidate(Some(1300)).is_due(1800, &SCHEDULE));
+        assert!(candidate(Some(1300)).is_due(1900, &SCHEDULE));
+    }
+
+    #[test]
+    fn test_missed_offsets_collapse() {
+        assert!(candidate(Some(1300)).is_due(10000, &SCHEDULE));
+        assert!(!candidate(Some(9000)).is_due(10000, &SCHEDULE));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::crawler::GenericStore;
+use crate::error::CrawlerError;
+use crate::item::Item;
+use std::collections::BTreeMap;
+
+/// Name of the checkpoint of the replica holding the sequence of the last change applied
+pub const REPLICATION_CHECKPOINT: &str = "replication";
+
+/// One recorded version of an item, in the order the source recorded them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Position of the change in the feed, increasing
+    pub seq: i64,
+    pub observed_at: i64,
+    pub item: Item,
+}
+
+/// Stores whose item history is an ordered change log: every new or updated item
+/// is appended to it, so a replica can follow the store from a sequence number
+pub trait GenericChangeFeed {
+    /// The changes recorded after `after_seq`, ordered by sequence, at most `limit` of them
+    fn changes_since(&mut self, after_seq: i64, limit: usize) -> Result<Vec<Change>, CrawlerError>;
+}
+
+/// Apply the changes of `source` that `replica` has not seen yet, `batch` at a time.
+/// The sequence of the last applied batch is saved in the `REPLICATION_CHECKPOINT` of the
+/// replica after the items, so an interrupted replication applies that batch again.
+/// The items the replica already holds are upserted, the new ones stored in bulk.
+/// Returns the number of changes applied
+pub fn replicate<S: GenericChangeFeed>(
+    source: &mut S,
+    replica: &mut dyn GenericStore,
+    batch: usize,
+) -> Result<usize, CrawlerError> {
+    let mut applied = 0;
+    loop {
+        let after = replica.get_checkpoint(REPLICATION_CHECKPOINT)?.unwrap_or(0);
+        let changes = source.changes_since(after, batch.max(1))?;
+        let Some(last) = changes.last().map(|c| c.seq) else {
+            return Ok(applied);
+        };
+        applied += changes.len();
+        // Only the latest version of each item in the batch matters to the replica
+        let latest: BTreeMap<i64, Item> =
+            changes.into_iter().map(|c| (c.item.id, c.item)).collect();
+        let ids: Vec<i64> = latest.keys().copied().collect();
+        let existing = replica.contains(&ids)?;
+        let (updated, new): (Vec<Item>, Vec<Item>) =
+            latest.into_values().partition(|i| existing.contains(&i.id));
+        replica.store_items(new)?;
+        for item in updated {
+            replica.upsert_item(item)?;
+        }
+        replica.save_checkpoint(REPLICATION_CHECKPOINT, last)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::{GenericStore, GenericStoreItem};
+    use crate::ext::file::FileClient;
+    use crate::item::Item;
+    use crate::replicate::{replicate, GenericChangeFeed, REPLICATION_CHECKPOINT};
+    use crate::store::Store;
+    use crate::testing::TempDir;
+
+    fn story(id: i64, score: i64) -> Item {
+        let mut item = Item::from(format!(
+            r#"{{"id": {id}, "type": "story", "by": "pg", "time": 1}}"#
+        ));
+        item.score = score;
+        item
+    }
+
+    #[test]
+    fn test_replicate_new_and_updated_items() {
+        let dir = TempDir::new("replicate-file");
+        let replicas: Vec<Box<dyn GenericStore>> = vec![
+            Box::new(Store::<FileClient>::new(&dir.uri())),
+            Box::new(Store::<sqlite::Connection>::new(":memory:")),
+        ];
+        for mut replica in replicas {
+            let mut source = Store::<sqlite::Connection>::new(":memory:");
+            source
+                .store_items(vec![story(1, 1), story(2, 1), story(3, 1)])
+                .unwrap();
+            assert_eq!(replicate(&mut source, replica.as_mut(), 2).unwrap(), 3);
+            assert_eq!(replicate(&mut source, replica.as_mut(), 2).unwrap(), 0);
+
+            // Updates of items already replicated follow, several updates of an item in one batch too
+            source.upsert_item(story(2, 5)).unwrap();
+            source.upsert_item(story(2, 9)).unwrap();
+            source.store_item(story(4, 1)).unwrap();
+            let changes = source.changes_since(3, 10).unwrap();
+            assert_eq!(
+                changes
+                    .iter()
+                    .map(|c| (c.item.id, c.item.score))
+                    .collect::<Vec<_>>(),
+                vec![(2, 5), (2, 9), (4, 1)]
+            );
+            assert_eq!(replicate(&mut source, replica.as_mut(), 10).unwrap(), 3);
+            assert_eq!(replica.get_item(2).unwrap(), Some(story(2, 9)));
+            assert_eq!(replica.get_items_range(1..=4, 10).unwrap().len(), 4);
+            assert_eq!(
+                replica.get_checkpoint(REPLICATION_CHECKPOINT).unwrap(),
+                Some(changes.last().unwrap().seq)
+            );
+        }
+    }
+}
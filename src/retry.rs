@@ -0,0 +1,109 @@
+use crate::error::CrawlerError;
+use crate::utils::{MAX_RETRY_ATTEMPTS, RETRY_BASE_DELAY, RETRY_MAX_DELAY};
+use serde::{Deserialize, Serialize};
+
+/// State of an item in the retry queue
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetryState {
+    /// Will be retried once `next_attempt_at` is reached
+    Pending,
+    /// Gave up after `MAX_RETRY_ATTEMPTS`, kept for inspection only
+    Dead,
+}
+
+impl RetryState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetryState::Pending => "pending",
+            RetryState::Dead => "dead",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(RetryState::Pending),
+            "dead" => Some(RetryState::Dead),
+            _ => None,
+        }
+    }
+}
+
+/// An item which failed to be fetched or stored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RetryEntry {
+    pub item_id: i64,
+    pub attempts: i64,
+    pub last_error: String,
+    /// Unix timestamp (seconds) of the earliest next attempt
+    pub next_attempt_at: i64,
+    pub state: RetryState,
+}
+
+impl RetryEntry {
+    /// Build the queue entry after one more failed attempt of `item_id`,
+    /// starting from the `previous` entry if the item already failed before
+    pub fn failed(previous: Option<RetryEntry>, item_id: i64, error: &str, now: i64) -> Self {
+        let attempts = previous.map_or(0, |p| p.attempts) + 1;
+        let state = if attempts >= *MAX_RETRY_ATTEMPTS as i64 {
+            RetryState::Dead
+        } else {
+            RetryState::Pending
+        };
+        Self {
+            item_id,
+            attempts,
+            last_error: error.to_string(),
+            next_attempt_at: now + backoff_delay(attempts),
+            state,
+        }
+    }
+}
+
+/// Exponential backoff in seconds: `RETRY_BASE_DELAY * 2^(attempts - 1)`,
+/// capped at `RETRY_MAX_DELAY`
+pub fn backoff_delay(attempts: i64) -> i64 {
+    let exp = (attempts - 1).clamp(0, 30) as u32;
+    std::cmp::min(*RETRY_BASE_DELAY * 2i64.pow(exp), *RETRY_MAX_DELAY)
+}
+
+/// Persisting failed items into generic Store so they can be retried later
+pub trait GenericRetryQueue {
+    fn get_retry(&mut self, item_id: i64) -> Result<Option<RetryEntry>, CrawlerError>;
+    fn save_retry(&mut self, entry: &RetryEntry) -> Result<(), CrawlerError>;
+    fn remove_retry(&mut self, item_id: i64) -> Result<(), CrawlerError>;
+    /// Pending entries whose `next_attempt_at` is before `now`, oldest first
+    fn due_retries(&mut self, now: i64, limit: usize) -> Result<Vec<RetryEntry>, CrawlerError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retry::{backoff_delay, RetryEntry, RetryState};
+    use crate::utils::{MAX_RETRY_ATTEMPTS, RETRY_BASE_DELAY, RETRY_MAX_DELAY};
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        assert_eq!(backoff_delay(1), *RETRY_BASE_DELAY);
+        assert_eq!(backoff_delay(2), *RETRY_BASE_DELAY * 2);
+        assert_eq!(backoff_delay(100), *RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_entry_becomes_dead() {
+        let mut entry = RetryEntry::failed(None, 1, "boom", 0);
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(entry.state, RetryState::Pending);
+        while entry.attempts < *MAX_RETRY_ATTEMPTS as i64 {
+            entry = RetryEntry::failed(Some(entry), 1, "boom", 0);
+        }
+        assert_eq!(entry.state, RetryState::Dead);
+    }
+
+    #[test]
+    fn test_parse_state() {
+        for state in [RetryState::Pending, RetryState::Dead] {
+            assert_eq!(RetryState::parse(state.as_str()), Some(state));
+        }
+        assert_eq!(RetryState::parse("pendng"), None);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::error::CrawlerError;
+use serde::Serialize;
+
+/// Markers around the matched terms in the snippets
+pub const HIGHLIGHT_START: &str = "[";
+pub const HIGHLIGHT_END: &str = "]";
+
+/// A full-text search over the titles and texts of the items
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Words to look for, all of them must match
+    pub text: String,
+    /// Only the items of this type, like `story` or `comment`
+    pub tp: Option<String>,
+    /// Only the items of this author
+    pub author: Option<String>,
+    /// Only the items posted at or after this unix timestamp
+    pub since: Option<i64>,
+    /// Only the items posted at or before this unix timestamp
+    pub until: Option<i64>,
+    pub limit: usize,
+}
+
+impl SearchQuery {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.into(),
+            tp: None,
+            author: None,
+            since: None,
+            until: None,
+            limit: 20,
+        }
+    }
+
+    /// The words of the query, an empty query is an error
+    pub fn words(&self) -> Result<Vec<&str>, CrawlerError> {
+        let words: Vec<&str> = self.text.split_whitespace().collect();
+        if words.is_empty() {
+            return Err(CrawlerError::Store {
+                reason: "the search query has no words".into(),
+            });
+        }
+        Ok(words)
+    }
+}
+
+/// One item matching a search, the best ranked first
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub item_id: i64,
+    #[serde(rename = "type")]
+    pub tp: String,
+    #[serde(rename = "by")]
+    pub who: String,
+    pub time: i64,
+    pub title: String,
+    /// Relevance of the item, higher is better. Only comparable within one backend
+    pub rank: f64,
+    /// Excerpt of the title or text with the matched words between `HIGHLIGHT_START` and `HIGHLIGHT_END`
+    pub snippet: String,
+}
+
+/// Stores indexing the titles and texts of the items, kept in sync on write
+pub trait GenericSearch {
+    /// The items matching `query`, ordered by decreasing rank, at most `query.limit` of them
+    fn search(&mut self, query: &SearchQuery) -> Result<Vec<SearchHit>, CrawlerError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::GenericStoreItem;
+    use crate::item::Item;
+    use crate::search::{GenericSearch, SearchQuery};
+    use crate::store::Store;
+
+    fn item(id: i64, tp: &str, by: &str, time: i64, title: &str, text: &str) -> Item {
+        Item::from(serde_json::json!({"id": id, "type": tp, "by": by, "time": time, "title": title, "text": text}).to_string())
+    }
+
+    #[test]
+    fn test_search_sqlite() {
+        let mut store = Store::<sqlite::Connection>::new(":memory:");
+        store
+            .store_items(vec![
+                item(1, "story", "pg", 100, "Rust compiler internals", ""),
+                item(
+                    2,
+                    "comment",
+                    "dang",
+                    200,
+                    "",
+                    "The borrow checker of the rust compiler is strict",
+                ),
+                item(3, "comment", "pg", 300, "", "Lisp macros are \"great\""),
+                item(
+                    4,
+                    "story",
+                    "tptacek",
+                    400,
+                    "Postgres full text search",
+                    "Nothing about compilers",
+                ),
+            ])
+            .unwrap();
+
+        let hits = store.search(&SearchQuery::new("rust compiler")).unwrap();
+        assert_eq!(
+            hits.iter().map(|h| h.item_id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(hits[0].rank >= hits[1].rank);
+        assert!(
+            hits[1].snippet.contains("[rust] [compiler]"),
+            "{}",
+            hits[1].snippet
+        );
+
+        let mut query = SearchQuery::new("rust");
+        query.tp = Some(String::from("comment"));
+        assert_eq!(
+            store
+                .search(&query)
+                .unwrap()
+                .iter()
+                .map(|h| h.item_id)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+        query = SearchQuery::new("compiler");
+        query.author = Some(String::from("pg"));
+        query.until = Some(150);
+        assert_eq!(
+            store
+                .search(&query)
+                .unwrap()
+                .iter()
+                .map(|h| h.item_id)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+        query.since = Some(150);
+        assert!(store.search(&query).unwrap().is_empty());
+
+        // The index follows the updates, the query syntax of the user is not interpreted
+        store
+            .upsert_item(item(1, "story", "pg", 100, "Go scheduler internals", ""))
+            .unwrap();
+        assert_eq!(
+            store.search(&SearchQuery::new("internals")).unwrap()[0].title,
+            "Go scheduler internals"
+        );
+        assert!(store
+            .search(&SearchQuery::new("rust"))
+            .unwrap()
+            .iter()
+            .all(|h| h.item_id != 1));
+        assert_eq!(
+            store
+                .search(&SearchQuery::new("\"great\" OR -macros*"))
+                .unwrap()
+                .len(),
+            0
+        );
+        assert_eq!(
+            store.search(&SearchQuery::new("\"great\"")).unwrap()[0].item_id,
+            3
+        );
+        assert!(store.search(&SearchQuery::new("  ")).is_err());
+
+        // An item stored again is one hit, and the index keyed by id outlives a VACUUM
+        store
+            .store_items(vec![item(
+                2,
+                "comment",
+                "dang",
+                200,
+                "",
+                "The borrow checker of the rust compiler is strict",
+            )])
+            .unwrap();
+        store
+            .backend_client
+            .execute(
+                "VACUUM; INSERT INTO `items_fts` (`items_fts`, rank) VALUES ('integrity-check', 1)",
+            )
+            .unwrap();
+        assert_eq!(
+            store
+                .search(&SearchQuery::new("borrow"))
+                .unwrap()
+                .iter()
+                .map(|h| h.item_id)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+}
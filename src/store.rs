@@ -1,3 +1,60 @@
+use crate::crawler::GenericStoreItem;
+use crate::error::CrawlerError;
+use crate::item::Item;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
 pub struct Store<T> {
-    pub backend_client: T
+    pub backend_client: T,
+}
+
+/// Number of items an `ItemScan` reads from the store at once
+const SCAN_PAGE_SIZE: usize = 1000;
+
+/// Streaming iterator over the stored items of a range, ordered by id.
+/// Items are read page by page with `get_items_range` so the whole range is never in memory
+pub struct ItemScan<'a, S: GenericStoreItem> {
+    store: &'a mut S,
+    next_id: Option<i64>,
+    end: i64,
+    page: VecDeque<Item>,
+}
+
+impl<'a, S: GenericStoreItem> ItemScan<'a, S> {
+    pub fn new(store: &'a mut S, range: RangeInclusive<i64>) -> Self {
+        let (start, end) = range.into_inner();
+        Self {
+            store,
+            next_id: if start <= end { Some(start) } else { None },
+            end,
+            page: VecDeque::new(),
+        }
+    }
+}
+
+impl<S: GenericStoreItem> Iterator for ItemScan<'_, S> {
+    type Item = Result<Item, CrawlerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() {
+            let start = self.next_id?;
+            match self.store.get_items_range(start..=self.end, SCAN_PAGE_SIZE) {
+                Ok(items) => {
+                    // A partial page means the range is exhausted
+                    self.next_id = match items.last() {
+                        Some(last) if items.len() == SCAN_PAGE_SIZE && last.id < self.end => {
+                            Some(last.id + 1)
+                        }
+                        _ => None,
+                    };
+                    self.page = items.into();
+                }
+                Err(e) => {
+                    self.next_id = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
 }
@@ -0,0 +1,87 @@
+//! Helpers shared by the tests of the crate
+use crate::item::Item;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// A local hub answering the paths of `routes` (`/item/1.json`, `/maxitem.json`, ...,
+/// without the query) with their body, and `null` for the other paths. Returns its base uri
+pub fn mock_hub(routes: HashMap<String, String>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            let target = request.split_whitespace().nth(1).unwrap_or_default();
+            let path = target.split('?').next().unwrap_or_default();
+            let body = routes
+                .get(path)
+                .cloned()
+                .unwrap_or_else(|| String::from("null"));
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+    url
+}
+
+/// The `/item/<id>.json` routes of `items` for `mock_hub`
+pub fn item_routes<'a>(items: impl IntoIterator<Item = &'a Item>) -> HashMap<String, String> {
+    items
+        .into_iter()
+        .map(|i| {
+            (
+                format!("/item/{}.json", i.id),
+                serde_json::to_string(i).unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// An empty directory of the test `name` under the temp dir, removed with its content
+/// when dropped, so the tests which fail don't leave it behind
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("crawler-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// The path of the directory as a store uri
+    pub fn uri(&self) -> String {
+        self.0.to_string_lossy().to_string()
+    }
+
+    /// The path of `name` in the directory as a store uri
+    pub fn join(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
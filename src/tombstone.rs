@@ -0,0 +1,195 @@
+use crate::error::CrawlerError;
+use crate::item::Item;
+use serde::{Deserialize, Serialize};
+
+/// The deleted/dead transitions of an item with its last known content,
+/// since the hub removes the content of deleted items
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Tombstone {
+    pub item_id: i64,
+    /// First time the item was seen deleted
+    pub deleted_at: Option<i64>,
+    /// First time the item was seen dead, cleared when it is revived
+    pub dead_at: Option<i64>,
+    pub who: String,
+    pub title: String,
+    pub text: String,
+    pub url: String,
+}
+
+impl Tombstone {
+    pub fn new(item_id: i64) -> Self {
+        Self {
+            item_id,
+            deleted_at: None,
+            dead_at: None,
+            who: String::new(),
+            title: String::new(),
+            text: String::new(),
+            url: String::new(),
+        }
+    }
+
+    /// Update the `existing` tombstone of an item which changed from `previous` to `current`
+    /// at `at`. Returns the tombstone to save, `None` when nothing changed
+    pub fn track(
+        existing: Option<Tombstone>,
+        previous: Option<&Item>,
+        current: &Item,
+        at: i64,
+    ) -> Option<Tombstone> {
+        if !current.deleted && !current.dead {
+            return match existing {
+                Some(mut t) if t.dead_at.is_some() => {
+                    t.dead_at = None;
+                    Some(t)
+                }
+                _ => None,
+            };
+        }
+        let mut tombstone = existing
+            .clone()
+            .unwrap_or_else(|| Tombstone::new(current.id));
+        if current.deleted && tombstone.deleted_at.is_none() {
+            tombstone.deleted_at = Some(at);
+        }
+        if current.dead && tombstone.dead_at.is_none() {
+            tombstone.dead_at = Some(at);
+        }
+        // Never erase content we already captured
+        for source in previous.into_iter().chain(Some(current)) {
+            tombstone.keep_content(source);
+        }
+        match existing {
+            Some(t) if t == tombstone => None,
+            _ => Some(tombstone),
+        }
+    }
+
+    fn keep_content(&mut self, item: &Item) {
+        let keep = |kept: &mut String, value: &String| {
+            if !value.is_empty() {
+                kept.clone_from(value);
+            }
+        };
+        keep(&mut self.who, &item.who);
+        keep(&mut self.title, &item.title);
+        keep(&mut self.text, &item.text);
+        keep(&mut self.url, &item.url);
+    }
+
+    /// Fill the emptied fields of `item` with the last known content
+    pub fn restore(&self, item: &mut Item) {
+        let restore = |value: &mut String, kept: &String| {
+            if value.is_empty() {
+                value.clone_from(kept);
+            }
+        };
+        restore(&mut item.who, &self.who);
+        restore(&mut item.title, &self.title);
+        restore(&mut item.text, &self.text);
+        restore(&mut item.url, &self.url);
+    }
+}
+
+/// How exports handle the deleted and dead items
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TombstonePolicy {
+    /// Export them as crawled
+    Include,
+    /// Export them with their last known content
+    Restore,
+    /// Leave them out
+    Exclude,
+}
+
+impl TombstonePolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "include" => Some(TombstonePolicy::Include),
+            "restore" => Some(TombstonePolicy::Restore),
+            "exclude" => Some(TombstonePolicy::Exclude),
+            _ => None,
+        }
+    }
+}
+
+/// Storing the tombstones into generic Store
+pub trait GenericTombstoneStore {
+    fn get_tombstone(&mut self, item_id: i64) -> Result<Option<Tombstone>, CrawlerError>;
+    fn save_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), CrawlerError>;
+    /// Prepare `item` for an export following `policy`, `None` when it is left out
+    fn export_item(
+        &mut self,
+        mut item: Item,
+        policy: TombstonePolicy,
+    ) -> Result<Option<Item>, CrawlerError> {
+        if !item.deleted && !item.dead {
+            return Ok(Some(item));
+        }
+        match policy {
+            TombstonePolicy::Include => Ok(Some(item)),
+            TombstonePolicy::Exclude => Ok(None),
+            TombstonePolicy::Restore => {
+                if let Some(tombstone) = self.get_tombstone(item.id)? {
+                    tombstone.restore(&mut item);
+                }
+                Ok(Some(item))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::Item;
+    use crate::tombstone::Tombstone;
+
+    fn comment(text: &str, deleted: bool, dead: bool) -> Item {
+        Item::from(
+            serde_json::json!({
+                "id": 1, "type": "comment", "time": 1, "by": if deleted { "" } else { "pg" },
+                "text": text, "deleted": deleted, "dead": dead
+            })
+            .to_string(),
+        )
+    }
+
+    #[test]
+    fn test_deleted_keeps_content() {
+        let alive = comment("hello", false, false);
+        let deleted = comment("", true, false);
+        assert_eq!(Tombstone::track(None, None, &alive, 10), None);
+        let tombstone = Tombstone::track(None, Some(&alive), &deleted, 20).unwrap();
+        assert_eq!(tombstone.deleted_at, Some(20));
+        assert_eq!(tombstone.text, "hello");
+        assert_eq!(tombstone.who, "pg");
+
+        // Seen deleted again: nothing to update, content is not erased
+        assert_eq!(
+            Tombstone::track(Some(tombstone.clone()), Some(&deleted), &deleted, 30),
+            None
+        );
+
+        let mut restored = deleted.clone();
+        tombstone.restore(&mut restored);
+        assert_eq!(restored.text, "hello");
+        assert!(restored.deleted);
+    }
+
+    #[test]
+    fn test_dead_and_revived() {
+        let dead = comment("spam?", false, true);
+        let tombstone = Tombstone::track(None, None, &dead, 10).unwrap();
+        assert_eq!(tombstone.dead_at, Some(10));
+        assert_eq!(tombstone.text, "spam?");
+        let revived = Tombstone::track(
+            Some(tombstone),
+            Some(&dead),
+            &comment("spam?", false, false),
+            20,
+        )
+        .unwrap();
+        assert_eq!(revived.dead_at, None);
+    }
+}
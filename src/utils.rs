@@ -1,20 +1,122 @@
-use std::env::var;
 use lazy_static::lazy_static;
+use std::env::var;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn env_or(name: &str, default: String) -> String {
     match var(name) {
-        Ok(v) => {v}
-        Err(_) => {default}
+        Ok(v) => v,
+        Err(_) => default,
     }
 }
 
+/// Current unix timestamp in seconds
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
 lazy_static! {
     pub static ref CRAWLER_HUB: String = env_or(
         "CRAWLER_HUB",
-        format!("https://hacker-news.firebaseio.com/v0/")
+        String::from("https://hacker-news.firebaseio.com/v0/")
     );
     pub static ref MAX_BATCH_ITEMS: u16 = env_or(
         "MAX_BATCH_ITEMS",
-        format!("10")
+        String::from("10")
+    ).parse::<u16>().unwrap();
+    pub static ref MAX_RETRY_ATTEMPTS: u16 = env_or(
+        "MAX_RETRY_ATTEMPTS",
+        String::from("5")
     ).parse::<u16>().unwrap();
-}
\ No newline at end of file
+    /// Seconds to wait before the first retry, doubled on each attempt
+    pub static ref RETRY_BASE_DELAY: i64 = env_or(
+        "RETRY_BASE_DELAY",
+        String::from("60")
+    ).parse::<i64>().unwrap();
+    pub static ref RETRY_MAX_DELAY: i64 = env_or(
+        "RETRY_MAX_DELAY",
+        String::from("21600")
+    ).parse::<i64>().unwrap();
+    /// Seconds after posting at which young stories are refreshed
+    pub static ref REFRESH_SCHEDULE: Vec<i64> = env_or(
+        "REFRESH_SCHEDULE",
+        String::from("300,900,3600,21600,86400")
+    ).split(',').map(|s| s.trim().parse::<i64>().unwrap()).collect();
+    /// Compression of the closed segments of the file store: `none`, `gzip` or `zstd`
+    pub static ref FILE_COMPRESSION: String = env_or(
+        "FILE_COMPRESSION",
+        String::from("none")
+    );
+    /// The active segment of the file store is rotated once it reaches this size in bytes
+    pub static ref FILE_SEGMENT_BYTES: u64 = env_or(
+        "FILE_SEGMENT_BYTES",
+        String::from("67108864")
+    ).parse::<u64>().unwrap();
+    /// ... or once the new item ids it holds span this many ids, the updates of older items aside
+    pub static ref FILE_SEGMENT_IDS: i64 = env_or(
+        "FILE_SEGMENT_IDS",
+        String::from("1000000")
+    ).parse::<i64>().unwrap();
+    /// Rows buffered by the parquet store before they are written as a row group
+    pub static ref PARQUET_ROW_GROUP_ROWS: usize = env_or(
+        "PARQUET_ROW_GROUP_ROWS",
+        String::from("100000")
+    ).parse::<usize>().unwrap();
+    /// Secret signing the webhook payloads with HMAC-SHA256, no signature when empty
+    pub static ref WEBHOOK_SECRET: String = env_or(
+        "WEBHOOK_SECRET",
+        String::new()
+    );
+    /// Directory of the webhook spool and checkpoints
+    pub static ref WEBHOOK_SPOOL_DIR: String = env_or(
+        "WEBHOOK_SPOOL_DIR",
+        String::from("data/webhook-spool")
+    );
+    /// Items POSTed at most in one webhook payload
+    pub static ref WEBHOOK_BATCH_ITEMS: usize = env_or(
+        "WEBHOOK_BATCH_ITEMS",
+        String::from("100")
+    ).parse::<usize>().unwrap();
+    /// Attempts to deliver a payload before it is spooled
+    pub static ref WEBHOOK_ATTEMPTS: u32 = env_or(
+        "WEBHOOK_ATTEMPTS",
+        String::from("3")
+    ).parse::<u32>().unwrap();
+    /// Milliseconds to wait before the second attempt, doubled on each attempt
+    pub static ref WEBHOOK_RETRY_DELAY_MS: u64 = env_or(
+        "WEBHOOK_RETRY_DELAY_MS",
+        String::from("500")
+    ).parse::<u64>().unwrap();
+    /// Upper bound of the webhook retry delay, in milliseconds
+    pub static ref WEBHOOK_MAX_RETRY_DELAY_MS: u64 = env_or(
+        "WEBHOOK_MAX_RETRY_DELAY_MS",
+        String::from("60000")
+    ).parse::<u64>().unwrap();
+    /// Journal mode of the sqlite store, `wal` lets readers query while the crawler writes
+    pub static ref SQLITE_JOURNAL_MODE: String = env_or(
+        "SQLITE_JOURNAL_MODE",
+        String::from("wal")
+    );
+    /// `off`, `normal`, `full` or `extra`, `normal` is safe with `wal` and skips most fsyncs
+    pub static ref SQLITE_SYNCHRONOUS: String = env_or(
+        "SQLITE_SYNCHRONOUS",
+        String::from("normal")
+    );
+    /// Page cache of the sqlite store, in pages or in KiB when negative
+    pub static ref SQLITE_CACHE_SIZE: i64 = env_or(
+        "SQLITE_CACHE_SIZE",
+        String::from("-65536")
+    ).parse::<i64>().unwrap();
+    /// Bytes of the sqlite database mapped in memory, 0 disables it
+    pub static ref SQLITE_MMAP_SIZE: i64 = env_or(
+        "SQLITE_MMAP_SIZE",
+        String::from("268435456")
+    ).parse::<i64>().unwrap();
+    /// Milliseconds to wait for a lock held by another connection before failing
+    pub static ref SQLITE_BUSY_TIMEOUT: usize = env_or(
+        "SQLITE_BUSY_TIMEOUT",
+        String::from("5000")
+    ).parse::<usize>().unwrap();
+}
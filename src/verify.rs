@@ -0,0 +1,255 @@
+use crate::crawler::{batch_runtime, GenericStore, ItemsCrawler};
+use crate::error::CrawlerError;
+use crate::item::Item;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+/// An item held by the store and the reference with different fields
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ItemDiff {
+    pub item_id: i64,
+    pub fields: Vec<String>,
+}
+
+/// Differences of a store with a reference, the hub or another store
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of ids compared
+    pub checked: u64,
+    /// Ids of the reference which the store does not hold
+    pub missing: Vec<i64>,
+    /// Ids of the store which the reference does not have
+    pub unexpected: Vec<i64>,
+    pub differing: Vec<ItemDiff>,
+    /// Ids stored more than once by the store
+    pub duplicates: Vec<i64>,
+    /// Ids the hub failed to answer, not compared
+    pub unreachable: Vec<i64>,
+}
+
+impl VerifyReport {
+    /// Whether no difference was found, the unreachable ids aside
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty()
+            && self.unexpected.is_empty()
+            && self.differing.is_empty()
+            && self.duplicates.is_empty()
+    }
+
+    /// Compare the items of `ids` held by the store and the reference, except the `ignore` fields
+    fn compare(&mut self, ids: &[i64], stored: Vec<Item>, reference: Vec<Item>, ignore: &[String]) {
+        let stored: BTreeMap<i64, Item> = stored.into_iter().map(|i| (i.id, i)).collect();
+        let reference: BTreeMap<i64, Item> = reference.into_iter().map(|i| (i.id, i)).collect();
+        for id in ids {
+            match (stored.get(id), reference.get(id)) {
+                (Some(ours), Some(theirs)) => {
+                    let fields: Vec<String> = ours
+                        .diff(theirs)
+                        .into_iter()
+                        .filter(|f| !ignore.contains(f))
+                        .collect();
+                    if !fields.is_empty() {
+                        self.differing.push(ItemDiff {
+                            item_id: *id,
+                            fields,
+                        });
+                    }
+                }
+                (None, Some(_)) => self.missing.push(*id),
+                (Some(_), None) => self.unexpected.push(*id),
+                (None, None) => {}
+            }
+        }
+        self.checked += ids.len() as u64;
+    }
+}
+
+/// `n` ids of `range` spread over it, one picked in each of `n` equal slices, all of them
+/// when the range is smaller. The picks only depend on `seed`
+pub fn sample_ids(range: RangeInclusive<i64>, n: usize, seed: u64) -> Vec<i64> {
+    let (start, end) = range.into_inner();
+    let len = (end - start + 1).max(0) as u64;
+    if n as u64 >= len {
+        return (start..=end).collect();
+    }
+    let mut state = seed | 1;
+    (0..n as u64)
+        .map(|k| {
+            // xorshift64, good enough to spread the picks
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let (low, high) = (k * len / n as u64, (k + 1) * len / n as u64);
+            start + (low + state % (high - low)) as i64
+        })
+        .collect()
+}
+
+/// The stored items of `ids`, with a range read when the ids are contiguous
+fn load_items(store: &mut dyn GenericStore, ids: &[i64]) -> Result<Vec<Item>, CrawlerError> {
+    match (ids.first(), ids.last()) {
+        (Some(first), Some(last)) if last - first + 1 == ids.len() as i64 => {
+            store.get_items_range(*first..=*last, ids.len())
+        }
+        _ => ids
+            .iter()
+            .filter_map(|id| store.get_item(*id).transpose())
+            .collect(),
+    }
+}
+
+/// Compare the items of `ids` in the store of `crawler` with the hub, taking `batch` ids at a time
+/// from `ids` so a large range is never held at once
+pub fn verify_hub(
+    crawler: &mut ItemsCrawler<Box<dyn GenericStore>>,
+    ids: impl IntoIterator<Item = i64>,
+    batch: usize,
+    ignore: &[String],
+) -> Result<VerifyReport, CrawlerError> {
+    let mut report = VerifyReport::default();
+    let mut ids = ids.into_iter();
+    let mut bounds: Option<(i64, i64)> = None;
+    loop {
+        let chunk: Vec<i64> = ids.by_ref().take(batch.max(1)).collect();
+        let (Some(first), Some(last)) = (chunk.iter().min(), chunk.iter().max()) else {
+            break;
+        };
+        bounds = Some(bounds.map_or((*first, *last), |(low, high)| {
+            (low.min(*first), high.max(*last))
+        }));
+        let (fetched, failed) = crawler.fetch_items_async(chunk.clone(), batch_runtime());
+        // The hub answers `null` for the ids it has no item for
+        let unreachable: Vec<i64> = failed
+            .into_iter()
+            .filter(|(_, e)| !matches!(e, CrawlerError::EmptyItem { .. }))
+            .map(|(id, _)| id)
+            .collect();
+        let checked: Vec<i64> = chunk
+            .into_iter()
+            .filter(|id| !unreachable.contains(id))
+            .collect();
+        let stored = load_items(crawler.client.as_mut(), &checked)?;
+        report.compare(&checked, stored, fetched, ignore);
+        report.unreachable.extend(unreachable);
+    }
+    if let Some((first, last)) = bounds {
+        report.duplicates = crawler.client.duplicates(first..=last)?;
+    }
+    report.unreachable.sort();
+    Ok(report)
+}
+
+/// Compare the items with ids in `range` of `store` with the ones of `reference`, `batch` ids at a time
+pub fn verify_stores(
+    store: &mut dyn GenericStore,
+    reference: &mut dyn GenericStore,
+    range: RangeInclusive<i64>,
+    batch: usize,
+    ignore: &[String],
+) -> Result<VerifyReport, CrawlerError> {
+    let mut report = VerifyReport::default();
+    let batch = batch.max(1);
+    let (mut start, last_id) = range.clone().into_inner();
+    while start <= last_id {
+        let end = std::cmp::min(start.saturating_add(batch as i64 - 1), last_id);
+        let ids: Vec<i64> = (start..=end).collect();
+        let stored = store.get_items_range(start..=end, batch)?;
+        let expected = reference.get_items_range(start..=end, batch)?;
+        report.compare(&ids, stored, expected, ignore);
+        start = end + 1;
+    }
+    report.duplicates = store.duplicates(range)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::{GenericStore, GenericStoreItem, ItemsCrawler};
+    use crate::ext::file::FileClient;
+    use crate::hub::NewsHub;
+    use crate::item::Item;
+    use crate::store::Store;
+    use crate::testing::TempDir;
+    use crate::testing::{item_routes, mock_hub};
+    use crate::verify::{sample_ids, verify_hub, verify_stores, ItemDiff};
+
+    fn story(id: i64, score: i64) -> Item {
+        let mut item = Item::from(format!(
+            r#"{{"id": {id}, "type": "story", "by": "pg", "time": 1, "descendants": 3}}"#
+        ));
+        item.score = score;
+        item
+    }
+
+    #[test]
+    fn test_sample_ids() {
+        assert_eq!(sample_ids(1..=3, 5, 1), vec![1, 2, 3]);
+        let sample = sample_ids(1..=1000, 10, 42);
+        assert_eq!(sample.len(), 10);
+        assert!(sample
+            .iter()
+            .enumerate()
+            .all(|(k, id)| (k as i64 * 100 + 1..=(k as i64 + 1) * 100).contains(id)));
+        assert_eq!(sample, sample_ids(1..=1000, 10, 42));
+    }
+
+    #[test]
+    fn test_verify_hub() {
+        let hub_items = [story(1, 1), story(2, 5), story(4, 1), story(5, 1)];
+        let mut routes = item_routes(&hub_items);
+        routes.insert(
+            String::from("/item/6.json"),
+            String::from("<html>Service Unavailable</html>"),
+        );
+        let mut store = Store::<sqlite::Connection>::new(":memory:");
+        store
+            .store_items(vec![story(1, 1), story(2, 1), story(3, 1), story(5, 1)])
+            .unwrap();
+        let mut crawler = ItemsCrawler::new(
+            NewsHub::new(&mock_hub(routes)),
+            Box::new(store) as Box<dyn GenericStore>,
+        );
+
+        let ignore = [String::from("descendants")];
+        let report = verify_hub(&mut crawler, 1..=6, 4, &ignore).unwrap();
+        assert_eq!(report.checked, 5);
+        assert_eq!(report.missing, vec![4]);
+        assert_eq!(report.unexpected, vec![3]);
+        assert_eq!(
+            report.differing,
+            vec![ItemDiff {
+                item_id: 2,
+                fields: vec![String::from("score")]
+            }]
+        );
+        assert_eq!(report.unreachable, vec![6]);
+        assert!(!report.is_consistent());
+    }
+
+    #[test]
+    fn test_verify_stores() {
+        let dir = TempDir::new("verify-file");
+        let mut store = Store::<sqlite::Connection>::new(":memory:");
+        let mut reference = Store::<FileClient>::new(&dir.uri());
+        let items: Vec<Item> = (1..=10).map(|id| story(id, 1)).collect();
+        store.store_items(items.clone()).unwrap();
+        reference.store_items(items).unwrap();
+        let ignore = [String::from("descendants")];
+        let report = verify_stores(&mut store, &mut reference, 1..=10, 3, &ignore).unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.checked, 10);
+
+        reference.upsert_item(story(7, 2)).unwrap();
+        reference.store_item(story(11, 1)).unwrap();
+        let report = verify_stores(&mut store, &mut reference, 1..=11, 3, &ignore).unwrap();
+        assert_eq!(report.missing, vec![11]);
+        assert_eq!(
+            report.differing,
+            vec![ItemDiff {
+                item_id: 7,
+                fields: vec![String::from("score")]
+            }]
+        );
+    }
+}